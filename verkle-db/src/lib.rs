@@ -11,11 +11,24 @@ mod rocksdb_impl;
 #[cfg(feature = "rocks_db")]
 pub use rocksdb_impl::DB as RocksDb;
 
+// An iterator over key-value pairs, in key order
+pub type KVIter<'a> = Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a>;
+
 // Bare metal database assumes the most basic functionality for a key value database
 pub trait BareMetalKVDb {
     // Get the value stored at this key
     fn fetch(&self, key: &[u8]) -> Option<Vec<u8>>;
 
+    // Iterate over the key-value pairs whose keys are in `start..end`, in key order
+    // If `end` is None, then the range has no upper bound
+    fn iter_range(&self, start: &[u8], end: Option<&[u8]>) -> KVIter<'_>;
+
+    // Iterate over the key-value pairs whose keys start with `prefix`, in key order
+    fn iter_prefix(&self, prefix: &[u8]) -> KVIter<'_> {
+        let end = prefix_end(prefix);
+        self.iter_range(prefix, end.as_deref())
+    }
+
     // Create a database given the default path
     // This cannot be implemented here since Self is not sized.
     fn new() -> Self;
@@ -31,6 +44,9 @@ pub trait BatchWriter {
     fn new() -> Self;
 
     fn batch_put(&mut self, key: &[u8], val: &[u8]);
+
+    // Remove the value stored at this key, when the batch is flushed
+    fn batch_delete(&mut self, key: &[u8]);
 }
 
 pub trait BatchDB {
@@ -38,3 +54,29 @@ pub trait BatchDB {
 
     fn flush(&mut self, batch: Self::BatchWrite);
 }
+
+// Returns the smallest key which is larger than every key starting with `prefix`
+// If there is no such key, ie the prefix is all 0xff, then None is returned
+pub fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::prefix_end;
+
+    #[test]
+    fn prefix_end_increments_last_byte() {
+        assert_eq!(prefix_end(&[1, 2, 3]), Some(vec![1, 2, 4]));
+        assert_eq!(prefix_end(&[1, 2, 255]), Some(vec![1, 3]));
+        assert_eq!(prefix_end(&[255, 255]), None);
+        assert_eq!(prefix_end(&[]), None);
+    }
+}
@@ -1,5 +1,6 @@
-use crate::{BareMetalDiskDb, BareMetalKVDb};
+use crate::{BareMetalDiskDb, BareMetalKVDb, KVIter};
 pub use rocksdb::DB;
+use rocksdb::{Direction, IteratorMode};
 
 impl BareMetalDiskDb for DB {
     fn from_path<P: AsRef<std::path::Path>>(path: P) -> Self {
@@ -17,6 +18,18 @@ impl BareMetalKVDb for DB {
     fn fetch(&self, key: &[u8]) -> Option<Vec<u8>> {
         self.get(key).unwrap()
     }
+
+    fn iter_range(&self, start: &[u8], end: Option<&[u8]>) -> KVIter<'_> {
+        let end = end.map(|end| end.to_vec());
+        let iter = self
+            .iterator(IteratorMode::From(start, Direction::Forward))
+            .take_while(move |(key, _)| match &end {
+                Some(end) => key[..] < end[..],
+                None => true,
+            })
+            .map(|(key, value)| (key.to_vec(), value.to_vec()));
+        Box::new(iter)
+    }
     // Create a database given the default path
     fn new() -> Self {
         Self::from_path(Self::DEFAULT_PATH)
@@ -34,6 +47,10 @@ impl BatchWriter for WriteBatch {
     fn batch_put(&mut self, key: &[u8], val: &[u8]) {
         self.put(key, val)
     }
+
+    fn batch_delete(&mut self, key: &[u8]) {
+        self.delete(key)
+    }
 }
 
 impl BatchDB for DB {
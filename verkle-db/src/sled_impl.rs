@@ -1,4 +1,4 @@
-use crate::{BareMetalDiskDb, BareMetalKVDb};
+use crate::{BareMetalDiskDb, BareMetalKVDb, BatchDB, BatchWriter, KVIter};
 pub use sled::Db as DB;
 
 impl BareMetalDiskDb for sled::Db {
@@ -14,8 +14,41 @@ impl BareMetalKVDb for sled::Db {
     fn fetch(&self, key: &[u8]) -> Option<Vec<u8>> {
         self.get(key).unwrap().map(|i_vec| i_vec.to_vec())
     }
+
+    fn iter_range(&self, start: &[u8], end: Option<&[u8]>) -> KVIter<'_> {
+        let iter = match end {
+            Some(end) => self.range(start.to_vec()..end.to_vec()),
+            None => self.range(start.to_vec()..),
+        };
+        Box::new(iter.map(|item| {
+            let (key, value) = item.unwrap();
+            (key.to_vec(), value.to_vec())
+        }))
+    }
     // Create a database given the default path
     fn new() -> Self {
         Self::from_path(Self::DEFAULT_PATH)
     }
 }
+
+impl BatchWriter for sled::Batch {
+    fn new() -> Self {
+        sled::Batch::default()
+    }
+
+    fn batch_put(&mut self, key: &[u8], val: &[u8]) {
+        self.insert(key, val)
+    }
+
+    fn batch_delete(&mut self, key: &[u8]) {
+        self.remove(key)
+    }
+}
+
+impl BatchDB for sled::Db {
+    type BatchWrite = sled::Batch;
+
+    fn flush(&mut self, batch: Self::BatchWrite) {
+        self.apply_batch(batch).unwrap();
+    }
+}
@@ -0,0 +1,344 @@
+use ethereum_types::{H256, U256};
+
+use crate::{
+    code::Bytes32,
+    parameters::{
+        BASIC_DATA_BALANCE_OFFSET, BASIC_DATA_CODE_SIZE_OFFSET, BASIC_DATA_NONCE_OFFSET,
+        BASIC_DATA_VERSION_OFFSET,
+    },
+    Header, Layout,
+};
+
+// Encoding of the values in the account header.
+// In the original layout, integers are little endian and padded with zeroes, and the code hash is stored as is.
+// In the basic data layout, the integers are big endian and packed into the basic data leaf
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueError {
+    // The value has non zero bytes which do not fit into the type that it is decoded as
+    Overflow,
+    // Some of the fields of the account header are in the trie, however this one is not
+    MissingField,
+    // The reserved bytes of the basic data leaf are not zero
+    NonZeroReserved,
+}
+
+pub fn encode_u256(value: U256) -> Bytes32 {
+    let mut bytes = [0u8; 32];
+    value.to_little_endian(&mut bytes);
+    bytes
+}
+
+pub fn decode_u256(bytes: Bytes32) -> U256 {
+    U256::from_little_endian(&bytes)
+}
+
+pub fn encode_u64(value: u64) -> Bytes32 {
+    let mut bytes = [0u8; 32];
+    bytes[0..8].copy_from_slice(&value.to_le_bytes());
+    bytes
+}
+
+pub fn decode_u64(bytes: Bytes32) -> Result<u64, ValueError> {
+    if bytes[8..].iter().any(|byte| *byte != 0) {
+        return Err(ValueError::Overflow);
+    }
+    Ok(u64::from_le_bytes(bytes[0..8].try_into().unwrap()))
+}
+
+pub fn encode_h256(value: H256) -> Bytes32 {
+    value.to_fixed_bytes()
+}
+
+pub fn decode_h256(bytes: Bytes32) -> H256 {
+    H256::from(bytes)
+}
+
+// The fields of the basic data leaf
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BasicData {
+    pub version: u8,
+    // The code size is stored in 3 bytes
+    pub code_size: u32,
+    pub nonce: u64,
+    pub balance: u128,
+}
+
+impl BasicData {
+    const MAX_CODE_SIZE: u32 = (1 << 24) - 1;
+
+    pub fn encode(&self) -> Result<Bytes32, ValueError> {
+        if self.code_size > BasicData::MAX_CODE_SIZE {
+            return Err(ValueError::Overflow);
+        }
+
+        let mut bytes = [0u8; 32];
+        bytes[BASIC_DATA_VERSION_OFFSET] = self.version;
+        bytes[BASIC_DATA_CODE_SIZE_OFFSET..BASIC_DATA_NONCE_OFFSET]
+            .copy_from_slice(&self.code_size.to_be_bytes()[1..]);
+        bytes[BASIC_DATA_NONCE_OFFSET..BASIC_DATA_BALANCE_OFFSET]
+            .copy_from_slice(&self.nonce.to_be_bytes());
+        bytes[BASIC_DATA_BALANCE_OFFSET..].copy_from_slice(&self.balance.to_be_bytes());
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: Bytes32) -> Result<BasicData, ValueError> {
+        if bytes[BASIC_DATA_VERSION_OFFSET + 1..BASIC_DATA_CODE_SIZE_OFFSET]
+            .iter()
+            .any(|byte| *byte != 0)
+        {
+            return Err(ValueError::NonZeroReserved);
+        }
+
+        let mut code_size = [0u8; 4];
+        code_size[1..]
+            .copy_from_slice(&bytes[BASIC_DATA_CODE_SIZE_OFFSET..BASIC_DATA_NONCE_OFFSET]);
+        Ok(BasicData {
+            version: bytes[BASIC_DATA_VERSION_OFFSET],
+            code_size: u32::from_be_bytes(code_size),
+            nonce: u64::from_be_bytes(
+                bytes[BASIC_DATA_NONCE_OFFSET..BASIC_DATA_BALANCE_OFFSET]
+                    .try_into()
+                    .unwrap(),
+            ),
+            balance: u128::from_be_bytes(bytes[BASIC_DATA_BALANCE_OFFSET..].try_into().unwrap()),
+        })
+    }
+}
+
+// The fields of an account which are stored at the keys of its `Header`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AccountHeader {
+    pub version: U256,
+    pub balance: U256,
+    pub nonce: u64,
+    // Keccak hash of the code
+    pub code_hash: H256,
+    pub code_size: u64,
+}
+
+impl AccountHeader {
+    // The writes which store the account at the keys of the header, which can be passed to `TrieTrait::insert`.
+    // In the basic data layout, the fields must fit into the basic data leaf
+    pub fn to_writes(&self, header: &Header) -> Result<Vec<(Bytes32, Bytes32)>, ValueError> {
+        let code_hash = (
+            header.code_keccak().to_fixed_bytes(),
+            encode_h256(self.code_hash),
+        );
+
+        match header.layout() {
+            Layout::Original => Ok(vec![
+                (header.version().to_fixed_bytes(), encode_u256(self.version)),
+                (header.balance().to_fixed_bytes(), encode_u256(self.balance)),
+                (header.nonce().to_fixed_bytes(), encode_u64(self.nonce)),
+                code_hash,
+                (
+                    header.code_size().to_fixed_bytes(),
+                    encode_u64(self.code_size),
+                ),
+            ]),
+            Layout::BasicData => {
+                let basic_data = self.basic_data()?;
+                Ok(vec![
+                    (header.balance().to_fixed_bytes(), basic_data.encode()?),
+                    code_hash,
+                ])
+            }
+        }
+    }
+
+    // Reads the account at the keys of the header, with `get` returning the value of a key in the trie.
+    // Returns `None` if none of the fields are in the trie, since the account does not exist
+    pub fn from_reads(
+        header: &Header,
+        mut get: impl FnMut(Bytes32) -> Option<Bytes32>,
+    ) -> Result<Option<AccountHeader>, ValueError> {
+        match header.layout() {
+            Layout::Original => AccountHeader::from_original_reads(header, get),
+            Layout::BasicData => {
+                let basic_data = get(header.balance().to_fixed_bytes());
+                let code_hash = get(header.code_keccak().to_fixed_bytes());
+
+                let (basic_data, code_hash) = match (basic_data, code_hash) {
+                    (None, None) => return Ok(None),
+                    (Some(basic_data), Some(code_hash)) => (basic_data, code_hash),
+                    _ => return Err(ValueError::MissingField),
+                };
+
+                let basic_data = BasicData::decode(basic_data)?;
+                Ok(Some(AccountHeader {
+                    version: U256::from(basic_data.version),
+                    balance: U256::from(basic_data.balance),
+                    nonce: basic_data.nonce,
+                    code_hash: decode_h256(code_hash),
+                    code_size: basic_data.code_size as u64,
+                }))
+            }
+        }
+    }
+
+    fn from_original_reads(
+        header: &Header,
+        mut get: impl FnMut(Bytes32) -> Option<Bytes32>,
+    ) -> Result<Option<AccountHeader>, ValueError> {
+        let version = get(header.version().to_fixed_bytes());
+        let balance = get(header.balance().to_fixed_bytes());
+        let nonce = get(header.nonce().to_fixed_bytes());
+        let code_hash = get(header.code_keccak().to_fixed_bytes());
+        let code_size = get(header.code_size().to_fixed_bytes());
+
+        let (version, balance, nonce, code_hash, code_size) =
+            match (version, balance, nonce, code_hash, code_size) {
+                (None, None, None, None, None) => return Ok(None),
+                (Some(version), Some(balance), Some(nonce), Some(code_hash), Some(code_size)) => {
+                    (version, balance, nonce, code_hash, code_size)
+                }
+                _ => return Err(ValueError::MissingField),
+            };
+
+        Ok(Some(AccountHeader {
+            version: decode_u256(version),
+            balance: decode_u256(balance),
+            nonce: decode_u64(nonce)?,
+            code_hash: decode_h256(code_hash),
+            code_size: decode_u64(code_size)?,
+        }))
+    }
+
+    // The fields which are packed into the basic data leaf
+    pub fn basic_data(&self) -> Result<BasicData, ValueError> {
+        if self.version > U256::from(u8::MAX)
+            || self.balance > U256::from(u128::MAX)
+            || self.code_size > BasicData::MAX_CODE_SIZE as u64
+        {
+            return Err(ValueError::Overflow);
+        }
+
+        Ok(BasicData {
+            version: self.version.low_u32() as u8,
+            code_size: self.code_size as u32,
+            nonce: self.nonce,
+            balance: self.balance.low_u128(),
+        })
+    }
+}
+
+#[cfg(test)]
+struct TestHasher;
+
+#[cfg(test)]
+impl crate::Hasher for TestHasher {
+    fn hash64(bytes64: [u8; 64]) -> H256 {
+        H256::from_slice(&bytes64[0..32])
+    }
+}
+
+#[test]
+fn values_roundtrip() {
+    let balance = U256::from(10u8).pow(U256::from(30u8));
+    let encoded = encode_u256(balance);
+    // The least significant byte is first
+    assert_eq!(encoded[0], (balance.low_u64() & 0xff) as u8);
+    assert_eq!(decode_u256(encoded), balance);
+
+    let nonce = u64::MAX - 1;
+    assert_eq!(decode_u64(encode_u64(nonce)), Ok(nonce));
+    assert_eq!(encode_u64(1)[0], 1);
+
+    let mut too_large = encode_u64(nonce);
+    too_large[8] = 1;
+    assert_eq!(decode_u64(too_large), Err(ValueError::Overflow));
+
+    let code_hash = H256::repeat_byte(0xc5);
+    assert_eq!(decode_h256(encode_h256(code_hash)), code_hash);
+}
+
+#[test]
+fn account_header_roundtrip() {
+    use std::collections::BTreeMap;
+
+    let header = Header::new::<TestHasher>(H256::repeat_byte(1));
+    let account = AccountHeader {
+        version: U256::zero(),
+        balance: U256::from(1_000_000u64),
+        nonce: 7,
+        code_hash: H256::repeat_byte(2),
+        code_size: 1024,
+    };
+
+    let trie: BTreeMap<_, _> = account.to_writes(&header).unwrap().into_iter().collect();
+    assert_eq!(trie.len(), 5);
+    let got = AccountHeader::from_reads(&header, |key| trie.get(&key).copied());
+    assert_eq!(got, Ok(Some(account)));
+
+    // An account which does not exist, and an account with a missing field
+    let got = AccountHeader::from_reads(&header, |_| None);
+    assert_eq!(got, Ok(None));
+
+    let mut partial = trie.clone();
+    partial.remove(&header.nonce().to_fixed_bytes());
+    let got = AccountHeader::from_reads(&header, |key| partial.get(&key).copied());
+    assert_eq!(got, Err(ValueError::MissingField));
+}
+
+#[test]
+fn basic_data_layout() {
+    use std::collections::BTreeMap;
+
+    let account = AccountHeader {
+        version: U256::zero(),
+        balance: U256::from(0x0102_0304_0506_0708_090a_0b0c_0d0e_0f10u128),
+        nonce: 0x1112_1314_1516_1718,
+        code_hash: H256::repeat_byte(2),
+        code_size: 0x19_1a1b,
+    };
+    let basic_data = account.basic_data().unwrap().encode().unwrap();
+    let mut expected = [0u8; 32];
+    expected[5..8].copy_from_slice(&[0x19, 0x1a, 0x1b]);
+    for (i, byte) in expected[8..].iter_mut().enumerate() {
+        // The nonce is followed by the balance, both big endian
+        *byte = if i < 8 {
+            0x11 + i as u8
+        } else {
+            0x01 + (i - 8) as u8
+        };
+    }
+    assert_eq!(basic_data, expected);
+
+    // The version, balance, nonce and code size share a leaf, and the code hash is in the next leaf
+    let header = Header::with_layout::<TestHasher>(H256::repeat_byte(1), Layout::BasicData);
+    assert_eq!(header.version(), header.balance());
+    assert_eq!(header.nonce(), header.code_size());
+    assert_eq!(header.balance()[31], 0);
+    assert_eq!(header.code_keccak()[31], 1);
+
+    let writes = account.to_writes(&header).unwrap();
+    assert_eq!(writes.len(), 2);
+    let trie: BTreeMap<_, _> = writes.into_iter().collect();
+    let got = AccountHeader::from_reads(&header, |key| trie.get(&key).copied());
+    assert_eq!(got, Ok(Some(account)));
+
+    // The keys of the original layout are on the same stem
+    let original = Header::new::<TestHasher>(H256::repeat_byte(1));
+    assert_eq!(original.balance()[0..31], header.balance()[0..31]);
+
+    // Fields which do not fit into the basic data leaf
+    let large_balance = AccountHeader {
+        balance: U256::from(u128::MAX) + 1,
+        ..account
+    };
+    assert_eq!(large_balance.to_writes(&header), Err(ValueError::Overflow));
+    assert!(large_balance.to_writes(&original).is_ok());
+    let large_code = AccountHeader {
+        code_size: 1 << 24,
+        ..account
+    };
+    assert_eq!(large_code.to_writes(&header), Err(ValueError::Overflow));
+
+    let mut reserved = expected;
+    reserved[2] = 1;
+    assert_eq!(
+        BasicData::decode(reserved),
+        Err(ValueError::NonZeroReserved)
+    );
+}
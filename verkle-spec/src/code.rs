@@ -1,9 +1,11 @@
 use ethereum_types::{H256, U256};
+use tiny_keccak::{Hasher as _, Keccak};
 
 use crate::{
+    account::{decode_h256, decode_u64, encode_h256, encode_u64, BasicData, ValueError},
     parameters::{CODE_OFFSET, VERKLE_NODE_WIDTH},
     util::{hash_addr_int, swap_last_byte, zero_align_bytes},
-    Address32, Hasher,
+    Address32, Hasher, Header, Layout,
 };
 
 pub struct Code {
@@ -12,8 +14,9 @@ pub struct Code {
 
 impl Code {
     pub fn new<H: Hasher>(address: Address32, chunk_id: U256) -> Code {
-        let index = (CODE_OFFSET + chunk_id) / VERKLE_NODE_WIDTH;
-        let sub_index = CODE_OFFSET + chunk_id % VERKLE_NODE_WIDTH;
+        let pos = CODE_OFFSET + chunk_id;
+        let index = pos / VERKLE_NODE_WIDTH;
+        let sub_index = pos % VERKLE_NODE_WIDTH;
 
         let base_hash = hash_addr_int::<H>(address, index);
         let code_chunk_tree_key = swap_last_byte(base_hash, sub_index);
@@ -133,6 +136,172 @@ fn compute_leftover_push_data(code_chunk: &[u8]) -> u8 {
     leftover
 }
 
+// The writes of the code chunks which are stored at a stem
+pub type StemChunks = ([u8; 31], Vec<(Bytes32, Bytes32)>);
+
+// The writes which store the code of a contract in the trie
+pub struct CodeWrites {
+    // The code chunks, grouped by the stem that they are stored at.
+    // The stems and the chunks of each stem are in the order of the chunks
+    pub chunks_by_stem: Vec<StemChunks>,
+    // The code size and code hash, which are stored in the account header.
+    // In the basic data layout, the code size is written with the rest of the basic data by `AccountHeader`
+    pub code_size: Option<(Bytes32, Bytes32)>,
+    pub code_hash: (Bytes32, Bytes32),
+}
+
+impl CodeWrites {
+    pub fn new<H: Hasher>(address: Address32, code: Vec<u8>) -> CodeWrites {
+        CodeWrites::with_layout::<H>(address, code, Layout::Original)
+    }
+
+    pub fn with_layout<H: Hasher>(address: Address32, code: Vec<u8>, layout: Layout) -> CodeWrites {
+        let header = Header::with_layout::<H>(address, layout);
+        let code_size = match layout {
+            Layout::Original => Some((
+                header.code_size().to_fixed_bytes(),
+                encode_u64(code.len() as u64),
+            )),
+            Layout::BasicData => None,
+        };
+        let code_hash = (
+            header.code_keccak().to_fixed_bytes(),
+            encode_h256(keccak256(&code)),
+        );
+
+        let mut chunks_by_stem: Vec<StemChunks> = Vec::new();
+        for (key, chunk) in code_chunk_keys::<H>(address).zip(chunkify_code(code)) {
+            let stem: [u8; 31] = key[0..31].try_into().unwrap();
+            match chunks_by_stem.last_mut() {
+                Some((last_stem, chunks)) if *last_stem == stem => chunks.push((key, chunk)),
+                _ => chunks_by_stem.push((stem, vec![(key, chunk)])),
+            }
+        }
+
+        CodeWrites {
+            chunks_by_stem,
+            code_size,
+            code_hash,
+        }
+    }
+
+    // All of the writes, which can be inserted into the trie as one batch
+    pub fn into_writes(self) -> Vec<(Bytes32, Bytes32)> {
+        let mut writes: Vec<_> = self.code_size.into_iter().collect();
+        writes.push(self.code_hash);
+        for (_, chunks) in self.chunks_by_stem {
+            writes.extend(chunks);
+        }
+        writes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeError {
+    // The code size or code hash could not be decoded, or only one of them is in the trie
+    Header(ValueError),
+    // The chunk at this index is not in the trie
+    MissingChunk(u64),
+    // The chunk at this index does not match the chunk of the code which was read
+    InvalidChunk(u64),
+    // The code which was read does not hash to the code hash
+    CodeHashMismatch,
+}
+
+// Reads the code of a contract, with `get` returning the value of a key in the trie.
+// Returns `None` if the code size and code hash are not in the trie.
+//
+// The code is checked against the code hash, and the chunks are checked against the
+// chunks of the code, so that the push data markers are also validated
+pub fn read_code<H: Hasher>(
+    address: Address32,
+    get: impl FnMut(Bytes32) -> Option<Bytes32>,
+) -> Result<Option<Vec<u8>>, CodeError> {
+    read_code_with_layout::<H>(address, Layout::Original, get)
+}
+
+pub fn read_code_with_layout<H: Hasher>(
+    address: Address32,
+    layout: Layout,
+    mut get: impl FnMut(Bytes32) -> Option<Bytes32>,
+) -> Result<Option<Vec<u8>>, CodeError> {
+    let header = Header::with_layout::<H>(address, layout);
+    let code_size = get(header.code_size().to_fixed_bytes());
+    let code_hash = get(header.code_keccak().to_fixed_bytes());
+    let (code_size, code_hash) = match (code_size, code_hash) {
+        (None, None) => return Ok(None),
+        (Some(code_size), Some(code_hash)) => {
+            let code_size = match layout {
+                Layout::Original => decode_u64(code_size),
+                Layout::BasicData => {
+                    BasicData::decode(code_size).map(|basic_data| basic_data.code_size as u64)
+                }
+            };
+            (
+                code_size.map_err(CodeError::Header)?,
+                decode_h256(code_hash),
+            )
+        }
+        _ => return Err(CodeError::Header(ValueError::MissingField)),
+    };
+
+    // Each chunk holds 31 bytes of code
+    let num_chunks = code_size.div_ceil(31);
+    let mut chunks = Vec::new();
+    let mut code = Vec::new();
+    for (chunk_id, key) in (0..num_chunks).zip(code_chunk_keys::<H>(address)) {
+        let chunk = get(key).ok_or(CodeError::MissingChunk(chunk_id))?;
+        code.extend_from_slice(&chunk[1..]);
+        chunks.push(chunk);
+    }
+
+    // The last chunk is padded with zeroes
+    let padding = code.split_off(code_size as usize);
+    if padding.iter().any(|byte| *byte != 0) {
+        return Err(CodeError::InvalidChunk(num_chunks - 1));
+    }
+    if keccak256(&code) != code_hash {
+        return Err(CodeError::CodeHashMismatch);
+    }
+    let expected_chunks = chunkify_code(code.clone());
+    if let Some(chunk_id) =
+        (0..num_chunks).find(|i| chunks[*i as usize] != expected_chunks[*i as usize])
+    {
+        return Err(CodeError::InvalidChunk(chunk_id));
+    }
+
+    Ok(Some(code))
+}
+
+// The keys of the code chunks in order, starting at the first chunk.
+// Consecutive chunks share a stem, so the stem is only hashed once for each of them
+fn code_chunk_keys<H: Hasher>(address: Address32) -> impl Iterator<Item = Bytes32> {
+    let mut base_hash: Option<(U256, H256)> = None;
+    (0u64..).map(move |chunk_id| {
+        let pos = CODE_OFFSET + U256::from(chunk_id);
+        let index = pos / VERKLE_NODE_WIDTH;
+        let sub_index = pos % VERKLE_NODE_WIDTH;
+
+        let hash = match base_hash {
+            Some((base_index, hash)) if base_index == index => hash,
+            _ => {
+                let hash = hash_addr_int::<H>(address, index);
+                base_hash = Some((index, hash));
+                hash
+            }
+        };
+        swap_last_byte(hash, sub_index).to_fixed_bytes()
+    })
+}
+
+fn keccak256(bytes: &[u8]) -> H256 {
+    let mut keccak = Keccak::v256();
+    keccak.update(bytes);
+    let mut hash = [0u8; 32];
+    keccak.finalize(&mut hash);
+    H256::from(hash)
+}
+
 #[test]
 fn check_against_eip() {
     // This was taken directly from the EIP as a sniff test
@@ -214,3 +383,124 @@ fn simple_chunkify_test() {
     // in the second chunk
     assert_eq!(chunk3[0], 0);
 }
+
+#[cfg(test)]
+struct TestHasher;
+
+#[cfg(test)]
+impl Hasher for TestHasher {
+    fn hash64(bytes64: [u8; 64]) -> H256 {
+        // The tree index is in the second half, so that each stem is different
+        let mut hash = [0u8; 32];
+        hash[0..16].copy_from_slice(&bytes64[0..16]);
+        hash[16..].copy_from_slice(&bytes64[32..48]);
+        H256::from(hash)
+    }
+}
+
+#[test]
+fn chunk_key_matches_code() {
+    let address = Address32::repeat_byte(3);
+    for (chunk_id, key) in code_chunk_keys::<TestHasher>(address).enumerate().take(600) {
+        let code = Code::new::<TestHasher>(address, U256::from(chunk_id));
+        assert_eq!(code.code_chunk().to_fixed_bytes(), key);
+    }
+}
+
+#[test]
+fn code_writes_roundtrip() {
+    use std::collections::BTreeMap;
+
+    let address = Address32::repeat_byte(4);
+    // Enough code for the chunks to be on three stems, which ends with a PUSH32 whose data is cut off
+    let mut code: Vec<u8> = (0..31 * 300).map(|i| (i % 90) as u8).collect();
+    code.push(PUSH32);
+    code.extend([7u8; 10]);
+
+    let writes = CodeWrites::new::<TestHasher>(address, code.clone());
+    // The first 128 chunks are on the stem of the header, and each other stem has 256 chunks
+    let chunks_per_stem: Vec<_> = writes
+        .chunks_by_stem
+        .iter()
+        .map(|(_, chunks)| chunks.len())
+        .collect();
+    assert_eq!(chunks_per_stem, vec![128, 173]);
+    let header = Header::new::<TestHasher>(address);
+    assert_eq!(writes.chunks_by_stem[0].0, header.code_size()[0..31]);
+
+    let trie: BTreeMap<_, _> = writes.into_writes().into_iter().collect();
+    assert_eq!(trie.len(), 2 + 301);
+    let got = read_code::<TestHasher>(address, |key| trie.get(&key).copied());
+    assert_eq!(got, Ok(Some(code.clone())));
+
+    // Code which was not written
+    let got = read_code::<TestHasher>(Address32::repeat_byte(5), |key| trie.get(&key).copied());
+    assert_eq!(got, Ok(None));
+
+    // A missing chunk, a chunk with a wrong push data marker and a chunk with different code
+    let last_chunk = Code::new::<TestHasher>(address, U256::from(300u64)).code_chunk();
+    let mut missing = trie.clone();
+    missing.remove(last_chunk.as_fixed_bytes());
+    let got = read_code::<TestHasher>(address, |key| missing.get(&key).copied());
+    assert_eq!(got, Err(CodeError::MissingChunk(300)));
+
+    let mut wrong_marker = trie.clone();
+    wrong_marker.get_mut(last_chunk.as_fixed_bytes()).unwrap()[0] += 1;
+    let got = read_code::<TestHasher>(address, |key| wrong_marker.get(&key).copied());
+    assert_eq!(got, Err(CodeError::InvalidChunk(300)));
+
+    let mut wrong_code = trie;
+    wrong_code.get_mut(last_chunk.as_fixed_bytes()).unwrap()[2] += 1;
+    let got = read_code::<TestHasher>(address, |key| wrong_code.get(&key).copied());
+    assert_eq!(got, Err(CodeError::CodeHashMismatch));
+}
+
+#[test]
+fn empty_code() {
+    let writes = CodeWrites::new::<TestHasher>(Address32::repeat_byte(6), Vec::new());
+    assert!(writes.chunks_by_stem.is_empty());
+    // The hash of empty code
+    assert_eq!(
+        writes.code_hash.1,
+        [
+            0xc5, 0xd2, 0x46, 0x01, 0x86, 0xf7, 0x23, 0x3c, 0x92, 0x7e, 0x7d, 0xb2, 0xdc, 0xc7,
+            0x03, 0xc0, 0xe5, 0x00, 0xb6, 0x53, 0xca, 0x82, 0x27, 0x3b, 0x7b, 0xfa, 0xd8, 0x04,
+            0x5d, 0x85, 0xa4, 0x70
+        ]
+    );
+    assert_eq!(writes.into_writes().len(), 2);
+}
+
+#[test]
+fn code_writes_with_basic_data() {
+    use crate::AccountHeader;
+    use std::collections::BTreeMap;
+
+    let address = Address32::repeat_byte(7);
+    let code: Vec<u8> = (0..200).map(|i| i as u8).collect();
+
+    // The chunks are at the same keys in both layouts
+    let original = CodeWrites::new::<TestHasher>(address, code.clone());
+    let writes = CodeWrites::with_layout::<TestHasher>(address, code.clone(), Layout::BasicData);
+    assert_eq!(writes.chunks_by_stem, original.chunks_by_stem);
+    assert!(writes.code_size.is_none());
+
+    // The code size is read from the basic data, which is written with the account
+    let header = Header::with_layout::<TestHasher>(address, Layout::BasicData);
+    let account = AccountHeader {
+        code_size: code.len() as u64,
+        code_hash: decode_h256(writes.code_hash.1),
+        ..AccountHeader::default()
+    };
+    let mut trie: BTreeMap<_, _> = writes.into_writes().into_iter().collect();
+    let got = read_code_with_layout::<TestHasher>(address, Layout::BasicData, |key| {
+        trie.get(&key).copied()
+    });
+    assert_eq!(got, Err(CodeError::Header(ValueError::MissingField)));
+
+    trie.extend(account.to_writes(&header).unwrap());
+    let got = read_code_with_layout::<TestHasher>(address, Layout::BasicData, |key| {
+        trie.get(&key).copied()
+    });
+    assert_eq!(got, Ok(Some(code)));
+}
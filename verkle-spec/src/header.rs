@@ -1,14 +1,14 @@
 use ethereum_types::H256;
 use ethereum_types::U256;
 
-use crate::parameters::{
-    BALANCE_LEAF_KEY, CODE_KECCAK_LEAF_KEY, CODE_SIZE_LEAF_KEY, NONCE_LEAF_KEY, VERSION_LEAF_KEY,
-};
 use crate::util::hash_addr_int;
-use crate::Hasher;
 use crate::{util::swap_last_byte, Address32};
+use crate::{Hasher, Layout};
 
+// The keys of the account header. In the basic data layout, the version, balance, nonce
+// and code size are all at the key of the basic data leaf
 pub struct Header {
+    layout: Layout,
     balance_tree_key: H256,
     version_tree_key: H256,
     code_size_tree_key: H256,
@@ -18,20 +18,30 @@ pub struct Header {
 
 impl Header {
     pub fn new<H: Hasher>(address: Address32) -> Header {
+        Header::with_layout::<H>(address, Layout::Original)
+    }
+
+    pub fn with_layout<H: Hasher>(address: Address32, layout: Layout) -> Header {
         let tree_index = U256::zero();
-        Header::with_tree_index::<H>(address, tree_index)
+        Header::with_tree_index::<H>(address, tree_index, layout)
     }
 
-    pub fn with_tree_index<H: Hasher>(addr: Address32, tree_index: U256) -> Header {
+    pub fn with_tree_index<H: Hasher>(addr: Address32, tree_index: U256, layout: Layout) -> Header {
         let base_hash = hash_addr_int::<H>(addr, tree_index);
+        Header::from_base_hash(base_hash, layout)
+    }
 
-        let version_tree_key = swap_last_byte(base_hash, VERSION_LEAF_KEY);
-        let balance_tree_key = swap_last_byte(base_hash, BALANCE_LEAF_KEY);
-        let nonce_tree_key = swap_last_byte(base_hash, NONCE_LEAF_KEY);
-        let code_keccak_tree_key = swap_last_byte(base_hash, CODE_KECCAK_LEAF_KEY);
-        let code_size_tree_key = swap_last_byte(base_hash, CODE_SIZE_LEAF_KEY);
+    fn from_base_hash(base_hash: H256, layout: Layout) -> Header {
+        let [version, balance, nonce, code_keccak, code_size] = layout.header_leaf_keys();
+
+        let version_tree_key = swap_last_byte(base_hash, version);
+        let balance_tree_key = swap_last_byte(base_hash, balance);
+        let nonce_tree_key = swap_last_byte(base_hash, nonce);
+        let code_keccak_tree_key = swap_last_byte(base_hash, code_keccak);
+        let code_size_tree_key = swap_last_byte(base_hash, code_size);
 
         Header {
+            layout,
             balance_tree_key,
             version_tree_key,
             code_size_tree_key,
@@ -40,6 +50,10 @@ impl Header {
         }
     }
 
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
     pub fn balance(&self) -> H256 {
         self.balance_tree_key
     }
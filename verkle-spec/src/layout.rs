@@ -0,0 +1,42 @@
+use ethereum_types::U256;
+
+use crate::parameters::{
+    BALANCE_LEAF_KEY, BASIC_DATA_LEAF_KEY, CODE_HASH_LEAF_KEY, CODE_KECCAK_LEAF_KEY,
+    CODE_SIZE_LEAF_KEY, NONCE_LEAF_KEY, VERSION_LEAF_KEY,
+};
+
+// How the fields of an account header are stored in the leaves of the account's first stem.
+//
+// Storage slots and code chunks are at the same keys in both layouts, since the offsets
+// of the header storage, the code and the main storage did not change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    // The version, balance, nonce, code hash and code size each have their own leaf
+    #[default]
+    Original,
+    // The version, code size, nonce and balance are packed into the basic data leaf,
+    // and the code hash is in the leaf after it
+    BasicData,
+}
+
+impl Layout {
+    // The sub indices of the leaves which store the version, balance, nonce, code hash and code size
+    pub(crate) fn header_leaf_keys(&self) -> [U256; 5] {
+        match self {
+            Layout::Original => [
+                VERSION_LEAF_KEY,
+                BALANCE_LEAF_KEY,
+                NONCE_LEAF_KEY,
+                CODE_KECCAK_LEAF_KEY,
+                CODE_SIZE_LEAF_KEY,
+            ],
+            Layout::BasicData => [
+                BASIC_DATA_LEAF_KEY,
+                BASIC_DATA_LEAF_KEY,
+                BASIC_DATA_LEAF_KEY,
+                CODE_HASH_LEAF_KEY,
+                BASIC_DATA_LEAF_KEY,
+            ],
+        }
+    }
+}
@@ -1,6 +1,11 @@
+pub mod account;
 pub mod code;
 pub mod header;
+pub mod layout;
+#[cfg(feature = "pedersen")]
+pub mod pedersen;
 pub mod storage;
+pub mod witness;
 
 pub(crate) mod parameters;
 mod util;
@@ -11,9 +16,14 @@ mod util;
 
 pub use ethereum_types::{H160, H256, U256};
 
-pub use code::Code;
+pub use account::AccountHeader;
+pub use code::{read_code, read_code_with_layout, Code, CodeWrites};
 pub use header::Header;
+pub use layout::Layout;
+#[cfg(feature = "pedersen")]
+pub use pedersen::PedersenHasher;
 pub use storage::Storage;
+pub use witness::AccessWitness;
 
 // Used to hash the input in get_tree_key
 pub trait Hasher {
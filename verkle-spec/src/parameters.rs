@@ -6,6 +6,14 @@ pub(crate) const BALANCE_LEAF_KEY: U256 = U256([1, 0, 0, 0]);
 pub(crate) const NONCE_LEAF_KEY: U256 = U256([2, 0, 0, 0]);
 pub(crate) const CODE_KECCAK_LEAF_KEY: U256 = U256([3, 0, 0, 0]);
 pub(crate) const CODE_SIZE_LEAF_KEY: U256 = U256([4, 0, 0, 0]);
+// Leaf keys of the basic data layout
+pub(crate) const BASIC_DATA_LEAF_KEY: U256 = U256::zero();
+pub(crate) const CODE_HASH_LEAF_KEY: U256 = U256([1, 0, 0, 0]);
+// Offsets of the big endian fields in the basic data leaf. Bytes 1 to 4 are reserved
+pub(crate) const BASIC_DATA_VERSION_OFFSET: usize = 0;
+pub(crate) const BASIC_DATA_CODE_SIZE_OFFSET: usize = 5;
+pub(crate) const BASIC_DATA_NONCE_OFFSET: usize = 8;
+pub(crate) const BASIC_DATA_BALANCE_OFFSET: usize = 16;
 pub(crate) const HEADER_STORAGE_OFFSET: U256 = U256([64, 0, 0, 0]);
 pub(crate) const CODE_OFFSET: U256 = U256([128, 0, 0, 0]);
 pub(crate) const VERKLE_NODE_WIDTH: U256 = U256([256, 0, 0, 0]);
@@ -31,6 +39,12 @@ fn check_hardcoded_values() {
     let code_size_leaf_key = U256::from(4u8);
     assert_eq!(code_size_leaf_key, CODE_SIZE_LEAF_KEY);
 
+    let basic_data_leaf_key = U256::from(0u8);
+    assert_eq!(basic_data_leaf_key, BASIC_DATA_LEAF_KEY);
+
+    let code_hash_leaf_key = U256::from(1u8);
+    assert_eq!(code_hash_leaf_key, CODE_HASH_LEAF_KEY);
+
     let header_storage_offset = U256::from(64u8);
     assert_eq!(header_storage_offset, HEADER_STORAGE_OFFSET);
 
@@ -55,6 +69,15 @@ fn check_invariants() {
     assert!(HEADER_STORAGE_OFFSET > NONCE_LEAF_KEY);
     assert!(HEADER_STORAGE_OFFSET > CODE_KECCAK_LEAF_KEY);
     assert!(HEADER_STORAGE_OFFSET > CODE_SIZE_LEAF_KEY);
+    assert!(HEADER_STORAGE_OFFSET > BASIC_DATA_LEAF_KEY);
+    assert!(HEADER_STORAGE_OFFSET > CODE_HASH_LEAF_KEY);
+
+    // The fields of the basic data leaf do not overlap: a 1 byte version, 4 reserved bytes,
+    // 3 byte code size, 8 byte nonce and 16 byte balance
+    assert_eq!(BASIC_DATA_VERSION_OFFSET + 1 + 4, BASIC_DATA_CODE_SIZE_OFFSET);
+    assert_eq!(BASIC_DATA_CODE_SIZE_OFFSET + 3, BASIC_DATA_NONCE_OFFSET);
+    assert_eq!(BASIC_DATA_NONCE_OFFSET + 8, BASIC_DATA_BALANCE_OFFSET);
+    assert_eq!(BASIC_DATA_BALANCE_OFFSET + 16, 32);
 
     // MAIN_STORAGE_OFFSET must be a power of VERKLE_NODE_WIDTH
     //
@@ -0,0 +1,284 @@
+use ark_ec::ProjectiveCurve;
+use ark_ff::{BigInteger, Field, PrimeField};
+use bandersnatch::{EdwardsAffine, Fr};
+use ethereum_types::H256;
+use once_cell::sync::Lazy;
+use verkle_trie::committer::{precompute::PrecomputeLagrange, Committer};
+use verkle_trie::constants::CRS;
+
+use crate::Hasher;
+
+// The 64 byte input of get_tree_key is chunked into 5 scalars, so only the first 5 generators are needed
+const NUM_INPUT_CHUNKS: usize = 5;
+
+static COMMITTER: Lazy<PrecomputeLagrange> = Lazy::new(|| {
+    let generators: Vec<_> = CRS.G[..NUM_INPUT_CHUNKS]
+        .iter()
+        .map(|point| point.into_affine())
+        .collect();
+    PrecomputeLagrange::precompute(&generators)
+});
+
+// The hash used by get_tree_key.
+// The input is committed to with the first generators of the verkle trie, and the commitment
+// is mapped to a scalar, whose little endian bytes are the hash
+pub struct PedersenHasher;
+
+impl Hasher for PedersenHasher {
+    fn hash64(bytes64: [u8; 64]) -> H256 {
+        let inputs = Self::chunk64(bytes64);
+        let commitment = COMMITTER.commit_sparse(
+            inputs
+                .into_iter()
+                .enumerate()
+                .map(|(index, input)| (Fr::from(input), index))
+                .collect(),
+        );
+
+        let scalar = map_to_scalar_field(&commitment.into());
+        H256::from_slice(&scalar.into_repr().to_bytes_le())
+    }
+}
+
+// x/y is the same for P and -P, so it does not depend on the representative of the element
+fn map_to_scalar_field(point: &EdwardsAffine) -> Fr {
+    let y_inverse = point
+        .y
+        .inverse()
+        .expect("y is never zero for an element of the subgroup");
+    let x_over_y = point.x * y_inverse;
+    Fr::from_le_bytes_mod_order(&x_over_y.into_repr().to_bytes_le())
+}
+
+// The generators of EIP-6800. The i'th candidate is the sha256 hash of the seed and i as 8 big endian bytes,
+// reduced modulo the base field, and candidates which are not the x coordinate of a banderwagon element are skipped
+#[cfg(test)]
+fn eip_generators(num_points: usize) -> Vec<bandersnatch::Fq> {
+    use ark_ec::models::TEModelParameters;
+    use ark_ff::{One, SquareRootField};
+    use bandersnatch::{EdwardsParameters, Fq};
+    use sha2::{Digest, Sha256};
+
+    let mut xs = Vec::with_capacity(num_points);
+    let mut counter = 0u64;
+    while xs.len() < num_points {
+        let hash = Sha256::new()
+            .chain(b"eth_verkle_oct_2021")
+            .chain(counter.to_be_bytes())
+            .finalize();
+        let x = Fq::from_be_bytes_mod_order(&hash);
+        let is_element = (Fq::one() - EdwardsParameters::COEFF_A * x.square())
+            .legendre()
+            .is_qr();
+        if is_element && EdwardsAffine::get_point_from_x(x, true).is_some() {
+            xs.push(x)
+        }
+        counter += 1;
+    }
+    xs
+}
+
+// The banderwagon element of a point is given by its x coordinate, up to sign
+#[cfg(test)]
+fn banderwagon_x(point: &EdwardsAffine) -> bandersnatch::Fq {
+    if point.y > -point.y {
+        point.x
+    } else {
+        -point.x
+    }
+}
+
+#[test]
+fn generators_match_the_crs() {
+    // The hash must use the generators of EIP-6800, which are the first points of the CRS of the trie
+    let generators = &CRS.G[..NUM_INPUT_CHUNKS];
+    for (point, x) in generators.iter().zip(eip_generators(NUM_INPUT_CHUNKS)) {
+        assert_eq!(banderwagon_x(&point.into_affine()), x);
+    }
+
+    // The first point of the CRS, in its 32 byte big endian banderwagon encoding
+    assert_eq!(
+        hex_string(
+            &banderwagon_x(&CRS.G[0].into_affine())
+                .into_repr()
+                .to_bytes_be()
+        ),
+        "01587ad1336675eb912550ec2a28eb8923b824b490dd2ba82e48f14590a298a0"
+    );
+}
+
+#[test]
+fn hash_matches_the_commitment() {
+    use ark_ec::AffineCurve;
+
+    let mut bytes64 = [0u8; 64];
+    for (i, byte) in bytes64.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+
+    // Commit to the inputs without the precomputed tables
+    let commitment = CRS.G[..NUM_INPUT_CHUNKS]
+        .iter()
+        .zip(crate::util::chunk64(bytes64))
+        .map(|(point, input)| point.into_affine().mul(Fr::from(input)))
+        .sum::<bandersnatch::EdwardsProjective>();
+    let scalar = map_to_scalar_field(&commitment.into_affine());
+    let expected = H256::from_slice(&scalar.into_repr().to_bytes_le());
+
+    assert_eq!(PedersenHasher::hash64(bytes64), expected);
+    assert_ne!(PedersenHasher::hash64([0u8; 64]), expected);
+}
+
+#[test]
+fn tree_keys_match_the_reference() {
+    use crate::{util::hash_addr_int, util::swap_last_byte, U256};
+
+    // (address, tree index, sub index, get_tree_key)
+    let vectors = [
+        (
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            U256::zero(),
+            0u8,
+            "1a100684fd68185060405f3f160e4bb6e034194336b547bdae323f888d533200",
+        ),
+        (
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            U256::zero(),
+            1,
+            "1a100684fd68185060405f3f160e4bb6e034194336b547bdae323f888d533201",
+        ),
+        (
+            "1212121212121212121212121212121212121212121212121212121212121212",
+            U256::zero(),
+            1,
+            "aca8a8233976f71b203fd440f0eb07d89704e73e44b2fa9b990f13d5d7fc6d01",
+        ),
+        (
+            "00000000000000000000000071562b71999873db5b286df957af199ec94617f7",
+            U256::zero(),
+            1,
+            "1540dfad7755b40be0768c6aa0a5096fbf0215e0e8cf354dd928a17834646601",
+        ),
+        (
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+            U256([5, 0, 0, 2u64.pow(56)]),
+            64,
+            "bd349d30019e4eda78ae0f3698e870a2fa07b5c0947d169fc8b5038058f20440",
+        ),
+        (
+            "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+            U256::MAX,
+            255,
+            "2cc7fa3fa1b3e53de8f8a523e12dfb716e84f52b34647f0db621033d2be25bff",
+        ),
+    ];
+
+    for (address, tree_index, sub_index, expected) in vectors {
+        let address = crate::Address32::from_slice(&hex_bytes(address));
+        let base_hash = hash_addr_int::<PedersenHasher>(address, tree_index);
+        let tree_key = swap_last_byte(base_hash, U256::from(sub_index));
+        assert_eq!(hex_string(tree_key.as_bytes()), expected);
+    }
+}
+
+#[test]
+fn layout_keys_match_the_reference() {
+    use crate::{Code, Header, Layout, Storage, U256};
+
+    let address = crate::Address32::from_slice(&hex_bytes(
+        "00000000000000000000000071562b71999873db5b286df957af199ec94617f7",
+    ));
+    let stem = "1540dfad7755b40be0768c6aa0a5096fbf0215e0e8cf354dd928a178346466";
+    let key = |sub_index: &str| format!("{}{}", stem, sub_index);
+
+    // The version, balance, nonce, code hash and code size
+    let header = Header::with_layout::<PedersenHasher>(address, Layout::Original);
+    let keys = [
+        header.version(),
+        header.balance(),
+        header.nonce(),
+        header.code_keccak(),
+        header.code_size(),
+    ];
+    let expected = [key("00"), key("01"), key("02"), key("03"), key("04")];
+    for (key, expected) in keys.iter().zip(expected) {
+        assert_eq!(hex_string(key.as_bytes()), expected);
+    }
+
+    // In the basic data layout, everything but the code hash is in the basic data leaf
+    let header = Header::with_layout::<PedersenHasher>(address, Layout::BasicData);
+    let keys = [
+        header.version(),
+        header.balance(),
+        header.nonce(),
+        header.code_keccak(),
+        header.code_size(),
+    ];
+    let expected = [key("00"), key("00"), key("00"), key("01"), key("00")];
+    for (key, expected) in keys.iter().zip(expected) {
+        assert_eq!(hex_string(key.as_bytes()), expected);
+    }
+
+    // Storage slots and code chunks have the same keys in both layouts
+    let storage = Storage::new::<PedersenHasher>(address, U256::zero());
+    assert_eq!(hex_string(storage.storage_slot().as_bytes()), key("40"));
+    let storage = Storage::new::<PedersenHasher>(address, U256::from(64u8));
+    assert_eq!(
+        hex_string(storage.storage_slot().as_bytes()),
+        "3163ce6f64dc2f2d0cdbd389a918856590810c519fe9dffdc9c35da3a723cf40"
+    );
+    let code = Code::new::<PedersenHasher>(address, U256::zero());
+    assert_eq!(hex_string(code.code_chunk().as_bytes()), key("80"));
+    let code = Code::new::<PedersenHasher>(address, U256::from(200u8));
+    assert_eq!(
+        hex_string(code.code_chunk().as_bytes()),
+        "ae1a2cf26c0967cbb5334d8a99cace67ba7a9e194daa4c3e8eef537e7c265b48"
+    );
+
+    // The layout is also used for headers at other tree indices
+    let header =
+        Header::with_tree_index::<PedersenHasher>(address, U256::from(7u8), Layout::BasicData);
+    assert_eq!(
+        hex_string(header.nonce().as_bytes()),
+        "247c2708b832eccdb128e65776c78bd3d6f67e56431cc6c88a2ccb43b0b37b00"
+    );
+    assert_eq!(
+        hex_string(header.code_keccak().as_bytes()),
+        "247c2708b832eccdb128e65776c78bd3d6f67e56431cc6c88a2ccb43b0b37b01"
+    );
+}
+
+#[test]
+fn tree_keys_share_a_stem() {
+    use crate::{Header, Storage, U256};
+
+    let address = crate::Address32::repeat_byte(0x12);
+    let header = Header::new::<PedersenHasher>(address);
+    let balance = header.balance();
+    let nonce = header.nonce();
+    assert_eq!(balance[0..31], nonce[0..31]);
+    assert_eq!(balance[31], 1);
+    assert_eq!(nonce[31], 2);
+
+    // The first 64 storage slots are on the same stem as the header
+    let storage = Storage::new::<PedersenHasher>(address, U256::from(5u8));
+    assert_eq!(storage.storage_slot()[0..31], balance[0..31]);
+    assert_eq!(storage.storage_slot()[31], 64 + 5);
+
+    // A different address has a different stem
+    let other_header = Header::new::<PedersenHasher>(crate::Address32::repeat_byte(0x13));
+    assert_ne!(other_header.balance()[0..31], balance[0..31]);
+}
+
+#[cfg(test)]
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+fn hex_bytes(hex_str: &str) -> Vec<u8> {
+    (0..hex_str.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex_str[i..i + 2], 16).unwrap())
+        .collect()
+}
@@ -6,20 +6,17 @@ use crate::{
     Address32, Hasher,
 };
 
+// The key of a storage slot, which is the same in both header layouts
 pub struct Storage {
     storage_slot_tree_key: H256,
 }
 
 impl Storage {
     pub fn new<H: Hasher>(address: Address32, storage_key: U256) -> Storage {
-        let pos = if storage_key < (CODE_OFFSET - HEADER_STORAGE_OFFSET) {
-            HEADER_STORAGE_OFFSET + storage_key
-        } else {
-            MAIN_STORAGE_OFFSET + storage_key
-        };
+        let (tree_index, sub_index) = storage_slot_position(storage_key);
 
-        let base_hash = hash_addr_int::<H>(address, pos / VERKLE_NODE_WIDTH);
-        let storage_slot_tree_key = swap_last_byte(base_hash, pos % VERKLE_NODE_WIDTH);
+        let base_hash = hash_addr_int::<H>(address, tree_index);
+        let storage_slot_tree_key = swap_last_byte(base_hash, sub_index);
 
         Storage {
             storage_slot_tree_key,
@@ -30,3 +27,75 @@ impl Storage {
         self.storage_slot_tree_key
     }
 }
+
+// The tree index and sub index of a storage slot. The first slots are on the stem of
+// the header, and the rest are in the main storage.
+//
+// `MAIN_STORAGE_OFFSET + storage_key` does not fit in 256 bits for large storage keys,
+// so the tree index is computed from the two terms separately. This is exact since
+// `MAIN_STORAGE_OFFSET` is a multiple of `VERKLE_NODE_WIDTH`
+pub(crate) fn storage_slot_position(storage_key: U256) -> (U256, U256) {
+    if storage_key < (CODE_OFFSET - HEADER_STORAGE_OFFSET) {
+        let pos = HEADER_STORAGE_OFFSET + storage_key;
+        (pos / VERKLE_NODE_WIDTH, pos % VERKLE_NODE_WIDTH)
+    } else {
+        let tree_index = MAIN_STORAGE_OFFSET / VERKLE_NODE_WIDTH + storage_key / VERKLE_NODE_WIDTH;
+        (tree_index, storage_key % VERKLE_NODE_WIDTH)
+    }
+}
+
+#[test]
+fn header_storage_follows_the_header() {
+    use crate::{Header, Layout};
+
+    struct TestHasher;
+    impl Hasher for TestHasher {
+        fn hash64(bytes64: [u8; 64]) -> H256 {
+            let mut hash = [0u8; 32];
+            for (i, byte) in hash.iter_mut().enumerate() {
+                *byte = bytes64[i] ^ bytes64[32 + i];
+            }
+            H256::from(hash)
+        }
+    }
+
+    let address = Address32::repeat_byte(9);
+    for layout in [Layout::Original, Layout::BasicData] {
+        let header = Header::with_layout::<TestHasher>(address, layout);
+        // The first 64 slots are on the stem of the header, after the header leaves
+        for slot in [0u8, 63] {
+            let key = Storage::new::<TestHasher>(address, U256::from(slot)).storage_slot();
+            assert_eq!(key[0..31], header.balance()[0..31]);
+            assert_eq!(key[31], 64 + slot);
+        }
+        // The other slots are in the main storage
+        let key = Storage::new::<TestHasher>(address, U256::from(64u8)).storage_slot();
+        assert_ne!(key[0..31], header.balance()[0..31]);
+    }
+}
+
+#[test]
+fn large_storage_keys_do_not_overflow() {
+    // The tree index is 2^248 / 256 + (2^256 - 1) / 256 = 2^240 + 2^248 - 1
+    let (tree_index, sub_index) = storage_slot_position(U256::MAX);
+    assert_eq!(
+        tree_index,
+        U256([
+            u64::MAX,
+            u64::MAX,
+            u64::MAX,
+            2u64.pow(48) + 2u64.pow(56) - 1
+        ])
+    );
+    assert_eq!(sub_index, U256::from(255u8));
+
+    // The first slot of the main storage
+    let (tree_index, sub_index) = storage_slot_position(U256::from(64u8));
+    assert_eq!(tree_index, MAIN_STORAGE_OFFSET / VERKLE_NODE_WIDTH);
+    assert_eq!(sub_index, U256::from(64u8));
+
+    // The last slot on the stem of the header
+    let (tree_index, sub_index) = storage_slot_position(U256::from(63u8));
+    assert_eq!(tree_index, U256::zero());
+    assert_eq!(sub_index, U256::from(127u8));
+}
@@ -0,0 +1,314 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::marker::PhantomData;
+
+use ethereum_types::{H256, U256};
+
+use crate::{
+    code::Bytes32,
+    parameters::{CODE_OFFSET, VERKLE_NODE_WIDTH},
+    storage::storage_slot_position,
+    util::{hash_addr_int, swap_last_byte},
+    Address32, Hasher, Layout,
+};
+
+// Gas costs of EIP-4762
+//
+// Charged the first time a stem is accessed
+pub const WITNESS_BRANCH_COST: u64 = 1900;
+// Charged the first time a leaf is accessed
+pub const WITNESS_CHUNK_COST: u64 = 200;
+// Charged the first time a leaf on a stem is written to
+pub const SUBTREE_EDIT_COST: u64 = 3000;
+// Charged the first time a leaf is written to
+pub const CHUNK_EDIT_COST: u64 = 500;
+// Charged the first time a leaf which had no value is written to
+pub const CHUNK_FILL_COST: u64 = 6200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    // A write to a leaf which already had a value
+    Write,
+    // A write to a leaf which had no value
+    Fill,
+}
+
+// The fields of an account header. In the basic data layout, the version, balance,
+// nonce and code size are in the same leaf, so only the first of them to be accessed is charged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderField {
+    Version,
+    Balance,
+    Nonce,
+    CodeHash,
+    CodeSize,
+}
+
+// Records the stems and leaves which are accessed during the execution of a block,
+// and charges gas for the first read and the first write of each of them.
+//
+// A write is also an access, so writing to a leaf which was not read is charged for both.
+// The keys of all of the leaves which were accessed make up the witness, which can be
+// passed to `create_verkle_proof`
+pub struct AccessWitness<H: Hasher> {
+    layout: Layout,
+    // The base hash of every stem which was accessed, keyed by address and tree index
+    stems: BTreeMap<(Address32, U256), H256>,
+    written_stems: BTreeSet<(Address32, U256)>,
+    leaves: BTreeSet<Bytes32>,
+    written_leaves: BTreeSet<Bytes32>,
+    filled_leaves: BTreeSet<Bytes32>,
+    _hasher: PhantomData<H>,
+}
+
+impl<H: Hasher> AccessWitness<H> {
+    pub fn new(layout: Layout) -> AccessWitness<H> {
+        AccessWitness {
+            layout,
+            stems: BTreeMap::new(),
+            written_stems: BTreeSet::new(),
+            leaves: BTreeSet::new(),
+            written_leaves: BTreeSet::new(),
+            filled_leaves: BTreeSet::new(),
+            _hasher: PhantomData,
+        }
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    // Returns the gas which is charged for accessing a field of the account header
+    pub fn touch_header(&mut self, address: Address32, field: HeaderField, access: Access) -> u64 {
+        let [version, balance, nonce, code_hash, code_size] = self.layout.header_leaf_keys();
+        let pos = match field {
+            HeaderField::Version => version,
+            HeaderField::Balance => balance,
+            HeaderField::Nonce => nonce,
+            HeaderField::CodeHash => code_hash,
+            HeaderField::CodeSize => code_size,
+        };
+        self.touch(address, U256::zero(), pos, access)
+    }
+
+    // Returns the gas which is charged for accessing a storage slot
+    pub fn touch_storage_slot(
+        &mut self,
+        address: Address32,
+        storage_key: U256,
+        access: Access,
+    ) -> u64 {
+        let (tree_index, sub_index) = storage_slot_position(storage_key);
+        self.touch(address, tree_index, sub_index, access)
+    }
+
+    // Returns the gas which is charged for accessing the code chunks that hold
+    // the bytes from `start` up to, but not including, `end`
+    pub fn touch_code_range(
+        &mut self,
+        address: Address32,
+        start: u64,
+        end: u64,
+        access: Access,
+    ) -> u64 {
+        if start >= end {
+            return 0;
+        }
+
+        // Each chunk holds 31 bytes of code
+        let first_chunk = start / 31;
+        let last_chunk = (end - 1) / 31;
+        (first_chunk..=last_chunk)
+            .map(|chunk_id| {
+                let pos = CODE_OFFSET + U256::from(chunk_id);
+                self.touch(
+                    address,
+                    pos / VERKLE_NODE_WIDTH,
+                    pos % VERKLE_NODE_WIDTH,
+                    access,
+                )
+            })
+            .sum()
+    }
+
+    // The keys of every leaf which was accessed, in ascending order
+    pub fn keys(&self) -> Vec<Bytes32> {
+        self.leaves.iter().copied().collect()
+    }
+
+    // The keys of every leaf which was written to, in ascending order
+    pub fn written_keys(&self) -> Vec<Bytes32> {
+        self.written_leaves.iter().copied().collect()
+    }
+
+    fn touch(
+        &mut self,
+        address: Address32,
+        tree_index: U256,
+        sub_index: U256,
+        access: Access,
+    ) -> u64 {
+        let stem = (address, tree_index);
+        let mut gas = 0;
+
+        let base_hash = match self.stems.get(&stem) {
+            Some(base_hash) => *base_hash,
+            None => {
+                gas += WITNESS_BRANCH_COST;
+                let base_hash = hash_addr_int::<H>(stem.0, stem.1);
+                self.stems.insert(stem, base_hash);
+                base_hash
+            }
+        };
+        let key = swap_last_byte(base_hash, sub_index).to_fixed_bytes();
+        if self.leaves.insert(key) {
+            gas += WITNESS_CHUNK_COST;
+        }
+
+        if access == Access::Read {
+            return gas;
+        }
+        if self.written_stems.insert(stem) {
+            gas += SUBTREE_EDIT_COST;
+        }
+        if self.written_leaves.insert(key) {
+            gas += CHUNK_EDIT_COST;
+        }
+        if access == Access::Fill && self.filled_leaves.insert(key) {
+            gas += CHUNK_FILL_COST;
+        }
+        gas
+    }
+}
+
+#[cfg(test)]
+struct TestHasher;
+
+#[cfg(test)]
+impl Hasher for TestHasher {
+    fn hash64(bytes64: [u8; 64]) -> H256 {
+        let mut hash = [0u8; 32];
+        for (i, byte) in hash.iter_mut().enumerate() {
+            *byte = bytes64[i] ^ bytes64[32 + i];
+        }
+        H256::from(hash)
+    }
+}
+
+#[test]
+fn reads_are_charged_once() {
+    let address = Address32::repeat_byte(1);
+
+    let mut witness = AccessWitness::<TestHasher>::new(Layout::Original);
+    let gas = witness.touch_header(address, HeaderField::Balance, Access::Read);
+    assert_eq!(gas, WITNESS_BRANCH_COST + WITNESS_CHUNK_COST);
+    assert_eq!(
+        witness.touch_header(address, HeaderField::Balance, Access::Read),
+        0
+    );
+    // The nonce is on the same stem, but in a different leaf
+    let gas = witness.touch_header(address, HeaderField::Nonce, Access::Read);
+    assert_eq!(gas, WITNESS_CHUNK_COST);
+
+    // In the basic data layout, the balance and the nonce are in the same leaf
+    let mut witness = AccessWitness::<TestHasher>::new(Layout::BasicData);
+    witness.touch_header(address, HeaderField::Balance, Access::Read);
+    assert_eq!(
+        witness.touch_header(address, HeaderField::Nonce, Access::Read),
+        0
+    );
+    let gas = witness.touch_header(address, HeaderField::CodeHash, Access::Read);
+    assert_eq!(gas, WITNESS_CHUNK_COST);
+
+    // Another account has its own stem
+    let other = Address32::repeat_byte(2);
+    let gas = witness.touch_header(other, HeaderField::Balance, Access::Read);
+    assert_eq!(gas, WITNESS_BRANCH_COST + WITNESS_CHUNK_COST);
+}
+
+#[test]
+fn writes_are_charged_once() {
+    let address = Address32::repeat_byte(1);
+    let mut witness = AccessWitness::<TestHasher>::new(Layout::Original);
+
+    witness.touch_header(address, HeaderField::Balance, Access::Read);
+    let gas = witness.touch_header(address, HeaderField::Balance, Access::Write);
+    assert_eq!(gas, SUBTREE_EDIT_COST + CHUNK_EDIT_COST);
+    assert_eq!(
+        witness.touch_header(address, HeaderField::Balance, Access::Write),
+        0
+    );
+
+    // A write to another leaf of the same stem, which was not read before
+    let gas = witness.touch_header(address, HeaderField::Nonce, Access::Fill);
+    assert_eq!(gas, WITNESS_CHUNK_COST + CHUNK_EDIT_COST + CHUNK_FILL_COST);
+    assert_eq!(
+        witness.touch_header(address, HeaderField::Nonce, Access::Fill),
+        0
+    );
+
+    // A write to a new stem is charged for all of the accesses
+    let gas = witness.touch_storage_slot(address, U256::from(1000u16), Access::Fill);
+    assert_eq!(
+        gas,
+        WITNESS_BRANCH_COST
+            + WITNESS_CHUNK_COST
+            + SUBTREE_EDIT_COST
+            + CHUNK_EDIT_COST
+            + CHUNK_FILL_COST
+    );
+    assert_eq!(witness.written_keys().len(), 3);
+}
+
+#[test]
+fn storage_and_code_share_the_header_stem() {
+    let address = Address32::repeat_byte(1);
+    let mut witness = AccessWitness::<TestHasher>::new(Layout::BasicData);
+    witness.touch_header(address, HeaderField::CodeSize, Access::Read);
+
+    // The first storage slots and code chunks are on the stem of the header
+    let gas = witness.touch_storage_slot(address, U256::from(3u8), Access::Read);
+    assert_eq!(gas, WITNESS_CHUNK_COST);
+    let gas = witness.touch_code_range(address, 0, 62, Access::Read);
+    assert_eq!(gas, 2 * WITNESS_CHUNK_COST);
+    assert_eq!(witness.touch_code_range(address, 10, 40, Access::Read), 0);
+    assert_eq!(witness.touch_code_range(address, 40, 40, Access::Read), 0);
+
+    // Chunk 127 is the last leaf of the header stem, and chunk 128 is on the next stem
+    let gas = witness.touch_code_range(address, 127 * 31, 128 * 31 + 1, Access::Read);
+    assert_eq!(gas, WITNESS_BRANCH_COST + 2 * WITNESS_CHUNK_COST);
+
+    // The main storage is on other stems
+    let gas = witness.touch_storage_slot(address, U256::from(64u8), Access::Read);
+    assert_eq!(gas, WITNESS_BRANCH_COST + WITNESS_CHUNK_COST);
+    let gas = witness.touch_storage_slot(address, U256::MAX, Access::Read);
+    assert_eq!(gas, WITNESS_BRANCH_COST + WITNESS_CHUNK_COST);
+}
+
+#[test]
+fn keys_match_the_tree_keys() {
+    use crate::{Code, Header, Storage};
+
+    let address = Address32::repeat_byte(1);
+    let mut witness = AccessWitness::<TestHasher>::new(Layout::BasicData);
+    witness.touch_header(address, HeaderField::Balance, Access::Read);
+    witness.touch_header(address, HeaderField::Nonce, Access::Write);
+    witness.touch_storage_slot(address, U256::from(70u8), Access::Read);
+    witness.touch_code_range(address, 0, 1, Access::Read);
+
+    let header = Header::with_layout::<TestHasher>(address, Layout::BasicData);
+    let storage = Storage::new::<TestHasher>(address, U256::from(70u8));
+    let code = Code::new::<TestHasher>(address, U256::zero());
+    let mut expected = vec![
+        header.balance().to_fixed_bytes(),
+        storage.storage_slot().to_fixed_bytes(),
+        code.code_chunk().to_fixed_bytes(),
+    ];
+    expected.sort();
+
+    assert_eq!(witness.keys(), expected);
+    assert_eq!(
+        witness.written_keys(),
+        vec![header.nonce().to_fixed_bytes()]
+    );
+}
@@ -1,5 +1,6 @@
 use crate::committer::{precompute::PrecomputeLagrange, test::TestCommitter};
 use crate::constants::CRS;
+use crate::errors::VerkleError;
 use ark_ec::ProjectiveCurve;
 use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
 use std::fs::File;
@@ -16,37 +17,29 @@ pub struct Config<Storage, PolyCommit> {
 // before running the tests; which are ran in parallel.
 const PRECOMPUTED_POINTS_PATH: &'static str = "precomputed_points.bin";
 
-// TODO: These two functions return Strings, when they should return a result with an enum variant ideally
-// TODO: This is an API change and will be done in the API refactor phase.
-
 pub type VerkleConfig<Storage> = Config<Storage, PrecomputeLagrange>;
 impl<Storage> VerkleConfig<Storage> {
-    pub fn new(db: Storage) -> Result<Self, &'static str> {
+    pub fn new(db: Storage) -> Result<Self, VerkleError> {
         let file_exists = std::path::Path::new(PRECOMPUTED_POINTS_PATH).exists();
         if file_exists {
-            return Err(
-                "file with precomputed points already exists. Please call the `open` method",
-            );
+            return Err(VerkleError::PrecomputedPointsExist);
         }
 
         // File is not already precomputed, so we pre-compute the points and store them
-        let mut file = File::create(PRECOMPUTED_POINTS_PATH).unwrap();
+        let mut file = File::create(PRECOMPUTED_POINTS_PATH)?;
         let g_aff: Vec<_> = CRS.G.iter().map(|point| point.into_affine()).collect();
         let committer = PrecomputeLagrange::precompute(&g_aff);
-        committer.serialize_unchecked(&mut file).unwrap();
+        committer.serialize_unchecked(&mut file)?;
         Ok(Config { db, committer })
     }
 
-    pub fn open(db: Storage) -> Result<Self, &'static str> {
+    pub fn open(db: Storage) -> Result<Self, VerkleError> {
         let file_exists = std::path::Path::new(PRECOMPUTED_POINTS_PATH).exists();
         if !file_exists {
-            return Err(
-                "file with precomputed points does not exist. Please call the `new` method",
-            );
+            return Err(VerkleError::PrecomputedPointsMissing);
         }
-        let mut file = File::open(PRECOMPUTED_POINTS_PATH).unwrap();
-        let committer: PrecomputeLagrange =
-            CanonicalDeserialize::deserialize_unchecked(&mut file).unwrap();
+        let mut file = File::open(PRECOMPUTED_POINTS_PATH)?;
+        let committer: PrecomputeLagrange = CanonicalDeserialize::deserialize_unchecked(&mut file)?;
         return Ok(Config { db, committer });
     }
 }
@@ -1,9 +1,18 @@
+pub mod archive;
+pub mod cache;
 pub mod default;
 mod generic;
+pub mod journal;
 pub mod memory_db;
 pub mod meta;
+mod pruner;
+#[cfg(test)]
+pub(crate) mod test_db;
 
+pub use archive::HistoricalDb;
+pub use cache::{CachePolicy, CacheStats};
 pub use default::VerkleDb;
+pub use journal::JournaledDb;
 pub use meta::{BranchChild, BranchMeta, Meta, StemMeta};
 pub trait ReadWriteHigherDb: ReadOnlyHigherDb + WriteOnlyHigherDb {}
 impl<T: ReadOnlyHigherDb + WriteOnlyHigherDb> ReadWriteHigherDb for T {}
@@ -13,7 +22,6 @@ impl<T: ReadOnlyHigherDb + WriteOnlyHigherDb> ReadWriteHigherDb for T {}
 // 2) Implement lower level traits in verkle-db, then use VerkleDb. The traits in this file will
 // be automatically implemented using the traits in verkle-db
 
-// TODO we need a populate cache method that populates the cache from storage
 // TODO Think of a better name than ReadOnlyHigherDb, WriteOnlyHigherDb
 // Allows a component to flush their memory database to disk
 // This is a no-op for components which are just memory databases
@@ -44,6 +52,15 @@ pub trait WriteOnlyHigherDb {
     // TODO maybe we can return BranchChild, as the previous data could have been a stem or branch_meta
     // TODO then we can leave it upto the caller on how to deal with it
     fn insert_branch(&mut self, key: Vec<u8>, meta: BranchMeta, _depth: u8) -> Option<BranchMeta>;
+
+    // The remove methods return the previous value, if the implementation has it to hand
+    // Disk backed writers will return None, since they do not read before deleting
+    fn remove_leaf(&mut self, key: [u8; 32]) -> Option<[u8; 32]>;
+
+    fn remove_stem(&mut self, key: [u8; 31]) -> Option<StemMeta>;
+
+    // Removes whatever is stored under this path, this can either be a stem or a branch node
+    fn remove_branch_child(&mut self, branch_child_id: Vec<u8>) -> Option<BranchChild>;
 }
 
 // Notice that these take self, which effectively forces the implementer
@@ -54,11 +71,9 @@ pub trait ReadOnlyHigherDb {
 
     fn get_branch_meta(&self, key: &[u8]) -> Option<BranchMeta>;
 
-    // TODO add a range query for the default database in verkle_db
     fn get_branch_children(&self, branch_id: &[u8]) -> Vec<(u8, BranchChild)>;
     fn get_branch_child(&self, branch_id: &[u8], index: u8) -> Option<BranchChild>;
 
-    // TODO add a range query for the default database in verkle_db
     fn get_stem_children(&self, stem_key: [u8; 31]) -> Vec<(u8, [u8; 32])>;
     fn get_leaf(&self, key: [u8; 32]) -> Option<[u8; 32]>;
 
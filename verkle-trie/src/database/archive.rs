@@ -0,0 +1,453 @@
+use super::{
+    generic::{BRANCH_TABLE_MARKER, LEAF_TABLE_MARKER, STEM_TABLE_MARKER},
+    BranchChild, BranchMeta, ReadOnlyHigherDb, StemMeta,
+};
+use crate::errors::VerkleError;
+use verkle_db::{prefix_end, BareMetalKVDb, BatchWriter};
+
+// In archive mode, every node that is flushed is also stored as a versioned record, so that the
+// trie can be opened read-only at any epoch (e.g. a block number) which has been flushed.
+//
+// The value of a node at each epoch that it was written at is stored as
+//   (VERSIONED_NODE_MARKER || table marker || node key || !epoch) -> node bytes
+// where an empty value means that the node was removed. Like in the latest state, the key of a
+// branch child is its path length followed by its path, so the records of the children of a
+// branch node share a prefix which none of their descendants have.
+//
+// Epochs are stored inverted and big endian, so the records of a node are sorted from the latest
+// epoch to the earliest. The version which is read at epoch `e` is then the first record at or
+// after (node key || !e), which a bounded range read finds without reading any other version.
+pub(crate) const VERSIONED_NODE_MARKER: u8 = 4;
+// Each archived epoch is stored as (ARCHIVED_EPOCH_MARKER || !epoch) -> (), so the latest one comes first
+pub(crate) const ARCHIVED_EPOCH_MARKER: u8 = 5;
+
+pub(super) const EPOCH_LEN: usize = 8;
+
+const CORRUPT_ARCHIVE: &str = "could not decode an archived node, the database is corrupt";
+
+// A node which was flushed, along with its encoded value.
+// `None` means that the node was removed
+pub(crate) struct ArchivedNode {
+    pub table: u8,
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+}
+
+// The prefix of all of the versioned records of a node
+fn node_prefix(table: u8, key: &[u8]) -> Vec<u8> {
+    let mut labelled_key = Vec::with_capacity(key.len() + 3 + EPOCH_LEN);
+    labelled_key.push(VERSIONED_NODE_MARKER);
+    labelled_key.push(table);
+    if table == BRANCH_TABLE_MARKER {
+        labelled_key.push(key.len() as u8);
+    }
+    labelled_key.extend_from_slice(key);
+    labelled_key
+}
+
+fn encode_epoch(epoch: u64) -> [u8; EPOCH_LEN] {
+    (!epoch).to_be_bytes()
+}
+
+fn decode_epoch(bytes: &[u8]) -> u64 {
+    !u64::from_be_bytes(bytes.try_into().expect(CORRUPT_ARCHIVE))
+}
+
+pub(super) fn versioned_node_key(table: u8, key: &[u8], epoch: u64) -> Vec<u8> {
+    let mut labelled_key = node_prefix(table, key);
+    labelled_key.extend_from_slice(&encode_epoch(epoch));
+    labelled_key
+}
+
+// Returns the table marker, node key and epoch of a versioned record
+pub(super) fn decode_versioned_node_key(labelled_key: &[u8]) -> (u8, Vec<u8>, u64) {
+    let table = labelled_key[1];
+    let (key, epoch) = labelled_key[2..].split_at(labelled_key.len() - 2 - EPOCH_LEN);
+    // The path of a branch child is after its length
+    let key = if table == BRANCH_TABLE_MARKER {
+        &key[1..]
+    } else {
+        key
+    };
+    (table, key.to_vec(), decode_epoch(epoch))
+}
+
+fn archived_epoch_key(epoch: u64) -> Vec<u8> {
+    let mut labelled_key = Vec::with_capacity(1 + EPOCH_LEN);
+    labelled_key.push(ARCHIVED_EPOCH_MARKER);
+    labelled_key.extend_from_slice(&encode_epoch(epoch));
+    labelled_key
+}
+
+pub(crate) fn is_archived<S: BareMetalKVDb>(storage: &S, epoch: u64) -> bool {
+    storage.fetch(&archived_epoch_key(epoch)).is_some()
+}
+
+// Returns the archived epochs, in increasing order
+pub(crate) fn archived_epochs<S: BareMetalKVDb>(storage: &S) -> Vec<u64> {
+    let mut epochs: Vec<_> = storage
+        .iter_prefix(&[ARCHIVED_EPOCH_MARKER])
+        .map(|(key, _)| decode_epoch(&key[1..]))
+        .collect();
+    epochs.reverse();
+    epochs
+}
+
+pub(crate) fn latest_archived_epoch<S: BareMetalKVDb>(storage: &S) -> Option<u64> {
+    storage
+        .iter_prefix(&[ARCHIVED_EPOCH_MARKER])
+        .next()
+        .map(|(key, _)| decode_epoch(&key[1..]))
+}
+
+pub(super) fn remove_archived_epoch<W: BatchWriter>(writer: &mut W, epoch: u64) {
+    writer.batch_delete(&archived_epoch_key(epoch))
+}
+
+// Checks that `epoch` does not rewrite history which has already been archived
+pub(crate) fn check_epoch<S: BareMetalKVDb>(storage: &S, epoch: u64) -> Result<(), VerkleError> {
+    match latest_archived_epoch(storage) {
+        Some(latest) if epoch < latest => Err(VerkleError::StaleEpoch { latest, epoch }),
+        _ => Ok(()),
+    }
+}
+
+// Adds the versioned records for `nodes` at `epoch` to the batch.
+// If a node has already been archived at this epoch, its record is overwritten
+pub(crate) fn write_versions<W: BatchWriter>(
+    writer: &mut W,
+    epoch: u64,
+    nodes: impl Iterator<Item = ArchivedNode>,
+) {
+    for node in nodes {
+        let value = node.value.unwrap_or_default();
+        writer.batch_put(&versioned_node_key(node.table, &node.key, epoch), &value);
+    }
+    writer.batch_put(&archived_epoch_key(epoch), &[]);
+}
+
+// A read-only view of the archived state of the trie, as it was at the end of `epoch`.
+// This only reads from the key value database, so the state must have been flushed
+pub struct HistoricalDb<'a, S> {
+    storage: &'a S,
+    epoch: u64,
+}
+
+impl<'a, S: BareMetalKVDb> HistoricalDb<'a, S> {
+    // Returns an error if `epoch` was never archived, or it has been pruned
+    pub(crate) fn new(storage: &'a S, epoch: u64) -> Result<Self, VerkleError> {
+        if !is_archived(storage, epoch) {
+            return Err(VerkleError::EpochNotArchived(epoch));
+        }
+        Ok(HistoricalDb::at_archived_epoch(storage, epoch))
+    }
+
+    // The caller must have checked that `epoch` is in the archive
+    pub(crate) fn at_archived_epoch(storage: &'a S, epoch: u64) -> Self {
+        HistoricalDb { storage, epoch }
+    }
+
+    // The archived epoch which this view reads from
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    fn fetch_at_epoch(&self, table: u8, key: &[u8]) -> Option<Vec<u8>> {
+        let prefix = node_prefix(table, key);
+        let end = prefix_end(&prefix);
+        let (_, value) = self
+            .storage
+            .iter_range(&versioned_node_key(table, key, self.epoch), end.as_deref())
+            .next()?;
+        // An empty value means that the node was removed at this epoch
+        if value.is_empty() {
+            return None;
+        }
+        Some(value)
+    }
+
+    // Returns the values of the children of a node at this epoch, where `prefix` is the prefix which
+    // the records of all of its children share, and a child is identified by the byte after the prefix.
+    //
+    // The first record at or after (child || !epoch) is the version of that child which is read at the epoch,
+    // unless the child was never written before it. Then it is the latest version of a later child, and the
+    // range is read again from that child. So this reads at most two ranges for each child which was written
+    fn children_at_epoch(&self, prefix: Vec<u8>) -> Vec<(u8, Vec<u8>)> {
+        let end = prefix_end(&prefix);
+        let mut children = Vec::new();
+        let mut start = prefix.clone();
+        start.push(0);
+        start.extend_from_slice(&encode_epoch(self.epoch));
+
+        while let Some((key, value)) = self.storage.iter_range(&start, end.as_deref()).next() {
+            let index = key[prefix.len()];
+            let epoch = decode_epoch(&key[prefix.len() + 1..]);
+            if epoch <= self.epoch {
+                if !value.is_empty() {
+                    children.push((index, value));
+                }
+                match index.checked_add(1) {
+                    Some(next) => start[prefix.len()] = next,
+                    None => break,
+                }
+            } else {
+                start[prefix.len()] = index;
+            }
+        }
+        children
+    }
+
+    fn get_branch_child_at(&self, id: &[u8]) -> Option<BranchChild> {
+        self.fetch_at_epoch(BRANCH_TABLE_MARKER, id)
+            .map(|bytes| BranchChild::from_bytes(&bytes).expect(CORRUPT_ARCHIVE))
+    }
+}
+
+impl<'a, S: BareMetalKVDb> ReadOnlyHigherDb for HistoricalDb<'a, S> {
+    fn get_stem_meta(&self, stem_key: [u8; 31]) -> Option<StemMeta> {
+        self.fetch_at_epoch(STEM_TABLE_MARKER, &stem_key)
+            .map(|bytes| StemMeta::from_bytes(&bytes).expect(CORRUPT_ARCHIVE))
+    }
+
+    fn get_branch_meta(&self, key: &[u8]) -> Option<BranchMeta> {
+        self.get_branch_child_at(key)
+            .and_then(|child| child.branch())
+    }
+
+    fn get_branch_children(&self, branch_id: &[u8]) -> Vec<(u8, BranchChild)> {
+        // The children have one more byte in their path than the branch node
+        let mut prefix = vec![
+            VERSIONED_NODE_MARKER,
+            BRANCH_TABLE_MARKER,
+            branch_id.len() as u8 + 1,
+        ];
+        prefix.extend_from_slice(branch_id);
+
+        self.children_at_epoch(prefix)
+            .into_iter()
+            .map(|(index, bytes)| {
+                (
+                    index,
+                    BranchChild::from_bytes(&bytes).expect(CORRUPT_ARCHIVE),
+                )
+            })
+            .collect()
+    }
+
+    fn get_branch_child(&self, branch_id: &[u8], index: u8) -> Option<BranchChild> {
+        let mut child_id = branch_id.to_vec();
+        child_id.push(index);
+        self.get_branch_child_at(&child_id)
+    }
+
+    fn get_stem_children(&self, stem_key: [u8; 31]) -> Vec<(u8, [u8; 32])> {
+        self.children_at_epoch(node_prefix(LEAF_TABLE_MARKER, &stem_key))
+            .into_iter()
+            .map(|(index, bytes)| (index, bytes.try_into().expect(CORRUPT_ARCHIVE)))
+            .collect()
+    }
+
+    fn get_leaf(&self, key: [u8; 32]) -> Option<[u8; 32]> {
+        self.fetch_at_epoch(LEAF_TABLE_MARKER, &key)
+            .map(|bytes| bytes.try_into().expect(CORRUPT_ARCHIVE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{archived_epochs, ARCHIVED_EPOCH_MARKER};
+    use crate::database::{test_db::KvStore, ReadOnlyHigherDb, VerkleDb};
+    use crate::errors::VerkleError;
+    use crate::{Key, TestConfig, Trie, TrieTrait};
+
+    fn key(first: u8, last: u8) -> Key {
+        let mut key = [0u8; 32];
+        key[0] = first;
+        key[1] = last;
+        key[31] = last;
+        key
+    }
+
+    #[test]
+    fn open_trie_at_previous_epochs() {
+        let mut db = VerkleDb::<KvStore>::open("").unwrap();
+        db.set_archive_epoch(1).unwrap();
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+
+        trie.insert((0..10).map(|i| (key(i, i), [i; 32])));
+        trie.flush_database();
+        let root_1 = trie.root_commitment();
+
+        trie.storage.set_archive_epoch(2).unwrap();
+        trie.insert((0..10).map(|i| (key(i, i), [i + 1; 32])));
+        trie.insert_single(key(5, 100), [100; 32]);
+        trie.delete(key(3, 3));
+        trie.flush_database();
+        let root_2 = trie.root_commitment();
+        assert_ne!(root_1, root_2);
+
+        let epoch_1 =
+            Trie::open_read_only(TestConfig::new(trie.storage.at_epoch(1).unwrap())).unwrap();
+        assert_eq!(epoch_1.root_commitment(), root_1);
+        assert_eq!(epoch_1.get(key(3, 3)), Some([3; 32]));
+        assert_eq!(epoch_1.get(key(5, 5)), Some([5; 32]));
+        assert_eq!(epoch_1.get(key(5, 100)), None);
+
+        // Proofs against a previous epoch verify against the root at that epoch
+        let keys = vec![key(3, 3), key(5, 5), key(5, 100)];
+        let values = keys.iter().map(|key| epoch_1.get(*key)).collect();
+        let proof = epoch_1
+            .create_verkle_proof(keys.clone().into_iter())
+            .unwrap();
+        assert!(proof.check(keys, values, root_1).is_ok());
+
+        let epoch_2 =
+            Trie::open_read_only(TestConfig::new(trie.storage.at_epoch(2).unwrap())).unwrap();
+        assert_eq!(epoch_2.root_commitment(), root_2);
+        assert_eq!(epoch_2.get(key(3, 3)), None);
+        assert_eq!(epoch_2.get(key(5, 100)), Some([100; 32]));
+
+        // Epochs which were never flushed cannot be read, including those before the first epoch
+        for epoch in [0, 5] {
+            assert!(matches!(
+                trie.storage.at_epoch(epoch),
+                Err(VerkleError::EpochNotArchived(got)) if got == epoch
+            ));
+        }
+    }
+
+    #[test]
+    fn archived_epochs_cannot_be_rewritten() {
+        let mut db = VerkleDb::<KvStore>::open("").unwrap();
+        db.set_archive_epoch(7).unwrap();
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+        trie.insert_single(key(1, 1), [1; 32]);
+        trie.flush_database();
+
+        let result = trie.storage.set_archive_epoch(6);
+        assert!(matches!(
+            result,
+            Err(VerkleError::StaleEpoch {
+                latest: 7,
+                epoch: 6
+            })
+        ));
+        // The same epoch can be flushed again
+        assert!(trie.storage.set_archive_epoch(7).is_ok());
+    }
+
+    #[test]
+    fn prune_keeps_retained_epochs() {
+        let mut db = VerkleDb::<KvStore>::open("").unwrap();
+        db.set_archive_epoch(1).unwrap();
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+
+        let mut roots = Vec::new();
+        trie.insert((0..10).map(|i| (key(i, i), [i; 32])));
+        trie.flush_database();
+        roots.push(trie.root_commitment());
+
+        trie.storage.set_archive_epoch(2).unwrap();
+        trie.insert((0..10).map(|i| (key(i, i), [i + 1; 32])));
+        trie.flush_database();
+        roots.push(trie.root_commitment());
+
+        trie.storage.set_archive_epoch(3).unwrap();
+        trie.delete(key(3, 3));
+        trie.insert_single(key(4, 100), [100; 32]);
+        trie.flush_database();
+        roots.push(trie.root_commitment());
+
+        let num_records = trie.storage.storage.0.len();
+        let num_deleted = trie.storage.prune([1, 3]);
+        assert!(num_deleted > 0);
+        assert!(trie.storage.storage.0.len() < num_records);
+
+        let epoch_1 =
+            Trie::open_read_only(TestConfig::new(trie.storage.at_epoch(1).unwrap())).unwrap();
+        assert_eq!(epoch_1.root_commitment(), roots[0]);
+        assert_eq!(epoch_1.get(key(3, 3)), Some([3; 32]));
+        assert_eq!(epoch_1.iter().count(), 10);
+
+        let epoch_3 =
+            Trie::open_read_only(TestConfig::new(trie.storage.at_epoch(3).unwrap())).unwrap();
+        assert_eq!(epoch_3.root_commitment(), roots[2]);
+        // The removal must be kept, otherwise the value at epoch 1 would be read
+        assert_eq!(epoch_3.get(key(3, 3)), None);
+        assert_eq!(epoch_3.get(key(4, 4)), Some([5; 32]));
+        assert_eq!(epoch_3.iter().count(), 10);
+
+        // The pruned epoch can no longer be read
+        assert!(matches!(
+            trie.storage.at_epoch(2),
+            Err(VerkleError::EpochNotArchived(2))
+        ));
+
+        // The latest state is not affected by pruning
+        assert_eq!(trie.get(key(4, 100)), Some([100; 32]));
+
+        // Pruning again with the same epochs does not delete anything else
+        assert_eq!(trie.storage.prune([1, 3]), 0);
+    }
+
+    #[test]
+    fn read_children_written_at_different_epochs() {
+        let mut db = VerkleDb::<KvStore>::open("").unwrap();
+        db.set_archive_epoch(1).unwrap();
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+
+        let stem = [9u8; 31];
+        let leaf = |suffix: u8| {
+            let mut key = [9u8; 32];
+            key[31] = suffix;
+            key
+        };
+
+        // Each epoch writes a different set of children, and removes some which were written before it
+        let mut expected = Vec::new();
+        for epoch in 1..=4u8 {
+            trie.storage.set_archive_epoch(epoch as u64).unwrap();
+            let suffixes = (0..=255u8).filter(|suffix| suffix % 4 == epoch - 1);
+            trie.insert(suffixes.map(|suffix| (leaf(suffix), [epoch; 32])));
+            trie.insert_single(key(epoch * 40, epoch), [epoch; 32]);
+            if epoch > 1 {
+                trie.delete(leaf(epoch * 8));
+                trie.delete(key((epoch - 1) * 40, epoch - 1));
+            }
+            trie.flush_database();
+
+            // The latest state does not return the children in order
+            let mut branch_children: Vec<_> = trie
+                .storage
+                .get_branch_children(&[])
+                .into_iter()
+                .map(|(index, _)| index)
+                .collect();
+            branch_children.sort();
+            let mut stem_children = trie.storage.get_stem_children(stem);
+            stem_children.sort();
+            expected.push((branch_children, stem_children));
+        }
+
+        for (epoch, (branch_children, stem_children)) in (1..=4).zip(expected) {
+            let view = trie.storage.at_epoch(epoch).unwrap();
+            let got: Vec<_> = view
+                .get_branch_children(&[])
+                .into_iter()
+                .map(|(index, _)| index)
+                .collect();
+            assert_eq!(got, branch_children);
+            assert_eq!(view.get_stem_children(stem), stem_children);
+        }
+
+        // Each archived epoch is stored under its own key
+        let storage = &trie.storage.storage;
+        assert_eq!(archived_epochs(&**storage), vec![1, 2, 3, 4]);
+        let num_epoch_keys = storage
+            .0
+            .keys()
+            .filter(|key| key[0] == ARCHIVED_EPOCH_MARKER)
+            .count();
+        assert_eq!(num_epoch_keys, 4);
+    }
+}
@@ -0,0 +1,246 @@
+use super::{BranchChild, StemMeta};
+use std::collections::{BTreeMap, HashMap};
+
+// The cache of a `VerkleDb` has two layers:
+// - The pinned layers hold every node at or above a fixed depth. These are the most accessed
+//   nodes in the trie, and since the layers are complete, listing the children of a branch node
+//   in them never needs to read from the storage.
+// - The LRU holds nodes below the pinned layers which were read from the storage, up to a byte budget.
+//   When the budget is exceeded, the least recently read nodes are evicted.
+//
+// Either layer can be disabled, the default is to pin the top four layers, without an LRU.
+
+// All nodes at this level or above are pinned by the default policy
+pub const DEFAULT_CACHE_DEPTH: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CachePolicy {
+    // All nodes at this depth or above are kept in memory, `None` disables the pinned layers
+    pub pinned_depth: Option<u8>,
+    // The maximum number of bytes used by the LRU, zero disables it
+    pub lru_bytes: usize,
+}
+
+impl CachePolicy {
+    pub fn fixed_depth(depth: u8) -> Self {
+        CachePolicy {
+            pinned_depth: Some(depth),
+            lru_bytes: 0,
+        }
+    }
+
+    pub fn lru(max_bytes: usize) -> Self {
+        CachePolicy {
+            pinned_depth: None,
+            lru_bytes: max_bytes,
+        }
+    }
+
+    pub fn fixed_depth_and_lru(depth: u8, max_bytes: usize) -> Self {
+        CachePolicy {
+            pinned_depth: Some(depth),
+            lru_bytes: max_bytes,
+        }
+    }
+
+    // Returns true if a leaf or a stem at this depth is pinned
+    pub(crate) fn pins_depth(&self, depth: u8) -> bool {
+        match self.pinned_depth {
+            Some(pinned_depth) => depth <= pinned_depth,
+            None => false,
+        }
+    }
+
+    // Returns true if all of the children of this branch node are pinned
+    pub(crate) fn pins_branch(&self, branch_id: &[u8]) -> bool {
+        match self.pinned_depth {
+            Some(pinned_depth) => branch_id.len() <= pinned_depth as usize,
+            None => false,
+        }
+    }
+
+    // Returns true if the child of a branch node at this path is pinned.
+    // The children of every pinned branch node are pinned, so that
+    // `get_branch_children` can be answered from the cache alone.
+    //
+    // This depends on the path rather than the depth passed in by the trie, since a node
+    // can move to a different depth without being rewritten, ie when a stem is pushed down.
+    pub(crate) fn pins_branch_child(&self, branch_child_id: &[u8]) -> bool {
+        match self.pinned_depth {
+            Some(pinned_depth) => branch_child_id.len() <= pinned_depth as usize + 1,
+            None => false,
+        }
+    }
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        CachePolicy::fixed_depth(DEFAULT_CACHE_DEPTH)
+    }
+}
+
+// Reads which were answered from the cache, and reads which needed the storage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    // The number of nodes and the number of bytes held by the LRU
+    pub lru_entries: usize,
+    pub lru_bytes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum NodeKey {
+    Leaf([u8; 32]),
+    Stem([u8; 31]),
+    BranchChild(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum CachedNode {
+    Leaf([u8; 32]),
+    // Stem metadata holds several points, so it is boxed to keep the other entries small
+    Stem(Box<StemMeta>),
+    BranchChild(BranchChild),
+}
+
+impl CachedNode {
+    pub(crate) fn leaf(self) -> Option<[u8; 32]> {
+        match self {
+            CachedNode::Leaf(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn stem(self) -> Option<StemMeta> {
+        match self {
+            CachedNode::Stem(meta) => Some(*meta),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn branch_child(self) -> Option<BranchChild> {
+        match self {
+            CachedNode::BranchChild(child) => Some(child),
+            _ => None,
+        }
+    }
+}
+
+// The approximate number of bytes used by an entry, including the recency index
+fn entry_size(key: &NodeKey) -> usize {
+    let key_size = match key {
+        NodeKey::Leaf(_) => 32,
+        NodeKey::Stem(_) => 31 + std::mem::size_of::<StemMeta>(),
+        NodeKey::BranchChild(id) => id.len(),
+    };
+    // The key is stored twice, once in the entries and once in the recency index.
+    // Stems also count their boxed metadata
+    2 * (std::mem::size_of::<NodeKey>() + key_size)
+        + std::mem::size_of::<CachedNode>()
+        + 2 * std::mem::size_of::<u64>()
+}
+
+// A least recently used cache, which is bounded by the number of bytes that its entries use
+#[derive(Debug, Default)]
+pub(crate) struct LruCache {
+    max_bytes: usize,
+    used_bytes: usize,
+    // Incremented on every access, the entry with the smallest tick is the least recently used
+    tick: u64,
+    entries: HashMap<NodeKey, (CachedNode, u64)>,
+    recency: BTreeMap<u64, NodeKey>,
+}
+
+impl LruCache {
+    pub(crate) fn new(max_bytes: usize) -> Self {
+        LruCache {
+            max_bytes,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+
+    // Returns the cached node, marking it as the most recently used
+    pub(crate) fn get(&mut self, key: &NodeKey) -> Option<CachedNode> {
+        self.tick += 1;
+        let (node, tick) = self.entries.get_mut(key)?;
+        let previous_tick = std::mem::replace(tick, self.tick);
+        let node = node.clone();
+
+        let key = self.recency.remove(&previous_tick)?;
+        self.recency.insert(self.tick, key);
+        Some(node)
+    }
+
+    pub(crate) fn insert(&mut self, key: NodeKey, node: CachedNode) {
+        let size = entry_size(&key);
+        if size > self.max_bytes {
+            return;
+        }
+        self.remove(&key);
+        while self.used_bytes + size > self.max_bytes {
+            self.evict_least_recent();
+        }
+
+        self.tick += 1;
+        self.used_bytes += size;
+        self.recency.insert(self.tick, key.clone());
+        self.entries.insert(key, (node, self.tick));
+    }
+
+    pub(crate) fn remove(&mut self, key: &NodeKey) {
+        if let Some((_, tick)) = self.entries.remove(key) {
+            self.recency.remove(&tick);
+            self.used_bytes -= entry_size(key);
+        }
+    }
+
+    fn evict_least_recent(&mut self) {
+        let key = match self.recency.iter().next() {
+            Some((_, key)) => key.clone(),
+            None => return,
+        };
+        self.remove(&key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{entry_size, CachedNode, LruCache, NodeKey};
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let size = entry_size(&NodeKey::Leaf([0; 32]));
+        let mut lru = LruCache::new(2 * size);
+
+        lru.insert(NodeKey::Leaf([1; 32]), CachedNode::Leaf([1; 32]));
+        lru.insert(NodeKey::Leaf([2; 32]), CachedNode::Leaf([2; 32]));
+        // Reading the first leaf makes the second leaf the least recently used
+        assert!(lru.get(&NodeKey::Leaf([1; 32])).is_some());
+
+        lru.insert(NodeKey::Leaf([3; 32]), CachedNode::Leaf([3; 32]));
+        assert_eq!(lru.len(), 2);
+        assert_eq!(lru.used_bytes(), 2 * size);
+        assert!(lru.get(&NodeKey::Leaf([2; 32])).is_none());
+        assert_eq!(
+            lru.get(&NodeKey::Leaf([1; 32])).and_then(CachedNode::leaf),
+            Some([1; 32])
+        );
+        assert_eq!(
+            lru.get(&NodeKey::Leaf([3; 32])).and_then(CachedNode::leaf),
+            Some([3; 32])
+        );
+
+        lru.remove(&NodeKey::Leaf([3; 32]));
+        assert_eq!(lru.len(), 1);
+        assert_eq!(lru.used_bytes(), size);
+    }
+}
@@ -1,18 +1,24 @@
 use super::{
-    generic::GenericBatchDB, memory_db::MemoryDb, BranchChild, BranchMeta, Flush, ReadOnlyHigherDb,
-    StemMeta, WriteOnlyHigherDb,
+    archive::{self, ArchivedNode, HistoricalDb},
+    cache::{CachePolicy, CacheStats, CachedNode, LruCache, NodeKey},
+    generic::{self, GenericBatchDB, BRANCH_TABLE_MARKER, LEAF_TABLE_MARKER, STEM_TABLE_MARKER},
+    memory_db::MemoryDb,
+    BranchChild, BranchMeta, Flush, ReadOnlyHigherDb, StemMeta, WriteOnlyHigherDb,
 };
+use crate::committer::{test::TestCommitter, Committer};
 use crate::database::generic::GenericBatchWriter;
-use std::collections::HashMap;
+use crate::errors::VerkleError;
+use crate::group_to_field;
+use crate::trace::timed_span;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 use verkle_db::{BareMetalDiskDb, BareMetalKVDb, BatchDB, BatchWriter};
 
 // A convenient structure that allows the end user to just implement BatchDb and BareMetalDiskDb
 // Then the methods needed for the Trie are auto implemented. In  particular, ReadOnlyHigherDb and WriteOnlyHigherDb
 // are implemented
 
-// All nodes at this level or above will be cached in memory
-const CACHE_DEPTH: u8 = 4;
-
 // A wrapper database for those that just want to implement the permanent storage
 pub struct VerkleDb<Storage> {
     // The underlying key value database
@@ -21,31 +27,307 @@ pub struct VerkleDb<Storage> {
     // This stores the key-value pairs that we need to insert into the storage
     // This is flushed after every batch insert
     pub batch: MemoryDb,
-    // This stores the top 3 layers of the trie, since these are the most accessed
-    // in the trie on average
+    // This stores the top layers of the trie, since these are the most accessed
+    // in the trie on average. The number of layers is set by the cache policy
     pub cache: MemoryDb,
+    // Deeper nodes which were read from the storage, bounded by the byte budget of the cache policy.
+    // Reads only have shared access to the database, so the LRU is behind a lock
+    lru: Mutex<LruCache>,
+    cache_policy: CachePolicy,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    // This stores the keys that have been removed since the last flush
+    // They hide any stale value in the storage and are deleted from it on flush
+    pub removed: RemovedKeys,
+    // When set, the database is in archive mode and every flush also stores
+    // a versioned copy of the flushed nodes at this epoch
+    archive_epoch: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RemovedKeys {
+    pub leaves: HashSet<[u8; 32]>,
+    pub stems: HashSet<[u8; 31]>,
+    pub branch_children: HashSet<Vec<u8>>,
+}
+
+impl RemovedKeys {
+    pub fn num_items(&self) -> usize {
+        self.leaves.len() + self.stems.len() + self.branch_children.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.leaves.clear();
+        self.stems.clear();
+        self.branch_children.clear();
+    }
 }
 
-impl<S: BareMetalDiskDb> BareMetalDiskDb for VerkleDb<S> {
+impl<S: BareMetalDiskDb + BareMetalKVDb + BatchDB> BareMetalDiskDb for VerkleDb<S> {
+    // Panics if the database already has a trie which cannot be loaded.
+    // Use `open`, `with_cache_policy` or `from_storage` instead, which return the error
     fn from_path<P: AsRef<std::path::Path>>(path: P) -> Self {
-        VerkleDb {
-            storage: GenericBatchDB::from_path(path),
+        VerkleDb::open(path).expect("could not load the cache from an existing database")
+    }
+
+    const DEFAULT_PATH: &'static str = S::DEFAULT_PATH;
+}
+
+impl<S: BareMetalDiskDb + BareMetalKVDb + BatchDB> VerkleDb<S> {
+    // Opens the database at `path` with the default cache policy.
+    // Returns an error if it already has a trie which cannot be loaded
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, VerkleError> {
+        VerkleDb::with_cache_policy(path, CachePolicy::default())
+    }
+
+    pub fn with_cache_policy<P: AsRef<std::path::Path>>(
+        path: P,
+        cache_policy: CachePolicy,
+    ) -> Result<Self, VerkleError> {
+        VerkleDb::from_storage(S::from_path(path), cache_policy)
+    }
+}
+
+impl<S: BareMetalKVDb + BatchDB> VerkleDb<S> {
+    // Opens a database over storage which may already have a trie in it, migrating it if it
+    // was written with an older format. Returns an error if the format is newer than this library,
+    // or if the top layers of the trie cannot be loaded into the cache
+    pub fn from_storage(mut storage: S, cache_policy: CachePolicy) -> Result<Self, VerkleError> {
+        generic::migrate(&mut storage)?;
+        let mut db = VerkleDb {
+            storage: GenericBatchDB::new(storage),
 
             batch: MemoryDb::new(),
             cache: MemoryDb::new(),
+            lru: Mutex::new(LruCache::new(cache_policy.lru_bytes)),
+            cache_policy,
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            removed: RemovedKeys::default(),
+            archive_epoch: None,
+        };
+        // If the database already has a trie, the cache needs to hold its top layers
+        db.warm_cache()?;
+        Ok(db)
+    }
+}
+
+impl<S: BareMetalKVDb> VerkleDb<S> {
+    // Reads answer from the cache for all nodes in the pinned layers, without checking the storage.
+    // So when a database is reopened, the top layers of the persisted trie must be loaded into the cache:
+    // - every branch node down to the pinned depth, along with all of their children
+    // - every stem which is a child of one of those branch nodes, along with all of its leaves
+    //
+    // Returns the number of nodes which were loaded
+    pub fn warm_cache(&mut self) -> Result<usize, VerkleError> {
+        self.cache = MemoryDb::new();
+        let pinned_depth = match self.cache_policy.pinned_depth {
+            Some(depth) => depth,
+            // Every read goes through the LRU, which is filled as nodes are read
+            None => return Ok(0),
+        };
+
+        let root = match self.storage.get_branch_meta(&[]) {
+            Some(root) => root,
+            // This is a fresh database, so there is nothing to load
+            None => return Ok(0),
+        };
+        let root_commitment = root.commitment;
+        self.cache.insert_branch(vec![], root, 0);
+        let mut num_loaded = 1;
+
+        let mut branches = vec![Vec::new()];
+        while let Some(branch_id) = branches.pop() {
+            let depth = branch_id.len() as u8;
+            for (index, child) in self.storage.get_branch_children(&branch_id) {
+                let mut child_id = branch_id.clone();
+                child_id.push(index);
+                num_loaded += 1;
+
+                match child {
+                    BranchChild::Branch(meta) => {
+                        let child_depth = child_id.len() as u8;
+                        self.cache
+                            .insert_branch(child_id.clone(), meta, child_depth);
+                        if child_depth <= pinned_depth {
+                            branches.push(child_id);
+                        }
+                    }
+                    BranchChild::Stem(stem_id) => {
+                        self.cache
+                            .add_stem_as_branch_child(child_id, stem_id, depth);
+                        let stem_meta = self
+                            .storage
+                            .get_stem_meta(stem_id)
+                            .ok_or(VerkleError::InconsistentCache)?;
+                        self.cache.insert_stem(stem_id, stem_meta, depth);
+                        num_loaded += 1;
+
+                        for (suffix, value) in self.storage.get_stem_children(stem_id) {
+                            let mut leaf_key = [0u8; 32];
+                            leaf_key[0..31].copy_from_slice(&stem_id);
+                            leaf_key[31] = suffix;
+                            self.cache.insert_leaf(leaf_key, value, depth);
+                            num_loaded += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        // The root must commit to the children which were loaded, otherwise the cache would
+        // answer with nodes which are not the ones in the trie
+        let root_children = self
+            .cache
+            .get_branch_children(&[])
+            .into_iter()
+            .map(|(index, child)| {
+                let commitment = match child {
+                    BranchChild::Branch(meta) => meta.commitment,
+                    BranchChild::Stem(stem_id) => {
+                        let stem_meta = self
+                            .cache
+                            .get_stem_meta(stem_id)
+                            .ok_or(VerkleError::InconsistentCache)?;
+                        stem_meta.stem_commitment
+                    }
+                };
+                Ok((group_to_field(&commitment), index as usize))
+            })
+            .collect::<Result<Vec<_>, VerkleError>>()?;
+        if TestCommitter.commit_sparse(root_children) != root_commitment {
+            return Err(VerkleError::InconsistentCache);
+        }
+
+        Ok(num_loaded)
+    }
+
+    // Puts the database into archive mode, all subsequent flushes are archived at `epoch`
+    // until this is called again with a later epoch.
+    //
+    // Nodes which were flushed before archive mode was enabled are not versioned,
+    // so archive mode should be enabled before anything is inserted into the trie
+    pub fn set_archive_epoch(&mut self, epoch: u64) -> Result<(), VerkleError> {
+        if let Some(latest) = self.archive_epoch {
+            if epoch < latest {
+                return Err(VerkleError::StaleEpoch { latest, epoch });
+            }
         }
+        archive::check_epoch(&*self.storage, epoch)?;
+        self.archive_epoch = Some(epoch);
+        Ok(())
     }
 
-    const DEFAULT_PATH: &'static str = S::DEFAULT_PATH;
+    pub fn archive_epoch(&self) -> Option<u64> {
+        self.archive_epoch
+    }
+
+    pub fn cache_policy(&self) -> CachePolicy {
+        self.cache_policy
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        let lru = self.lru.lock().unwrap();
+        CacheStats {
+            hits: self.cache_hits.load(Ordering::Relaxed),
+            misses: self.cache_misses.load(Ordering::Relaxed),
+            lru_entries: lru.len(),
+            lru_bytes: lru.used_bytes(),
+        }
+    }
+
+    fn record_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Reads a node below the pinned layers, first from the LRU and then from the storage.
+    // Nodes which are read from the storage are added to the LRU
+    fn read_through(
+        &self,
+        key: NodeKey,
+        fetch: impl FnOnce() -> Option<CachedNode>,
+    ) -> Option<CachedNode> {
+        if self.cache_policy.lru_bytes == 0 {
+            self.cache_misses.fetch_add(1, Ordering::Relaxed);
+            return fetch();
+        }
+        if let Some(node) = self.lru.lock().unwrap().get(&key) {
+            self.record_hit();
+            return Some(node);
+        }
+
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+        let node = fetch()?;
+        self.lru.lock().unwrap().insert(key, node.clone());
+        Some(node)
+    }
+
+    // Returns a read-only view of the trie as it was when `epoch` was last flushed.
+    // The view can be used to open a `Trie` with `Trie::open_read_only`.
+    // Returns an error if the epoch was never archived, or it has been pruned
+    pub fn at_epoch(&self, epoch: u64) -> Result<HistoricalDb<'_, S>, VerkleError> {
+        HistoricalDb::new(&*self.storage, epoch)
+    }
+}
+
+// The nodes which will be written by the next flush, in their storage encoding
+fn archived_nodes<'a>(
+    batch: &'a MemoryDb,
+    removed: &'a RemovedKeys,
+) -> impl Iterator<Item = ArchivedNode> + 'a {
+    let leaves = batch.leaf_table.iter().map(|(key, value)| ArchivedNode {
+        table: LEAF_TABLE_MARKER,
+        key: key.to_vec(),
+        value: Some(value.to_vec()),
+    });
+    let stems = batch.stem_table.iter().map(|(key, meta)| ArchivedNode {
+        table: STEM_TABLE_MARKER,
+        key: key.to_vec(),
+        value: Some(meta.to_bytes()),
+    });
+    let branch_children = batch
+        .branch_table
+        .iter()
+        .map(|(branch_id, b_child)| ArchivedNode {
+            table: BRANCH_TABLE_MARKER,
+            key: branch_id.clone(),
+            value: Some(b_child.to_bytes()),
+        });
+
+    let removed_leaves = removed.leaves.iter().map(|key| ArchivedNode {
+        table: LEAF_TABLE_MARKER,
+        key: key.to_vec(),
+        value: None,
+    });
+    let removed_stems = removed.stems.iter().map(|key| ArchivedNode {
+        table: STEM_TABLE_MARKER,
+        key: key.to_vec(),
+        value: None,
+    });
+    let removed_branch_children = removed.branch_children.iter().map(|key| ArchivedNode {
+        table: BRANCH_TABLE_MARKER,
+        key: key.clone(),
+        value: None,
+    });
+
+    leaves
+        .chain(stems)
+        .chain(branch_children)
+        .chain(removed_leaves)
+        .chain(removed_stems)
+        .chain(removed_branch_children)
 }
 
-impl<S: BatchDB> Flush for VerkleDb<S> {
+impl<S: BatchDB + BareMetalKVDb> Flush for VerkleDb<S> {
     // flush the batch to the storage
     fn flush(&mut self) {
         let writer = S::BatchWrite::new();
         let mut w = GenericBatchWriter { inner: writer };
 
-        let now = std::time::Instant::now();
+        let _span = timed_span!(
+            "flush",
+            num_items = self.batch.num_items() + self.removed.num_items()
+        );
 
         for (key, value) in self.batch.leaf_table.iter() {
             w.insert_leaf(*key, *value, 0);
@@ -67,16 +349,27 @@ impl<S: BatchDB> Flush for VerkleDb<S> {
             };
         }
 
-        let num_items = self.batch.num_items();
-        println!(
-            "write to batch time: {}, item count : {}",
-            now.elapsed().as_millis(),
-            num_items
-        );
+        for key in self.removed.leaves.iter() {
+            w.remove_leaf(*key);
+        }
+
+        for key in self.removed.stems.iter() {
+            w.remove_stem(*key);
+        }
+
+        for branch_id in self.removed.branch_children.iter() {
+            w.remove_branch_child(branch_id.clone());
+        }
+
+        if let Some(epoch) = self.archive_epoch {
+            let nodes = archived_nodes(&self.batch, &self.removed);
+            archive::write_versions(&mut w.inner, epoch, nodes);
+        }
 
         self.storage.flush(w.inner);
 
         self.batch.clear();
+        self.removed.clear();
     }
 }
 
@@ -84,59 +377,103 @@ impl<S: BareMetalKVDb> ReadOnlyHigherDb for VerkleDb<S> {
     fn get_leaf(&self, key: [u8; 32]) -> Option<[u8; 32]> {
         // First try to get it from cache
         if let Some(val) = self.cache.get_leaf(key) {
+            self.record_hit();
             return Some(val);
         }
         // Now try to get it from batch
         if let Some(val) = self.batch.get_leaf(key) {
             return Some(val);
         }
-        // Now try the disk
-        self.storage.get_leaf(key)
+        // If it was removed, then the value on disk is stale
+        if self.removed.leaves.contains(&key) {
+            return None;
+        }
+        // Now try the LRU and then the disk
+        self.read_through(NodeKey::Leaf(key), || {
+            self.storage.get_leaf(key).map(CachedNode::Leaf)
+        })
+        .and_then(CachedNode::leaf)
     }
 
     fn get_stem_meta(&self, stem_key: [u8; 31]) -> Option<StemMeta> {
         // First try to get it from cache
         if let Some(val) = self.cache.get_stem_meta(stem_key) {
+            self.record_hit();
             return Some(val);
         }
         // Now try to get it from batch
         if let Some(val) = self.batch.get_stem_meta(stem_key) {
             return Some(val);
         }
-        // Now try the disk
-        self.storage.get_stem_meta(stem_key)
+        // If it was removed, then the value on disk is stale
+        if self.removed.stems.contains(&stem_key) {
+            return None;
+        }
+        // Now try the LRU and then the disk
+        self.read_through(NodeKey::Stem(stem_key), || {
+            self.storage
+                .get_stem_meta(stem_key)
+                .map(|meta| CachedNode::Stem(Box::new(meta)))
+        })
+        .and_then(CachedNode::stem)
     }
 
     fn get_branch_meta(&self, key: &[u8]) -> Option<BranchMeta> {
         // First try to get it from cache
         if let Some(val) = self.cache.get_branch_meta(key) {
+            self.record_hit();
             return Some(val);
         }
         // Now try to get it from batch
         if let Some(val) = self.batch.get_branch_meta(key) {
             return Some(val);
         }
-        // Now try the disk
-        self.storage.get_branch_meta(key)
+        // If it was removed, then the value on disk is stale
+        if self.removed.branch_children.contains(key) {
+            return None;
+        }
+        // Now try the LRU and then the disk
+        self.read_through(NodeKey::BranchChild(key.to_vec()), || {
+            let meta = self.storage.get_branch_meta(key)?;
+            Some(CachedNode::BranchChild(BranchChild::Branch(meta)))
+        })
+        .and_then(CachedNode::branch_child)
+        .and_then(|child| match child {
+            BranchChild::Branch(meta) => Some(meta),
+            BranchChild::Stem(_) => None,
+        })
     }
 
     fn get_branch_child(&self, branch_id: &[u8], index: u8) -> Option<BranchChild> {
         // First try to get it from cache
         if let Some(val) = self.cache.get_branch_child(branch_id, index) {
+            self.record_hit();
             return Some(val);
         }
         // Now try to get it from batch
         if let Some(val) = self.batch.get_branch_child(branch_id, index) {
             return Some(val);
         }
-        // Now try the disk
-        self.storage.get_branch_child(branch_id, index)
+        // If it was removed, then the value on disk is stale
+        let mut child_id = branch_id.to_vec();
+        child_id.push(index);
+        if self.removed.branch_children.contains(&child_id) {
+            return None;
+        }
+        // Now try the LRU and then the disk
+        self.read_through(NodeKey::BranchChild(child_id), || {
+            self.storage
+                .get_branch_child(branch_id, index)
+                .map(CachedNode::BranchChild)
+        })
+        .and_then(CachedNode::branch_child)
     }
 
     fn get_branch_children(&self, branch_id: &[u8]) -> Vec<(u8, BranchChild)> {
-        // Check the depth. If the branch is at CACHE_DEPTH or lower, then it will be in the cache
-        // TODO this assumes that the cache is populated on startup from disk
-        if branch_id.len() as u8 <= CACHE_DEPTH {
+        // Check the depth. If the branch is in the pinned layers, then all of its children will be in the cache
+        // This relies on the cache being loaded from storage when the database is opened, see `warm_cache`
+        if self.cache_policy.pins_branch(branch_id) {
+            self.record_hit();
             return self.cache.get_branch_children(branch_id);
         }
         // First get the children from storage
@@ -144,7 +481,11 @@ impl<S: BareMetalKVDb> ReadOnlyHigherDb for VerkleDb<S> {
             .storage
             .get_branch_children(branch_id)
             .into_iter()
-            .map(|(index, val)| (index, val))
+            .filter(|(index, _)| {
+                let mut child_id = branch_id.to_vec();
+                child_id.push(*index);
+                !self.removed.branch_children.contains(&child_id)
+            })
             .collect();
         //
         // Then get the children from the batch
@@ -162,21 +503,21 @@ impl<S: BareMetalKVDb> ReadOnlyHigherDb for VerkleDb<S> {
     }
 
     fn get_stem_children(&self, stem_key: [u8; 31]) -> Vec<(u8, [u8; 32])> {
-        // Stems don't have a depth, however the children for all stem will always be on the same depth
-        // If we get any children for the stem in the cache storage, then this means we have collected all of them
-        // TODO this assumes that the cache is populated on startup from disk
-        let children = self.cache.get_stem_children(stem_key);
-        if !children.is_empty() {
-            return children;
-        }
-
+        // The leaves of a stem are not always cached together, since a stem can move below the cache depth,
+        // so they are always read from the storage, which is a single range read.
+        //
         // It's possible that they are in disk storage and that batch storage has some recent updates
         // First get the children from storage
         let mut children: HashMap<_, _> = self
             .storage
             .get_stem_children(stem_key)
             .into_iter()
-            .map(|(index, val)| (index, val))
+            .filter(|(index, _)| {
+                let mut leaf_key = [0u8; 32];
+                leaf_key[0..31].copy_from_slice(&stem_key);
+                leaf_key[31] = *index;
+                !self.removed.leaves.contains(&leaf_key)
+            })
             .collect();
         //
         // Then get the children from the batch
@@ -194,19 +535,37 @@ impl<S: BareMetalKVDb> ReadOnlyHigherDb for VerkleDb<S> {
     }
 }
 
-// Always save in the permanent storage and only save in the memorydb if the depth is <= cache depth
+impl<S> VerkleDb<S> {
+    // Written nodes are served from the batch until the next flush, so the LRU entry is dropped
+    // rather than updated. It is read back from the storage once the write has been flushed
+    fn invalidate(&mut self, key: &NodeKey) {
+        self.lru.get_mut().unwrap().remove(key);
+    }
+}
+
+// Always save in the permanent storage and only save in the memorydb if the node is pinned by the cache policy
+// Leaves and stems which are not pinned are evicted from the cache, since they may have been
+// pinned before they moved deeper into the trie
 impl<S> WriteOnlyHigherDb for VerkleDb<S> {
     fn insert_leaf(&mut self, key: [u8; 32], value: [u8; 32], depth: u8) -> Option<Vec<u8>> {
-        if depth <= CACHE_DEPTH {
+        if self.cache_policy.pins_depth(depth) {
             self.cache.insert_leaf(key, value, depth);
+        } else {
+            self.cache.remove_leaf(key);
         }
+        self.invalidate(&NodeKey::Leaf(key));
+        self.removed.leaves.remove(&key);
         self.batch.insert_leaf(key, value, depth)
     }
 
     fn insert_stem(&mut self, key: [u8; 31], meta: StemMeta, depth: u8) -> Option<StemMeta> {
-        if depth <= CACHE_DEPTH {
+        if self.cache_policy.pins_depth(depth) {
             self.cache.insert_stem(key, meta, depth);
+        } else {
+            self.cache.remove_stem(key);
         }
+        self.invalidate(&NodeKey::Stem(key));
+        self.removed.stems.remove(&key);
         self.batch.insert_stem(key, meta, depth)
     }
 
@@ -216,18 +575,259 @@ impl<S> WriteOnlyHigherDb for VerkleDb<S> {
         stem_id: [u8; 31],
         depth: u8,
     ) -> Option<BranchChild> {
-        if depth <= CACHE_DEPTH {
+        if self.cache_policy.pins_branch_child(&branch_child_id) {
             self.cache
                 .add_stem_as_branch_child(branch_child_id.clone(), stem_id, depth);
         }
+        self.invalidate(&NodeKey::BranchChild(branch_child_id.clone()));
+        self.removed.branch_children.remove(&branch_child_id);
         self.batch
             .add_stem_as_branch_child(branch_child_id, stem_id, depth)
     }
 
     fn insert_branch(&mut self, key: Vec<u8>, meta: BranchMeta, depth: u8) -> Option<BranchMeta> {
-        if depth <= CACHE_DEPTH {
+        if self.cache_policy.pins_branch_child(&key) {
             self.cache.insert_branch(key.clone(), meta, depth);
         }
+        self.invalidate(&NodeKey::BranchChild(key.clone()));
+        self.removed.branch_children.remove(&key);
         self.batch.insert_branch(key, meta, depth)
     }
+
+    // Removals are applied to the cache and the batch, and are recorded so that
+    // the storage is updated on the next flush
+    fn remove_leaf(&mut self, key: [u8; 32]) -> Option<[u8; 32]> {
+        self.invalidate(&NodeKey::Leaf(key));
+        let cached = self.cache.remove_leaf(key);
+        let batched = self.batch.remove_leaf(key);
+        self.removed.leaves.insert(key);
+        batched.or(cached)
+    }
+
+    fn remove_stem(&mut self, key: [u8; 31]) -> Option<StemMeta> {
+        self.invalidate(&NodeKey::Stem(key));
+        let cached = self.cache.remove_stem(key);
+        let batched = self.batch.remove_stem(key);
+        self.removed.stems.insert(key);
+        batched.or(cached)
+    }
+
+    fn remove_branch_child(&mut self, branch_child_id: Vec<u8>) -> Option<BranchChild> {
+        self.invalidate(&NodeKey::BranchChild(branch_child_id.clone()));
+        let cached = self.cache.remove_branch_child(branch_child_id.clone());
+        let batched = self.batch.remove_branch_child(branch_child_id.clone());
+        self.removed.branch_children.insert(branch_child_id);
+        batched.or(cached)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VerkleDb;
+    use crate::database::generic::{
+        BRANCH_TABLE_MARKER, FORMAT_VERSION, FORMAT_VERSION_KEY, STEM_TABLE_MARKER,
+    };
+    use crate::database::{memory_db::MemoryDb, test_db::KvStore, CachePolicy, ReadOnlyHigherDb};
+    use crate::{TestConfig, Trie, TrieTrait, VerkleError};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn warm_cache_after_reopen() {
+        let db = VerkleDb::<KvStore>::open("").unwrap();
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+
+        // Keys which share long prefixes, so that there are branch nodes below the cache depth
+        let keys: Vec<_> = (0..50u8)
+            .map(|i| {
+                let mut key = [i % 3; 32];
+                key[6] = i;
+                key[31] = i;
+                key
+            })
+            .collect();
+        trie.insert(keys.iter().map(|key| (*key, *key)));
+        trie.flush_database();
+        let root = trie.root_commitment();
+        let root_children = trie.storage.get_branch_children(&[]).len();
+
+        // Reopening the database starts with an empty cache
+        trie.storage.cache = MemoryDb::new();
+        assert!(trie.storage.get_branch_children(&[]).is_empty());
+
+        assert!(trie.storage.warm_cache().unwrap() > 0);
+        assert_eq!(trie.storage.get_branch_children(&[]).len(), root_children);
+        assert_eq!(trie.root_commitment(), root);
+        for key in &keys {
+            assert_eq!(trie.get(*key), Some(*key));
+        }
+
+        // The trie can still be updated after the cache has been loaded
+        let mut reference = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        reference.insert(keys.iter().map(|key| (*key, *key)));
+        let new_key = [1u8; 32];
+        trie.insert_single(new_key, new_key);
+        reference.insert_single(new_key, new_key);
+        assert_eq!(trie.root_commitment(), reference.root_commitment());
+    }
+
+    #[test]
+    fn open_existing_storage() {
+        let db = VerkleDb::<KvStore>::open("").unwrap();
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+        let keys: Vec<_> = (0..10u8).map(|i| [i; 32]).collect();
+        trie.insert(keys.iter().map(|key| (*key, *key)));
+        trie.flush_database();
+        let root = trie.root_commitment();
+        let stored = trie.storage.storage.0.clone();
+
+        let db = VerkleDb::from_storage(KvStore(stored.clone()), CachePolicy::default()).unwrap();
+        let reopened = Trie::new(TestConfig::new(db)).unwrap();
+        assert_eq!(reopened.root_commitment(), root);
+        assert_eq!(reopened.get(keys[3]), Some(keys[3]));
+
+        // A stem which is a child of the root, but whose metadata is missing from the storage
+        let mut corrupt = stored;
+        let mut stem_key = vec![STEM_TABLE_MARKER];
+        stem_key.extend_from_slice(&keys[3][0..31]);
+        assert!(corrupt.remove(&stem_key).is_some());
+        let result = VerkleDb::from_storage(KvStore(corrupt), CachePolicy::default());
+        assert!(matches!(result, Err(VerkleError::InconsistentCache)));
+    }
+
+    #[test]
+    fn root_must_commit_to_the_stored_children() {
+        let db = VerkleDb::<KvStore>::open("").unwrap();
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+        // Two keys under each of the first two children of the root, so both of them are branch nodes
+        let keys: Vec<_> = (0..4u8)
+            .map(|i| {
+                let mut key = [i; 32];
+                key[0] = i / 2;
+                key
+            })
+            .collect();
+        trie.insert(keys.iter().map(|key| (*key, *key)));
+        trie.flush_database();
+        let stored = trie.storage.storage.0.clone();
+
+        // Swapping the records of the two depth 1 branch nodes keeps them decodable,
+        // but the root no longer commits to them
+        let first = vec![BRANCH_TABLE_MARKER, 1, 0];
+        let second = vec![BRANCH_TABLE_MARKER, 1, 1];
+        let mut corrupt = stored.clone();
+        let first_record = corrupt[&first].clone();
+        let second_record = corrupt[&second].clone();
+        assert_ne!(first_record, second_record);
+        corrupt.insert(first, second_record);
+        corrupt.insert(second, first_record);
+
+        let result = VerkleDb::from_storage(KvStore(corrupt), CachePolicy::default());
+        assert!(matches!(result, Err(VerkleError::InconsistentCache)));
+        assert!(VerkleDb::from_storage(KvStore(stored), CachePolicy::default()).is_ok());
+    }
+
+    #[test]
+    fn migrate_branch_keys() {
+        let db = VerkleDb::<KvStore>::open("").unwrap();
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+        // Keys which share prefixes, so that there are branch nodes below the root
+        let keys: Vec<_> = (0..30u8)
+            .map(|i| {
+                let mut key = [i % 4; 32];
+                key[1] = i % 2;
+                key[31] = i;
+                key
+            })
+            .collect();
+        trie.insert(keys.iter().map(|key| (*key, *key)));
+        trie.flush_database();
+        let root = trie.root_commitment();
+        let current: BTreeMap<_, _> = trie.storage.storage.0.clone();
+
+        // The same trie, as it was stored before branch keys had the path length
+        let mut old = BTreeMap::new();
+        for (key, value) in &current {
+            match key[0] {
+                BRANCH_TABLE_MARKER => old.insert([&key[0..1], &key[2..]].concat(), value.clone()),
+                _ if key[..] == FORMAT_VERSION_KEY => None,
+                _ => old.insert(key.clone(), value.clone()),
+            };
+        }
+        assert_ne!(old, current);
+
+        let db = VerkleDb::from_storage(KvStore(old), CachePolicy::default()).unwrap();
+        assert_eq!(db.storage.0, current);
+        let reopened = Trie::new(TestConfig::new(db)).unwrap();
+        assert_eq!(reopened.root_commitment(), root);
+        assert_eq!(reopened.iter().count(), keys.len());
+
+        // A database written by a newer version is not opened
+        let mut newer = current;
+        newer.insert(FORMAT_VERSION_KEY.to_vec(), vec![FORMAT_VERSION + 1]);
+        let result = VerkleDb::from_storage(KvStore(newer), CachePolicy::default());
+        assert!(matches!(
+            result,
+            Err(VerkleError::UnsupportedStorageVersion(version)) if version == FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn lru_serves_deep_nodes() {
+        let keys: Vec<_> = (0..20u8)
+            .map(|i| {
+                let mut key = [0u8; 32];
+                key[8] = i;
+                key
+            })
+            .collect();
+        let mut reference = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        reference.insert(keys.iter().map(|key| (*key, *key)));
+
+        for policy in [
+            CachePolicy::lru(1 << 20),
+            CachePolicy::fixed_depth_and_lru(2, 1 << 20),
+        ] {
+            let db = VerkleDb::<KvStore>::with_cache_policy("", policy).unwrap();
+            let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+            trie.insert(keys.iter().map(|key| (*key, *key)));
+            trie.flush_database();
+            assert_eq!(trie.root_commitment(), reference.root_commitment());
+
+            // The first read of a deep leaf goes to the storage, the second is served from the LRU.
+            // The last key is used, since it was below the pinned layers when it was inserted
+            let key = keys[keys.len() - 1];
+            let before = trie.storage.cache_stats();
+            assert_eq!(trie.get(key), Some(key));
+            let after_first_read = trie.storage.cache_stats();
+            assert!(after_first_read.misses > before.misses);
+            assert!(after_first_read.lru_entries > 0);
+
+            assert_eq!(trie.get(key), Some(key));
+            let after_second_read = trie.storage.cache_stats();
+            assert_eq!(after_second_read.misses, after_first_read.misses);
+            assert!(after_second_read.hits > after_first_read.hits);
+
+            // Updates are not hidden by stale entries in the LRU
+            trie.insert_single(key, [1; 32]);
+            trie.flush_database();
+            assert_eq!(trie.get(key), Some([1; 32]));
+        }
+    }
+
+    #[test]
+    fn lru_respects_byte_budget() {
+        let budget = 4096;
+        let db = VerkleDb::<KvStore>::with_cache_policy("", CachePolicy::lru(budget)).unwrap();
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+        let keys: Vec<_> = (0..100u8).map(|i| [i; 32]).collect();
+        trie.insert(keys.iter().map(|key| (*key, *key)));
+        trie.flush_database();
+
+        for key in &keys {
+            assert_eq!(trie.get(*key), Some(*key));
+        }
+        let stats = trie.storage.cache_stats();
+        assert!(stats.lru_entries > 0);
+        assert!(stats.lru_bytes <= budget);
+    }
 }
@@ -1,4 +1,5 @@
 use super::{BranchChild, BranchMeta, ReadOnlyHigherDb, StemMeta, WriteOnlyHigherDb};
+use crate::errors::VerkleError;
 use verkle_db::{BareMetalDiskDb, BareMetalKVDb, BatchDB, BatchWriter};
 
 // The purpose of this file is to allows us to implement generic implementation for BatchWriter and BareMetalKVDb
@@ -9,6 +10,62 @@ pub(crate) const LEAF_TABLE_MARKER: u8 = 0;
 pub(crate) const STEM_TABLE_MARKER: u8 = 1;
 pub(crate) const BRANCH_TABLE_MARKER: u8 = 2;
 
+// The version of the layout of the records in storage.
+// Databases which do not have a version were written with version 0, where branch children
+// were keyed by BRANCH_TABLE_MARKER || path, without the path length
+pub(crate) const FORMAT_VERSION_KEY: [u8; 1] = [6];
+pub(crate) const FORMAT_VERSION: u8 = 1;
+
+// Branch children are keyed by BRANCH_TABLE_MARKER || path length || path
+// The length means that the children of a branch node share a prefix which none of their
+// descendants have, so they can be fetched with a single range read
+fn branch_key(path: &[u8]) -> Vec<u8> {
+    let mut labelled_key = Vec::with_capacity(path.len() + 2);
+    labelled_key.push(BRANCH_TABLE_MARKER);
+    labelled_key.push(path.len() as u8);
+    labelled_key.extend_from_slice(path);
+    labelled_key
+}
+
+// Brings the records in storage up to the current format version, and stores the version.
+// The records are rewritten in a single batch, so a migration is never left half done
+pub(crate) fn migrate<S: BareMetalKVDb + BatchDB>(storage: &mut S) -> Result<(), VerkleError> {
+    let version = match storage.fetch(&FORMAT_VERSION_KEY).as_deref() {
+        Some([version]) => *version,
+        Some(bytes) => {
+            return Err(VerkleError::CorruptNode {
+                expected_len: 1,
+                got_len: bytes.len(),
+            })
+        }
+        None => 0,
+    };
+    if version == FORMAT_VERSION {
+        return Ok(());
+    }
+    if version > FORMAT_VERSION {
+        return Err(VerkleError::UnsupportedStorageVersion(version));
+    }
+
+    let mut batch = S::BatchWrite::new();
+    // An old key can be the same as the new key of another branch child, so all of the
+    // old keys are deleted before any of the new keys are written
+    let branch_children: Vec<_> = storage.iter_prefix(&[BRANCH_TABLE_MARKER]).collect();
+    for (old_key, _) in &branch_children {
+        batch.batch_delete(old_key);
+    }
+    for (old_key, value) in &branch_children {
+        batch.batch_put(&branch_key(&old_key[1..]), value);
+    }
+    batch.batch_put(&FORMAT_VERSION_KEY, &[FORMAT_VERSION]);
+    storage.flush(batch);
+    Ok(())
+}
+
+// Nodes in the storage are only ever written by the trie, so if we cannot decode one,
+// then the database has been corrupted and there is no sensible way to continue
+const CORRUPT_NODE: &str = "could not decode a node from storage, the database is corrupt";
+
 // GenericBatchWriter does not write the values to disk
 // We need to flush them later on
 // This struct allows us to provide default implementations to everything that is a
@@ -40,20 +97,34 @@ impl<T: BatchWriter> WriteOnlyHigherDb for GenericBatchWriter<T> {
         stem_id: [u8; 31],
         _depth: u8,
     ) -> Option<BranchChild> {
-        let mut labelled_key = Vec::with_capacity(branch_child_id.len() + 1);
-        labelled_key.push(BRANCH_TABLE_MARKER);
-        labelled_key.extend(branch_child_id);
-
-        self.inner.batch_put(&labelled_key, &stem_id);
+        self.inner
+            .batch_put(&branch_key(&branch_child_id), &stem_id);
         None
     }
 
     fn insert_branch(&mut self, key: Vec<u8>, meta: BranchMeta, _depth: u8) -> Option<BranchMeta> {
+        self.inner.batch_put(&branch_key(&key), &meta.to_bytes());
+        None
+    }
+
+    fn remove_leaf(&mut self, key: [u8; 32]) -> Option<[u8; 32]> {
         let mut labelled_key = Vec::with_capacity(key.len() + 1);
-        labelled_key.push(BRANCH_TABLE_MARKER);
+        labelled_key.push(LEAF_TABLE_MARKER);
         labelled_key.extend_from_slice(&key);
+        self.inner.batch_delete(&labelled_key);
+        None
+    }
 
-        self.inner.batch_put(&labelled_key, &meta.to_bytes());
+    fn remove_stem(&mut self, key: [u8; 31]) -> Option<StemMeta> {
+        let mut labelled_key = Vec::with_capacity(key.len() + 1);
+        labelled_key.push(STEM_TABLE_MARKER);
+        labelled_key.extend_from_slice(&key);
+        self.inner.batch_delete(&labelled_key);
+        None
+    }
+
+    fn remove_branch_child(&mut self, branch_child_id: Vec<u8>) -> Option<BranchChild> {
+        self.inner.batch_delete(&branch_key(&branch_child_id));
         None
     }
 }
@@ -64,6 +135,12 @@ pub struct GenericBatchDB<T> {
     inner: T,
 }
 
+impl<T> GenericBatchDB<T> {
+    pub fn new(inner: T) -> Self {
+        GenericBatchDB { inner }
+    }
+}
+
 impl<T> std::ops::Deref for GenericBatchDB<T> {
     type Target = T;
 
@@ -108,69 +185,49 @@ impl<T: BareMetalKVDb> ReadOnlyHigherDb for GenericBatchDB<T> {
 
         self.inner
             .fetch(&labelled_key)
-            .map(|old_val_bytes| StemMeta::from_bytes(&old_val_bytes))
+            .map(|old_val_bytes| StemMeta::from_bytes(&old_val_bytes).expect(CORRUPT_NODE))
     }
 
     fn get_branch_children(&self, branch_id: &[u8]) -> Vec<(u8, BranchChild)> {
-        let mut children = Vec::with_capacity(256);
-
-        let mut labelled_key = Vec::with_capacity(branch_id.len() + 1);
-        labelled_key.push(BRANCH_TABLE_MARKER);
-        labelled_key.extend_from_slice(&branch_id);
-
-        for i in 0u8..=255 {
-            let mut child = labelled_key.clone();
-            child.push(i);
-
-            let child_value = self.inner.fetch(&child);
+        // The children are the only keys with the path length of a child and the branch path as a prefix
+        let mut prefix = Vec::with_capacity(branch_id.len() + 2);
+        prefix.push(BRANCH_TABLE_MARKER);
+        prefix.push(branch_id.len() as u8 + 1);
+        prefix.extend_from_slice(branch_id);
 
-            if let Some(x) = child_value {
-                children.push((i, BranchChild::from_bytes(&x)))
-            }
-        }
-
-        children
+        self.inner
+            .iter_prefix(&prefix)
+            .map(|(key, value)| {
+                let index = key[prefix.len()];
+                (index, BranchChild::from_bytes(&value).expect(CORRUPT_NODE))
+            })
+            .collect()
     }
 
     fn get_branch_meta(&self, key: &[u8]) -> Option<BranchMeta> {
-        let mut labelled_key = Vec::with_capacity(key.len() + 1);
-        labelled_key.push(BRANCH_TABLE_MARKER);
-        labelled_key.extend_from_slice(&key);
-
         self.inner
-            .fetch(&labelled_key)
-            .map(|old_val_bytes| BranchMeta::from_bytes(&old_val_bytes))
+            .fetch(&branch_key(key))
+            .map(|old_val_bytes| BranchMeta::from_bytes(&old_val_bytes).expect(CORRUPT_NODE))
     }
 
     fn get_branch_child(&self, branch_id: &[u8], index: u8) -> Option<BranchChild> {
-        let mut labelled_key = Vec::with_capacity(branch_id.len() + 2);
-        labelled_key.push(BRANCH_TABLE_MARKER);
-
-        labelled_key.extend_from_slice(&branch_id);
-        labelled_key.push(index);
+        let mut child_id = Vec::with_capacity(branch_id.len() + 1);
+        child_id.extend_from_slice(branch_id);
+        child_id.push(index);
         self.inner
-            .fetch(&labelled_key)
-            .map(|old_val_bytes| BranchChild::from_bytes(&old_val_bytes))
+            .fetch(&branch_key(&child_id))
+            .map(|old_val_bytes| BranchChild::from_bytes(&old_val_bytes).expect(CORRUPT_NODE))
     }
 
     fn get_stem_children(&self, stem_key: [u8; 31]) -> Vec<(u8, [u8; 32])> {
-        let mut children = Vec::with_capacity(256);
-
-        let mut labelled_key = Vec::with_capacity(stem_key.len() + 1);
-        labelled_key.push(LEAF_TABLE_MARKER);
-        labelled_key.extend_from_slice(&stem_key);
-
-        for i in 0u8..=255 {
-            let mut child = labelled_key.clone();
-            child.push(i);
-
-            let child_value = self.inner.fetch(&child);
+        // Leaf keys all have the same length, so the keys with the stem as a prefix are its leaves
+        let mut prefix = Vec::with_capacity(stem_key.len() + 1);
+        prefix.push(LEAF_TABLE_MARKER);
+        prefix.extend_from_slice(&stem_key);
 
-            if let Some(x) = child_value {
-                children.push((i, x.try_into().unwrap()))
-            }
-        }
-
-        children
+        self.inner
+            .iter_prefix(&prefix)
+            .map(|(key, value)| (key[prefix.len()], value.try_into().unwrap()))
+            .collect()
     }
 }
@@ -0,0 +1,310 @@
+use super::{BranchChild, BranchMeta, Flush, ReadOnlyHigherDb, StemMeta, WriteOnlyHigherDb};
+
+// A database wrapper which records the previous value of every node that it writes to.
+//
+// Journaling only happens while a checkpoint is open. Each checkpoint has its own journal, and
+// replaying a journal in reverse order restores every node to the value it had when the checkpoint
+// was opened. Since the root's `BranchMeta` is one of these nodes, the root commitment is restored
+// exactly, without recomputing any commitments.
+#[derive(Debug, Clone)]
+pub struct JournaledDb<Storage> {
+    pub storage: Storage,
+    // The journals for each open checkpoint along with its id, the innermost checkpoint is last
+    journals: Vec<(u64, Vec<JournalEntry>)>,
+    // Checkpoint ids are never reused, so a handle to a closed checkpoint cannot refer to a newer one
+    next_checkpoint_id: u64,
+}
+
+// The value a node had before it was written to
+#[derive(Debug, Clone)]
+enum JournalEntry {
+    Leaf {
+        key: [u8; 32],
+        previous: Option<[u8; 32]>,
+        depth: u8,
+    },
+    // Stem metadata holds several points, so it is boxed to keep the other entries small
+    Stem {
+        key: [u8; 31],
+        previous: Option<Box<StemMeta>>,
+        depth: u8,
+    },
+    // Branch nodes and stems are both stored under the path from the root
+    BranchChild {
+        id: Vec<u8>,
+        previous: Option<BranchChild>,
+    },
+}
+
+impl<Storage> JournaledDb<Storage> {
+    pub fn new(storage: Storage) -> Self {
+        JournaledDb {
+            storage,
+            journals: Vec::new(),
+            next_checkpoint_id: 0,
+        }
+    }
+
+    // Returns the number of checkpoints which have not been reverted or committed
+    pub fn num_checkpoints(&self) -> usize {
+        self.journals.len()
+    }
+
+    pub fn into_inner(self) -> Storage {
+        self.storage
+    }
+
+    pub(crate) fn open_checkpoint(&mut self) -> u64 {
+        let id = self.next_checkpoint_id;
+        self.next_checkpoint_id += 1;
+        self.journals.push((id, Vec::new()));
+        id
+    }
+
+    // Returns the position of the checkpoint, or None if it has been reverted or committed
+    fn checkpoint_position(&self, id: u64) -> Option<usize> {
+        self.journals
+            .iter()
+            .position(|(checkpoint_id, _)| *checkpoint_id == id)
+    }
+
+    // Merges the checkpoint, and every checkpoint opened after it, into its parent so that their writes
+    // are reverted along with the parent. If it is the outermost checkpoint, the journals are no longer
+    // needed and are dropped. Returns false if the checkpoint has already been reverted or committed
+    pub(crate) fn commit_checkpoint(&mut self, id: u64) -> bool {
+        let position = match self.checkpoint_position(id) {
+            Some(position) => position,
+            None => return false,
+        };
+        let journals = self.journals.split_off(position);
+        if let Some((_, parent)) = self.journals.last_mut() {
+            for (_, journal) in journals {
+                parent.extend(journal);
+            }
+        }
+        true
+    }
+
+    fn record(&mut self, entry: JournalEntry) {
+        if let Some((_, journal)) = self.journals.last_mut() {
+            journal.push(entry)
+        }
+    }
+
+    fn is_journaling(&self) -> bool {
+        !self.journals.is_empty()
+    }
+}
+
+impl<Storage: ReadOnlyHigherDb + WriteOnlyHigherDb> JournaledDb<Storage> {
+    // Undoes every write made since the checkpoint was opened, closing it and all of the
+    // checkpoints that were opened after it. Returns false if the checkpoint has already
+    // been reverted or committed
+    pub(crate) fn revert_checkpoint(&mut self, id: u64) -> bool {
+        let position = match self.checkpoint_position(id) {
+            Some(position) => position,
+            None => return false,
+        };
+        while self.journals.len() > position {
+            let (_, journal) = self.journals.pop().unwrap();
+            for entry in journal.into_iter().rev() {
+                self.undo(entry);
+            }
+        }
+        true
+    }
+
+    fn undo(&mut self, entry: JournalEntry) {
+        match entry {
+            JournalEntry::Leaf {
+                key,
+                previous: Some(value),
+                depth,
+            } => {
+                self.storage.insert_leaf(key, value, depth);
+            }
+            JournalEntry::Leaf {
+                key,
+                previous: None,
+                ..
+            } => {
+                self.storage.remove_leaf(key);
+            }
+            JournalEntry::Stem {
+                key,
+                previous: Some(meta),
+                depth,
+            } => {
+                self.storage.insert_stem(key, *meta, depth);
+            }
+            JournalEntry::Stem {
+                key,
+                previous: None,
+                ..
+            } => {
+                self.storage.remove_stem(key);
+            }
+            JournalEntry::BranchChild { id, previous } => {
+                // Children are cached with the depth of their parent, so that
+                // a cached branch node always has all of its children in the cache
+                let depth = id.len().saturating_sub(1) as u8;
+                match previous {
+                    Some(BranchChild::Branch(meta)) => {
+                        self.storage.insert_branch(id, meta, depth);
+                    }
+                    Some(BranchChild::Stem(stem_id)) => {
+                        self.storage.add_stem_as_branch_child(id, stem_id, depth);
+                    }
+                    None => {
+                        self.storage.remove_branch_child(id);
+                    }
+                }
+            }
+        }
+    }
+
+    fn previous_branch_child(&self, id: &[u8]) -> Option<BranchChild> {
+        match id.split_last() {
+            Some((index, parent)) => self.storage.get_branch_child(parent, *index),
+            None => self.storage.get_branch_meta(id).map(BranchChild::Branch),
+        }
+    }
+
+    // The depth of a stem is the length of its path from the root.
+    // This is only needed for removals, since the depth is not passed in
+    fn stem_depth(&self, stem: [u8; 31]) -> u8 {
+        let mut path = Vec::with_capacity(stem.len());
+        for index in stem {
+            match self.storage.get_branch_child(&path, index) {
+                Some(BranchChild::Branch(_)) => path.push(index),
+                _ => break,
+            }
+        }
+        path.len() as u8 + 1
+    }
+}
+
+impl<Storage: ReadOnlyHigherDb + WriteOnlyHigherDb> WriteOnlyHigherDb for JournaledDb<Storage> {
+    fn insert_leaf(&mut self, key: [u8; 32], value: [u8; 32], depth: u8) -> Option<Vec<u8>> {
+        if self.is_journaling() {
+            let previous = self.storage.get_leaf(key);
+            self.record(JournalEntry::Leaf {
+                key,
+                previous,
+                depth,
+            });
+        }
+        self.storage.insert_leaf(key, value, depth)
+    }
+
+    fn insert_stem(&mut self, key: [u8; 31], meta: StemMeta, depth: u8) -> Option<StemMeta> {
+        if self.is_journaling() {
+            let previous = self.storage.get_stem_meta(key).map(Box::new);
+            self.record(JournalEntry::Stem {
+                key,
+                previous,
+                depth,
+            });
+        }
+        self.storage.insert_stem(key, meta, depth)
+    }
+
+    fn add_stem_as_branch_child(
+        &mut self,
+        branch_child_id: Vec<u8>,
+        stem_id: [u8; 31],
+        depth: u8,
+    ) -> Option<BranchChild> {
+        if self.is_journaling() {
+            let previous = self.previous_branch_child(&branch_child_id);
+            self.record(JournalEntry::BranchChild {
+                id: branch_child_id.clone(),
+                previous,
+            });
+        }
+        self.storage
+            .add_stem_as_branch_child(branch_child_id, stem_id, depth)
+    }
+
+    fn insert_branch(&mut self, key: Vec<u8>, meta: BranchMeta, depth: u8) -> Option<BranchMeta> {
+        if self.is_journaling() {
+            let previous = self.previous_branch_child(&key);
+            self.record(JournalEntry::BranchChild {
+                id: key.clone(),
+                previous,
+            });
+        }
+        self.storage.insert_branch(key, meta, depth)
+    }
+
+    fn remove_leaf(&mut self, key: [u8; 32]) -> Option<[u8; 32]> {
+        if self.is_journaling() {
+            let previous = self.storage.get_leaf(key);
+            let depth = self.stem_depth(key[0..31].try_into().unwrap());
+            self.record(JournalEntry::Leaf {
+                key,
+                previous,
+                depth,
+            });
+        }
+        self.storage.remove_leaf(key)
+    }
+
+    fn remove_stem(&mut self, key: [u8; 31]) -> Option<StemMeta> {
+        if self.is_journaling() {
+            let previous = self.storage.get_stem_meta(key).map(Box::new);
+            let depth = self.stem_depth(key);
+            self.record(JournalEntry::Stem {
+                key,
+                previous,
+                depth,
+            });
+        }
+        self.storage.remove_stem(key)
+    }
+
+    fn remove_branch_child(&mut self, branch_child_id: Vec<u8>) -> Option<BranchChild> {
+        if self.is_journaling() {
+            let previous = self.previous_branch_child(&branch_child_id);
+            self.record(JournalEntry::BranchChild {
+                id: branch_child_id.clone(),
+                previous,
+            });
+        }
+        self.storage.remove_branch_child(branch_child_id)
+    }
+}
+
+impl<Storage: ReadOnlyHigherDb> ReadOnlyHigherDb for JournaledDb<Storage> {
+    fn get_stem_meta(&self, stem_key: [u8; 31]) -> Option<StemMeta> {
+        self.storage.get_stem_meta(stem_key)
+    }
+
+    fn get_branch_meta(&self, key: &[u8]) -> Option<BranchMeta> {
+        self.storage.get_branch_meta(key)
+    }
+
+    fn get_branch_children(&self, branch_id: &[u8]) -> Vec<(u8, BranchChild)> {
+        self.storage.get_branch_children(branch_id)
+    }
+
+    fn get_branch_child(&self, branch_id: &[u8], index: u8) -> Option<BranchChild> {
+        self.storage.get_branch_child(branch_id, index)
+    }
+
+    fn get_stem_children(&self, stem_key: [u8; 31]) -> Vec<(u8, [u8; 32])> {
+        self.storage.get_stem_children(stem_key)
+    }
+
+    fn get_leaf(&self, key: [u8; 32]) -> Option<[u8; 32]> {
+        self.storage.get_leaf(key)
+    }
+}
+
+// Flushing does not close any checkpoints, reverting after a flush
+// writes the previous values back through the storage
+impl<Storage: Flush> Flush for JournaledDb<Storage> {
+    fn flush(&mut self) {
+        self.storage.flush()
+    }
+}
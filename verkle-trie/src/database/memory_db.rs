@@ -254,6 +254,18 @@ impl WriteOnlyHigherDb for MemoryDb {
         self.branch_table
             .insert(branch_child_id, BranchChild::Stem(stem_id))
     }
+
+    fn remove_leaf(&mut self, key: [u8; 32]) -> Option<[u8; 32]> {
+        self.leaf_table.remove(&key)
+    }
+
+    fn remove_stem(&mut self, key: [u8; 31]) -> Option<StemMeta> {
+        self.stem_table.remove(&key)
+    }
+
+    fn remove_branch_child(&mut self, branch_child_id: Vec<u8>) -> Option<BranchChild> {
+        self.branch_table.remove(&branch_child_id)
+    }
 }
 
 impl Flush for MemoryDb {
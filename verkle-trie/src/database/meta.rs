@@ -1,3 +1,4 @@
+use crate::errors::VerkleError;
 use bandersnatch::{EdwardsProjective, Fr};
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
@@ -50,36 +51,37 @@ fn scalar_to_array(scalar: &Fr) -> [u8; 32] {
     bytes
 }
 impl StemMeta {
-    // panic if we cannot deserialise, do not call this method if you are unsure if the data is
-    // not structured properly. We cn guarantee this in verkle trie.
-    pub fn from_bytes(bytes: &[u8]) -> StemMeta {
+    // Returns an error if the bytes are not a serialised StemMeta
+    pub fn from_bytes(bytes: &[u8]) -> Result<StemMeta, VerkleError> {
         let len = bytes.len();
-        assert_eq!(len, 64 * 3 + 32 * 3);
+        if len != 64 * 3 + 32 * 3 {
+            return Err(VerkleError::CorruptNode {
+                expected_len: 64 * 3 + 32 * 3,
+                got_len: len,
+            });
+        }
 
         use ark_serialize::CanonicalDeserialize;
 
         let point_bytes = &bytes[0..64 * 3];
-        let C_1 =
-            EdwardsProjective::deserialize_uncompressed(&point_bytes[0 * 64..1 * 64]).unwrap();
-        let C_2 =
-            EdwardsProjective::deserialize_uncompressed(&point_bytes[1 * 64..2 * 64]).unwrap();
+        let C_1 = EdwardsProjective::deserialize_uncompressed(&point_bytes[0 * 64..1 * 64])?;
+        let C_2 = EdwardsProjective::deserialize_uncompressed(&point_bytes[1 * 64..2 * 64])?;
         let stem_commitment =
-            EdwardsProjective::deserialize_uncompressed(&point_bytes[2 * 64..3 * 64]).unwrap();
+            EdwardsProjective::deserialize_uncompressed(&point_bytes[2 * 64..3 * 64])?;
 
         let scalar_bytes = &bytes[64 * 3..];
-        let hash_c1 = Fr::deserialize_uncompressed(&scalar_bytes[0 * 32..1 * 32]).unwrap();
-        let hash_c2 = Fr::deserialize_uncompressed(&scalar_bytes[1 * 32..2 * 32]).unwrap();
-        let hash_stem_commitment =
-            Fr::deserialize_uncompressed(&scalar_bytes[2 * 32..3 * 32]).unwrap();
+        let hash_c1 = Fr::deserialize_uncompressed(&scalar_bytes[0 * 32..1 * 32])?;
+        let hash_c2 = Fr::deserialize_uncompressed(&scalar_bytes[1 * 32..2 * 32])?;
+        let hash_stem_commitment = Fr::deserialize_uncompressed(&scalar_bytes[2 * 32..3 * 32])?;
 
-        StemMeta {
+        Ok(StemMeta {
             C_1,
             hash_c1,
             C_2,
             hash_c2,
             stem_commitment,
             hash_stem_commitment,
-        }
+        })
     }
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::with_capacity(3 * (64 + 32));
@@ -125,27 +127,28 @@ impl BranchMeta {
             hash_commitment: Fr::zero(),
         }
     }
-    pub fn from_bytes(bytes: &[u8]) -> BranchMeta {
+    // Returns an error if the bytes are not a serialised BranchMeta
+    pub fn from_bytes(bytes: &[u8]) -> Result<BranchMeta, VerkleError> {
         let len = bytes.len();
-        assert_eq!(
-            len,
-            32 + 64,
-            "BranchMeta was not serialised properly, got {}",
-            hex::encode(bytes)
-        );
+        if len != 32 + 64 {
+            return Err(VerkleError::CorruptNode {
+                expected_len: 32 + 64,
+                got_len: len,
+            });
+        }
 
         use ark_serialize::CanonicalDeserialize;
 
         let point_bytes = &bytes[0..64];
         let scalar_bytes = &bytes[64..64 + 32];
 
-        let commitment = EdwardsProjective::deserialize_uncompressed(point_bytes).unwrap();
-        let hash_commitment = Fr::deserialize_uncompressed(scalar_bytes).unwrap();
+        let commitment = EdwardsProjective::deserialize_uncompressed(point_bytes)?;
+        let hash_commitment = Fr::deserialize_uncompressed(scalar_bytes)?;
 
-        BranchMeta {
+        Ok(BranchMeta {
             commitment,
             hash_commitment,
-        }
+        })
     }
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::with_capacity(64 + 32);
@@ -208,11 +211,11 @@ pub enum BranchChild {
 }
 
 impl BranchChild {
-    pub fn from_bytes(bytes: &[u8]) -> BranchChild {
+    pub fn from_bytes(bytes: &[u8]) -> Result<BranchChild, VerkleError> {
         if bytes.len() == 31 {
-            return BranchChild::Stem(bytes.try_into().unwrap());
+            return Ok(BranchChild::Stem(bytes.try_into().unwrap()));
         }
-        BranchChild::Branch(BranchMeta::from_bytes(bytes))
+        BranchMeta::from_bytes(bytes).map(BranchChild::Branch)
     }
     pub fn to_bytes(&self) -> Vec<u8> {
         match self {
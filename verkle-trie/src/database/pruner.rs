@@ -0,0 +1,139 @@
+use super::{
+    archive::{
+        archived_epochs, decode_versioned_node_key, remove_archived_epoch, HistoricalDb, EPOCH_LEN,
+        VERSIONED_NODE_MARKER,
+    },
+    generic::{BRANCH_TABLE_MARKER, LEAF_TABLE_MARKER, STEM_TABLE_MARKER},
+    BranchChild, ReadOnlyHigherDb, VerkleDb,
+};
+use std::collections::{BTreeSet, HashMap};
+use verkle_db::{BareMetalKVDb, BatchDB, BatchWriter};
+
+// Pruning removes the versioned records which are no longer needed to read any of the retained epochs.
+//
+// The nodes which are reachable from each retained root are found by walking the trie from that root,
+// using `get_branch_children` and `get_stem_children`. Then, for every node in the archive,
+// a version of the node is kept if a retained epoch reads it:
+// - If the node is reachable from the root of a retained epoch, then the version at that epoch is kept.
+// - Leaves can also be read directly, so if a node was removed before a retained epoch, the removal
+//   is kept as long as an older version of the node is kept, otherwise the older version would be read.
+// Every other version is deleted. The versioned records of a node are next to each other in the
+// key value database, so the archive is pruned in a single scan over the versioned records.
+//
+// The keys of pruned epochs are removed from the archive, so they can no longer be read.
+// The latest state of the trie, which is not versioned, is never modified by the pruner.
+
+// A node in the archive, identified by its table marker and its key
+type NodeId = (u8, Vec<u8>);
+
+impl<S: BatchDB + BareMetalKVDb> VerkleDb<S> {
+    // Deletes all archived state which is not needed to read the `retain`ed epochs.
+    // Returns the number of versioned records which were deleted
+    pub fn prune(&mut self, retain: impl IntoIterator<Item = u64>) -> usize {
+        let archived = archived_epochs(&*self.storage);
+        let retain: BTreeSet<u64> = retain.into_iter().collect();
+
+        // The retained epochs which each node is reachable from
+        let mut reachable: HashMap<NodeId, BTreeSet<u64>> = HashMap::new();
+        for epoch in archived.iter().filter(|epoch| retain.contains(epoch)) {
+            let view = HistoricalDb::at_archived_epoch(&*self.storage, *epoch);
+            walk_trie(&view, &mut |node| {
+                reachable.entry(node).or_default().insert(*epoch);
+            });
+        }
+
+        let mut w = S::BatchWrite::new();
+        let mut num_deleted = 0;
+        {
+            let mut records = self
+                .storage
+                .iter_prefix(&[VERSIONED_NODE_MARKER])
+                .peekable();
+            while let Some((labelled_key, value)) = records.next() {
+                let (table, key, epoch) = decode_versioned_node_key(&labelled_key);
+                // The records of a node are sorted from its latest version to its earliest
+                let node_prefix = labelled_key[..labelled_key.len() - EPOCH_LEN].to_vec();
+                let mut records_of_node = vec![(epoch, labelled_key, value.is_empty())];
+                while let Some((next_key, _)) = records.peek() {
+                    if next_key.len() != node_prefix.len() + EPOCH_LEN
+                        || !next_key.starts_with(&node_prefix)
+                    {
+                        break;
+                    }
+                    let (next_key, value) = records.next().unwrap();
+                    let (_, _, epoch) = decode_versioned_node_key(&next_key);
+                    records_of_node.push((epoch, next_key, value.is_empty()));
+                }
+                records_of_node.reverse();
+                let versions: Vec<u64> =
+                    records_of_node.iter().map(|(epoch, _, _)| *epoch).collect();
+
+                // The index of the version which would be read at `epoch`
+                let version_at = |epoch: u64| {
+                    let num_versions = versions.partition_point(|version| *version <= epoch);
+                    num_versions.checked_sub(1)
+                };
+
+                let node_reachable = reachable.remove(&(table, key)).unwrap_or_default();
+                let mut kept: BTreeSet<usize> = node_reachable
+                    .iter()
+                    .filter_map(|epoch| version_at(*epoch))
+                    .collect();
+                for epoch in retain.difference(&node_reachable) {
+                    let version = match version_at(*epoch) {
+                        Some(version) => version,
+                        None => continue,
+                    };
+                    let older_version_kept = kept.range(..version).next().is_some();
+                    let (_, _, is_removal) = records_of_node[version];
+                    if older_version_kept || !is_removal {
+                        kept.insert(version);
+                    }
+                }
+
+                for (index, (_, labelled_key, _)) in records_of_node.iter().enumerate() {
+                    if !kept.contains(&index) {
+                        w.batch_delete(labelled_key);
+                        num_deleted += 1;
+                    }
+                }
+            }
+        }
+
+        for epoch in archived.into_iter().filter(|epoch| !retain.contains(epoch)) {
+            remove_archived_epoch(&mut w, epoch);
+        }
+
+        self.storage.flush(w);
+        num_deleted
+    }
+}
+
+// Visits every node in the trie, depth first
+fn walk_trie<Storage: ReadOnlyHigherDb>(storage: &Storage, visit: &mut impl FnMut(NodeId)) {
+    if storage.root_is_missing() {
+        return;
+    }
+    visit((BRANCH_TABLE_MARKER, Vec::new()));
+
+    let mut branches = vec![Vec::new()];
+    while let Some(branch_id) = branches.pop() {
+        for (index, child) in storage.get_branch_children(&branch_id) {
+            let mut child_id = branch_id.clone();
+            child_id.push(index);
+            visit((BRANCH_TABLE_MARKER, child_id.clone()));
+
+            match child {
+                BranchChild::Branch(_) => branches.push(child_id),
+                BranchChild::Stem(stem_id) => {
+                    visit((STEM_TABLE_MARKER, stem_id.to_vec()));
+                    for (suffix, _) in storage.get_stem_children(stem_id) {
+                        let mut leaf_key = stem_id.to_vec();
+                        leaf_key.push(suffix);
+                        visit((LEAF_TABLE_MARKER, leaf_key));
+                    }
+                }
+            }
+        }
+    }
+}
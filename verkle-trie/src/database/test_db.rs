@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+use verkle_db::{BareMetalDiskDb, BareMetalKVDb, BatchDB, BatchWriter, KVIter};
+
+// An in-memory key value database, so that the default database can be tested without a disk database
+#[derive(Default)]
+pub(crate) struct KvStore(pub(crate) BTreeMap<Vec<u8>, Vec<u8>>);
+#[derive(Default)]
+pub(crate) struct KvBatch(Vec<(Vec<u8>, Option<Vec<u8>>)>);
+
+impl BareMetalKVDb for KvStore {
+    fn fetch(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.0.get(key).cloned()
+    }
+    fn iter_range(&self, start: &[u8], end: Option<&[u8]>) -> KVIter<'_> {
+        let end = end.map(|end| end.to_vec());
+        let iter = self
+            .0
+            .range(start.to_vec()..)
+            .take_while(move |(key, _)| match &end {
+                Some(end) => key < &end,
+                None => true,
+            })
+            .map(|(key, value)| (key.clone(), value.clone()));
+        Box::new(iter)
+    }
+    fn new() -> Self {
+        KvStore::default()
+    }
+}
+impl BareMetalDiskDb for KvStore {
+    fn from_path<P: AsRef<std::path::Path>>(_path: P) -> Self {
+        KvStore::default()
+    }
+    const DEFAULT_PATH: &'static str = "";
+}
+impl BatchWriter for KvBatch {
+    fn new() -> Self {
+        KvBatch::default()
+    }
+    fn batch_put(&mut self, key: &[u8], val: &[u8]) {
+        self.0.push((key.to_vec(), Some(val.to_vec())))
+    }
+    fn batch_delete(&mut self, key: &[u8]) {
+        self.0.push((key.to_vec(), None))
+    }
+}
+impl BatchDB for KvStore {
+    type BatchWrite = KvBatch;
+
+    fn flush(&mut self, batch: Self::BatchWrite) {
+        for (key, value) in batch.0 {
+            match value {
+                Some(value) => self.0.insert(key, value),
+                None => self.0.remove(&key),
+            };
+        }
+    }
+}
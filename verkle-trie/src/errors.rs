@@ -0,0 +1,159 @@
+use ark_serialize::SerializationError;
+
+// Errors which can be returned from the public API of the trie
+//
+// Anything which is derived from data that we do not control, such as a proof
+// sent by a peer, must return one of these instead of panicking.
+#[derive(Debug)]
+pub enum VerkleError {
+    // The file with the precomputed points already exists, so `open` should be called instead of `new`
+    PrecomputedPointsExist,
+    // The file with the precomputed points does not exist, so `new` should be called instead of `open`
+    PrecomputedPointsMissing,
+    Io(std::io::Error),
+    // A point or scalar could not be serialised or deserialised
+    Serialization(SerializationError),
+    // A node read from storage does not have the length of any known node encoding
+    CorruptNode { expected_len: usize, got_len: usize },
+    // The database does not have a root node
+    MissingRoot,
+    // A proof cannot be created over zero keys
+    NoKeys,
+    // The proof is malformed, or it is not consistent with the keys and values it is checked against
+    InvalidProof(&'static str),
+    // The proof is well formed, however the opening proof did not verify
+    ProofVerificationFailed,
+    // The indices of the proofs in a batch which did not verify
+    BatchVerificationFailed(Vec<usize>),
+    // The partial trie or the proof does not have the nodes which are needed to read or update the key
+    KeyNotProven,
+    // The proof was encoded with a version of the format which this library cannot read
+    UnsupportedProofVersion(u8),
+    // There are bytes left over after the proof was decoded
+    TrailingBytes(usize),
+    // A point in the proof is not the canonical encoding of an element of the prime order subgroup
+    InvalidPoint(PointError),
+    DuplicateKeys,
+    LengthMismatch { keys: usize, values: usize },
+    // The archive already has state for a later epoch, so this epoch cannot be archived
+    StaleEpoch { latest: u64, epoch: u64 },
+    // The epoch was never archived, or it has been pruned
+    EpochNotArchived(u64),
+    // The storage was written with a newer format than this library can read
+    UnsupportedStorageVersion(u8),
+    // The nodes loaded into the cache do not agree with the nodes in storage
+    InconsistentCache,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointError {
+    // The point has a different encoding, or the x coordinate is not reduced
+    NonCanonical,
+    NotOnCurve,
+    // The point has a small order component
+    NotInSubgroup,
+}
+
+impl std::fmt::Display for PointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PointError::NonCanonical => write!(f, "the point is not canonically encoded"),
+            PointError::NotOnCurve => write!(f, "the point is not on the curve"),
+            PointError::NotInSubgroup => {
+                write!(f, "the point is not in the prime order subgroup")
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for VerkleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerkleError::PrecomputedPointsExist => write!(
+                f,
+                "file with precomputed points already exists. Please call the `open` method"
+            ),
+            VerkleError::PrecomputedPointsMissing => write!(
+                f,
+                "file with precomputed points does not exist. Please call the `new` method"
+            ),
+            VerkleError::Io(err) => write!(f, "io error: {}", err),
+            VerkleError::Serialization(err) => write!(f, "serialization error: {}", err),
+            VerkleError::CorruptNode {
+                expected_len,
+                got_len,
+            } => write!(
+                f,
+                "corrupt node encoding, expected {} bytes but got {}",
+                expected_len, got_len
+            ),
+            VerkleError::MissingRoot => write!(f, "the database does not have a root node"),
+            VerkleError::NoKeys => write!(f, "cannot create a proof with no keys"),
+            VerkleError::InvalidProof(reason) => write!(f, "invalid proof: {}", reason),
+            VerkleError::ProofVerificationFailed => write!(f, "proof did not verify"),
+            VerkleError::BatchVerificationFailed(failed) => {
+                write!(
+                    f,
+                    "proofs at indices {:?} in the batch did not verify",
+                    failed
+                )
+            }
+            VerkleError::KeyNotProven => {
+                write!(f, "the key is not covered by the proof")
+            }
+            VerkleError::UnsupportedProofVersion(version) => {
+                write!(f, "unsupported proof encoding version {}", version)
+            }
+            VerkleError::TrailingBytes(num_bytes) => {
+                write!(
+                    f,
+                    "{} unexpected bytes after the end of the proof",
+                    num_bytes
+                )
+            }
+            VerkleError::InvalidPoint(err) => write!(f, "invalid point in proof: {}", err),
+            VerkleError::DuplicateKeys => write!(f, "keys must be unique"),
+            VerkleError::LengthMismatch { keys, values } => write!(
+                f,
+                "number of keys ({}) does not match the number of values ({})",
+                keys, values
+            ),
+            VerkleError::StaleEpoch { latest, epoch } => write!(
+                f,
+                "cannot archive epoch {}, the archive already has epoch {}",
+                epoch, latest
+            ),
+            VerkleError::EpochNotArchived(epoch) => {
+                write!(f, "epoch {} is not in the archive", epoch)
+            }
+            VerkleError::UnsupportedStorageVersion(version) => {
+                write!(f, "storage format version {} is not supported", version)
+            }
+            VerkleError::InconsistentCache => {
+                write!(f, "the cached root does not match the root in storage")
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerkleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VerkleError::Io(err) => Some(err),
+            VerkleError::Serialization(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for VerkleError {
+    fn from(err: std::io::Error) -> Self {
+        VerkleError::Io(err)
+    }
+}
+
+impl From<SerializationError> for VerkleError {
+    fn from(err: SerializationError) -> Self {
+        VerkleError::Serialization(err)
+    }
+}
@@ -0,0 +1,190 @@
+use crate::database::{BranchMeta, StemMeta};
+use crate::proof::validation;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use bandersnatch::{EdwardsProjective, Fr};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryInto;
+
+// JSON encoding for debugging and for exchanging test vectors with other implementations.
+//
+// Byte strings are hex encoded with a 0x prefix, which is the convention of go-verkle's JSON.
+// Points are in their 32 byte Banderwagon encoding, which is also used for the points of a proof in JSON,
+// and scalars are in their 32 byte little endian encoding, as produced by `CanonicalSerialize`.
+// Decoding accepts hex with or without the prefix, and points are checked to be elements of the group.
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+pub(crate) fn from_hex<E: de::Error>(hex_str: &str) -> Result<Vec<u8>, E> {
+    let hex_str = hex_str.strip_prefix("0x").unwrap_or(hex_str);
+    hex::decode(hex_str).map_err(E::custom)
+}
+
+pub(crate) fn from_hex_array<E: de::Error, const N: usize>(hex_str: &str) -> Result<[u8; N], E> {
+    let bytes = from_hex::<E>(hex_str)?;
+    let len = bytes.len();
+    bytes
+        .try_into()
+        .map_err(|_| E::custom(format!("expected {} bytes, got {}", N, len)))
+}
+
+pub(crate) fn point_to_hex(point: &EdwardsProjective) -> String {
+    to_hex(&validation::encode_banderwagon(point))
+}
+
+pub(crate) fn point_from_hex<E: de::Error>(hex_str: &str) -> Result<EdwardsProjective, E> {
+    let bytes: [u8; 32] = from_hex_array(hex_str)?;
+    let point = validation::decode_banderwagon(&bytes).map_err(E::custom)?;
+    Ok(point.into())
+}
+
+pub(crate) fn scalar_to_hex(scalar: &Fr) -> String {
+    let mut bytes = [0u8; 32];
+    scalar
+        .serialize(&mut bytes[..])
+        .expect("could not serialise scalar into a 32 byte array");
+    to_hex(&bytes)
+}
+
+pub(crate) fn scalar_from_hex<E: de::Error>(hex_str: &str) -> Result<Fr, E> {
+    let bytes: [u8; 32] = from_hex_array(hex_str)?;
+    Fr::deserialize(&bytes[..]).map_err(E::custom)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StemMetaJson {
+    c1: String,
+    hash_c1: String,
+    c2: String,
+    hash_c2: String,
+    stem_commitment: String,
+    hash_stem_commitment: String,
+}
+
+impl Serialize for StemMeta {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        StemMetaJson {
+            c1: point_to_hex(&self.C_1),
+            hash_c1: scalar_to_hex(&self.hash_c1),
+            c2: point_to_hex(&self.C_2),
+            hash_c2: scalar_to_hex(&self.hash_c2),
+            stem_commitment: point_to_hex(&self.stem_commitment),
+            hash_stem_commitment: scalar_to_hex(&self.hash_stem_commitment),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for StemMeta {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let json = StemMetaJson::deserialize(deserializer)?;
+        Ok(StemMeta {
+            C_1: point_from_hex(&json.c1)?,
+            hash_c1: scalar_from_hex(&json.hash_c1)?,
+            C_2: point_from_hex(&json.c2)?,
+            hash_c2: scalar_from_hex(&json.hash_c2)?,
+            stem_commitment: point_from_hex(&json.stem_commitment)?,
+            hash_stem_commitment: scalar_from_hex(&json.hash_stem_commitment)?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BranchMetaJson {
+    commitment: String,
+    hash_commitment: String,
+}
+
+impl Serialize for BranchMeta {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        BranchMetaJson {
+            commitment: point_to_hex(&self.commitment),
+            hash_commitment: scalar_to_hex(&self.hash_commitment),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BranchMeta {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let json = BranchMetaJson::deserialize(deserializer)?;
+        Ok(BranchMeta {
+            commitment: point_from_hex(&json.commitment)?,
+            hash_commitment: scalar_from_hex(&json.hash_commitment)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{memory_db::MemoryDb, BranchMeta, ReadOnlyHigherDb};
+    use crate::{TestConfig, Trie, TrieTrait};
+    use ark_ec::ProjectiveCurve;
+    use bandersnatch::{EdwardsProjective, Fr};
+
+    #[test]
+    fn meta_json_roundtrip() {
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        trie.insert_single([1; 32], [2; 32]);
+
+        let stem_meta = trie.storage.get_stem_meta([1; 31]).unwrap();
+        let json = serde_json::to_string(&stem_meta).unwrap();
+        assert!(json.contains("\"hashStemCommitment\":\"0x"));
+        assert_eq!(
+            serde_json::from_str::<crate::database::StemMeta>(&json).unwrap(),
+            stem_meta
+        );
+
+        let branch_meta = trie.storage.get_branch_meta(&[]).unwrap();
+        let json = serde_json::to_string(&branch_meta).unwrap();
+        assert_eq!(
+            serde_json::from_str::<BranchMeta>(&json).unwrap(),
+            branch_meta
+        );
+    }
+
+    #[test]
+    fn meta_json_encoding() {
+        let zero = format!("0x{}", "00".repeat(32));
+        let json = serde_json::to_value(BranchMeta::zero()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "commitment": zero, "hashCommitment": zero })
+        );
+
+        // The prefix is optional when decoding, but the length is not
+        let unprefixed =
+            serde_json::json!({ "commitment": "00".repeat(32), "hashCommitment": zero });
+        assert_eq!(
+            serde_json::from_value::<BranchMeta>(unprefixed).unwrap(),
+            BranchMeta::zero()
+        );
+        let short = serde_json::json!({ "commitment": "0x00", "hashCommitment": zero });
+        assert!(serde_json::from_value::<BranchMeta>(short).is_err());
+
+        // Points are in the Banderwagon encoding, this is the one of the generator
+        let generator = BranchMeta {
+            commitment: EdwardsProjective::prime_subgroup_generator(),
+            hash_commitment: Fr::from(1u8),
+        };
+        let json = serde_json::to_value(&generator).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "commitment": "0x4a2c7486fd924882bf02c6908de395122843e3e05264d7991e18e7985dad51e9",
+                "hashCommitment": format!("0x01{}", "00".repeat(31)),
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<BranchMeta>(json).unwrap(),
+            generator
+        );
+
+        // An x coordinate which is not less than the modulus is not an encoding of a point
+        let non_canonical = serde_json::json!({ "commitment": format!("0x{}", "ff".repeat(32)), "hashCommitment": zero });
+        assert!(serde_json::from_value::<BranchMeta>(non_canonical).is_err());
+    }
+}
@@ -3,12 +3,17 @@ pub mod committer;
 pub mod config;
 pub mod constants;
 pub mod database;
+pub mod errors;
 pub mod from_to_bytes;
+#[cfg(feature = "serde")]
+mod json;
 pub mod proof;
+mod trace;
 pub mod trie;
 mod trie_fuzzer;
 
 pub use config::*;
+pub use errors::VerkleError;
 pub use trie::Trie;
 
 pub use bandersnatch::{EdwardsProjective, Fr};
@@ -34,6 +39,18 @@ pub trait TrieTrait {
     /// TODO: Find out if this method is ever needed
     fn get(&self, key: Key) -> Option<Value>;
 
+    /// Removes the value at the `Key`, returning it if it existed
+    /// This method will implicitly compute the new root
+    fn delete(&mut self, key: Key) -> Option<Value>;
+
+    /// Removes multiple values from the trie
+    /// This method will implicitly compute the new root
+    fn delete_many(&mut self, keys: impl Iterator<Item = Key>) {
+        for key in keys {
+            self.delete(key);
+        }
+    }
+
     /// Returns the root of the trie
     fn root_hash(&self) -> Fr;
 
@@ -41,8 +58,10 @@ pub trait TrieTrait {
     fn root_commitment(&self) -> EdwardsProjective;
 
     /// Creates a verkle proof over many keys
-    /// TODO: This will return a Result in the future
-    fn create_verkle_proof(&self, key: impl Iterator<Item = Key>) -> proof::VerkleProof;
+    fn create_verkle_proof(
+        &self,
+        key: impl Iterator<Item = Key>,
+    ) -> Result<proof::VerkleProof, VerkleError>;
 
     // fn to_dot() -> String;
 }
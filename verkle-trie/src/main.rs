@@ -52,7 +52,7 @@ fn main() {
     // let temp_dir = tempdir().unwrap();
 
     // let db = MemoryDb::new();
-    // let db = VerkleDb::<RocksDb>::from_path(&temp_dir);
+    // let db = VerkleDb::<RocksDb>::open(&temp_dir).unwrap();
 
     // let mut trie = Trie::new(db, &*PRECOMPUTED_TABLE);
     // // Initial set of keys
@@ -1,19 +1,29 @@
 use crate::constants::CRS;
+use crate::errors::VerkleError;
+use crate::trace::timed_span;
 use ark_ec::AffineCurve;
-use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
-use bandersnatch::{EdwardsAffine, EdwardsProjective, Fr};
+use ark_serialize::CanonicalSerialize;
+use bandersnatch::EdwardsProjective;
 use ipa_multipoint::multiproof::MultiPointProof;
 use std::collections::{BTreeMap, BTreeSet};
 
-// TODO: We use the IO Result while we do not have a dedicated Error enum
-type IOResult<T> = ark_std::io::Result<T>;
-type IOError = ark_std::io::Error;
-type IOErrorKind = ark_std::io::ErrorKind;
-
+mod batch;
+mod encoding;
+pub mod execution_witness;
+#[cfg(feature = "serde")]
+mod json;
 mod key_path_finder;
 mod opening_data;
+mod partial_trie;
 pub(crate) mod prover;
 pub mod stateless_updater;
+mod stats;
+pub use batch::{verify_batch, BatchItem};
+pub use encoding::{KeysValues, PROOF_MAGIC, PROOF_VERSION};
+pub use key_path_finder::{KeyNotFound, KeyState};
+pub use partial_trie::PartialTrie;
+pub use stats::ProofStats;
+pub(crate) mod validation;
 pub(crate) mod verifier;
 
 // Every stem node has an associated extension node
@@ -68,7 +78,7 @@ impl VerificationHint {
     // We need the number of keys because we do not serialise the length of
     // the ext_status|| depth. This is equal to the number of keys in the proof, which
     // we assume the user knows.
-    pub fn read<R: ark_std::io::Read>(mut reader: R) -> IOResult<VerificationHint> {
+    pub fn read<R: ark_std::io::Read>(mut reader: R) -> Result<VerificationHint, VerkleError> {
         // First extract the stems with no values opened for them
         let mut num_stems = [0u8; 4];
         reader.read_exact(&mut num_stems)?;
@@ -89,23 +99,19 @@ impl VerificationHint {
         let mut depths = Vec::new();
         let mut extension_present = Vec::new();
 
-        let mut buffer = vec![0u8; num_depths];
-        reader.read_exact(&mut buffer)?;
-
-        for byte in buffer {
-            // use a mask to get the last two bits
-            const MASK: u8 = 3;
-            let ext_status = MASK & byte;
-            let ext_status = match ext_status {
-                0 => ExtPresent::None,
-                1 => ExtPresent::DifferentStem,
-                2 => ExtPresent::Present,
-                x => panic!("unexpected ext status number {} ", x),
-            };
-            // shift away the last 3 bits in order to get the depth
-            let depth = byte >> 3;
-            depths.push(depth);
-            extension_present.push(ext_status)
+        // The number of depths has not been checked against the length of the input,
+        // so they are read in chunks instead of allocating a buffer of that size up front
+        let mut buffer = [0u8; 256];
+        let mut remaining = num_depths;
+        while remaining > 0 {
+            let chunk = &mut buffer[..remaining.min(256)];
+            reader.read_exact(chunk)?;
+            for byte in chunk.iter() {
+                let (depth, ext_status) = decode_depth_and_ext(*byte)?;
+                depths.push(depth);
+                extension_present.push(ext_status)
+            }
+            remaining -= chunk.len();
         }
 
         Ok(VerificationHint {
@@ -114,51 +120,60 @@ impl VerificationHint {
             diff_stem_no_proof,
         })
     }
-    pub fn write<W: ark_std::io::Write>(&self, writer: &mut W) -> IOResult<()> {
+    pub fn write<W: ark_std::io::Write>(&self, writer: &mut W) -> Result<(), VerkleError> {
         // Encode the number of stems with no value openings
         let num_stems = self.diff_stem_no_proof.len() as u32;
-        writer.write(&num_stems.to_le_bytes());
+        writer.write_all(&num_stems.to_le_bytes())?;
 
         for stem in &self.diff_stem_no_proof {
-            writer.write(stem)?;
+            writer.write_all(stem)?;
         }
 
         let num_depths = self.depths.len() as u32;
-        writer.write(&num_depths.to_le_bytes());
+        writer.write_all(&num_depths.to_le_bytes())?;
 
-        // The depths and extension status can be put into a single byte
-        // because extension status only needs 3 bits and depth only needs at most 5 bits
         for (depth, ext_status) in self.depths.iter().zip(&self.extension_present) {
-            let mut byte = 0;
-            // Encode extension status into the byte
-            match ext_status {
-                ExtPresent::None => {
-                    // For None, we set the bit to be zero, so do nothing
-                }
-                ExtPresent::DifferentStem => {
-                    // For different stem, we set the first bit to be 1
-                    // This corresponds to the number 1.
-                    byte = 1;
-                }
-                ExtPresent::Present => {
-                    // For present, we set the second bit to be 1
-                    // and the first bit to be zero
-                    // This corresponds to the number 2.
-                    byte = 2;
-                }
-            };
-
-            // Encode depth into the byte, it should only be less
-            // than or equal to 32, and so we only need 5 bits.
-            debug_assert!(*depth <= 32);
-            byte = byte | (depth << 3);
-
-            writer.write(&[byte])?;
+            writer.write_all(&[encode_depth_and_ext(*depth, *ext_status)])?;
         }
         Ok(())
     }
 }
 
+// The depths and extension status can be put into a single byte
+// because extension status only needs 3 bits and depth only needs at most 5 bits
+pub(crate) fn encode_depth_and_ext(depth: u8, ext_status: ExtPresent) -> u8 {
+    let byte = match ext_status {
+        // For None, we set the bit to be zero
+        ExtPresent::None => 0,
+        // For different stem, we set the first bit to be 1
+        // This corresponds to the number 1.
+        ExtPresent::DifferentStem => 1,
+        // For present, we set the second bit to be 1
+        // and the first bit to be zero
+        // This corresponds to the number 2.
+        ExtPresent::Present => 2,
+    };
+
+    // Encode depth into the byte, it should only be less
+    // than or equal to 32, and so we only need 5 bits.
+    debug_assert!(depth <= 32);
+    byte | (depth << 3)
+}
+
+pub(crate) fn decode_depth_and_ext(byte: u8) -> Result<(u8, ExtPresent), VerkleError> {
+    // use a mask to get the last two bits
+    const MASK: u8 = 3;
+    let ext_status = match MASK & byte {
+        0 => ExtPresent::None,
+        1 => ExtPresent::DifferentStem,
+        2 => ExtPresent::Present,
+        _ => return Err(VerkleError::InvalidProof("unexpected extension status")),
+    };
+    // shift away the last 3 bits in order to get the depth
+    let depth = byte >> 3;
+    Ok((depth, ext_status))
+}
+
 // Auxillary information that the verifier needs in order to update the root statelessly
 pub struct UpdateHint {
     depths_and_ext_by_stem: BTreeMap<[u8; 31], (ExtPresent, u8)>,
@@ -168,6 +183,8 @@ pub struct UpdateHint {
     other_stems_by_prefix: BTreeMap<Vec<u8>, [u8; 31]>,
 }
 
+// Every point in a proof is checked to be in the prime order subgroup when the proof
+// is deserialised, so `check` does not validate them again
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct VerkleProof {
     verification_hint: VerificationHint,
@@ -179,7 +196,9 @@ pub struct VerkleProof {
 }
 
 impl VerkleProof {
-    pub fn read<R: ark_std::io::Read>(mut reader: R) -> IOResult<VerkleProof> {
+    // Reads a proof in the layout which was used before the versioned encoding, see `encoding.rs`.
+    // The multipoint proof is not length prefixed, so it is read until the end of the reader
+    pub fn read_legacy<R: ark_std::io::Read>(mut reader: R) -> Result<VerkleProof, VerkleError> {
         let verification_hint = VerificationHint::read(&mut reader)?;
 
         let mut num_comms = [0u8; 4];
@@ -188,14 +207,17 @@ impl VerkleProof {
 
         let mut comms_sorted = Vec::new();
         for _ in 0..num_comms {
-            let point: EdwardsAffine = CanonicalDeserialize::deserialize(&mut reader)
-                .map_err(|_| IOError::from(IOErrorKind::InvalidData))?;
+            let mut point_bytes = [0u8; validation::POINT_SIZE];
+            reader.read_exact(&mut point_bytes)?;
+            let point = validation::decode_point(&point_bytes)?;
             comms_sorted.push(point.into_projective());
         }
 
         let mut bytes = Vec::new();
         reader.read_to_end(&mut bytes)?;
-        let proof = MultiPointProof::from_bytes(&bytes, crate::constants::VERKLE_NODE_WIDTH)?;
+        validation::check_multiproof_points(&bytes)?;
+        let proof = MultiPointProof::from_bytes(&bytes, crate::constants::VERKLE_NODE_WIDTH)
+            .map_err(|_| VerkleError::InvalidProof("could not deserialise the multipoint proof"))?;
 
         Ok(VerkleProof {
             verification_hint,
@@ -204,54 +226,101 @@ impl VerkleProof {
         })
     }
 
-    pub fn write<W: ark_std::io::Write>(&self, mut writer: W) -> IOResult<()> {
-        self.verification_hint.write(&mut writer);
+    pub fn write_legacy<W: ark_std::io::Write>(&self, mut writer: W) -> Result<(), VerkleError> {
+        self.verification_hint.write(&mut writer)?;
 
         let num_comms = self.comms_sorted.len() as u32;
-        writer.write(&num_comms.to_le_bytes());
+        writer.write_all(&num_comms.to_le_bytes())?;
 
         for comm in &self.comms_sorted {
             let mut comm_serialised = [0u8; 32];
-            comm.serialize(&mut comm_serialised[..])
-                .map_err(|_| IOError::from(IOErrorKind::InvalidInput));
-            writer.write(&comm_serialised);
+            comm.serialize(&mut comm_serialised[..])?;
+            writer.write_all(&comm_serialised)?;
         }
 
         // Serialise the Multipoint proof
         let proof_bytes = self.proof.to_bytes()?;
-        writer.write(&proof_bytes);
+        writer.write_all(&proof_bytes)?;
         Ok(())
     }
 
+    // Returns the data needed to statelessly update the root, if the proof verifies
     pub fn check(
         self,
         keys: Vec<[u8; 32]>,
         values: Vec<Option<[u8; 32]>>,
         root: EdwardsProjective,
-    ) -> (bool, Option<UpdateHint>) {
-        // TODO: check the commitments are in the correct subgroup
-        // TODO: possibly will be done with Decaf
+    ) -> Result<UpdateHint, VerkleError> {
+        let _span = timed_span!("check_verkle_proof", num_keys = keys.len());
+
+        if keys.len() != values.len() {
+            return Err(VerkleError::LengthMismatch {
+                keys: keys.len(),
+                values: values.len(),
+            });
+        }
 
         // TODO: remove need for this Clone, by splitting off the IPA proof object
         // TODO here and sending the rest of the struct to create_verifier_queries
         let proof = self.proof.clone();
-        let queries_update_hint = verifier::create_verifier_queries(self, keys, values, root);
-        if queries_update_hint.is_err() {
-            return (false, None);
-        }
-
-        let (queries, update_hint) = match queries_update_hint.unwrap() {
-            Some((queries, update_hint)) => (queries, update_hint),
-            None => return (false, None),
-        };
+        let (queries, update_hint) = verifier::create_verifier_queries(self, keys, values, root)?;
 
         use crate::constants::{PRECOMPUTED_WEIGHTS, VERKLE_NODE_WIDTH};
         use ipa_multipoint::transcript::Transcript;
 
         let mut transcript = Transcript::new(b"vt");
         let ok = proof.check(&CRS, &PRECOMPUTED_WEIGHTS, &queries, &mut transcript);
+        if !ok {
+            return Err(VerkleError::ProofVerificationFailed);
+        }
+
+        Ok(update_hint)
+    }
 
-        (ok, Some(update_hint))
+    // Checks the proof against the states of its keys, as returned by `create_verkle_proof_with_key_states`,
+    // and returns whether each key is present in the trie.
+    // Absent keys are checked to be absent in the way that their state claims, so a key which
+    // is claimed to be at a different stem is rejected if the proof is for its own stem or for another stem
+    pub fn check_key_states(
+        self,
+        keys: Vec<[u8; 32]>,
+        key_states: &[KeyState],
+        root: EdwardsProjective,
+    ) -> Result<(Vec<bool>, UpdateHint), VerkleError> {
+        if keys.len() != key_states.len() {
+            return Err(VerkleError::LengthMismatch {
+                keys: keys.len(),
+                values: key_states.len(),
+            });
+        }
+        let values = key_states.iter().map(KeyState::value).collect();
+        let update_hint = self.check(keys.clone(), values, root)?;
+
+        for (key, key_state) in keys.iter().zip(key_states) {
+            let stem: [u8; 31] = key[0..31].try_into().unwrap();
+            let (ext_status, depth) = update_hint.depths_and_ext_by_stem[&stem];
+            let consistent = match key_state {
+                KeyState::Found(_) | KeyState::NotFound(KeyNotFound::StemFound) => {
+                    ext_status == ExtPresent::Present
+                }
+                KeyState::NotFound(KeyNotFound::Empty) => ext_status == ExtPresent::None,
+                KeyState::NotFound(KeyNotFound::DifferentStem(other_stem)) => {
+                    ext_status == ExtPresent::DifferentStem
+                        && update_hint
+                            .other_stems_by_prefix
+                            .get(&stem[0..depth as usize])
+                            == Some(other_stem)
+                }
+            };
+            if !consistent {
+                return Err(VerkleError::InvalidProof(
+                    "the state of a key does not match the proof",
+                ));
+            }
+        }
+
+        let present = key_states.iter().map(KeyState::is_present).collect();
+        Ok((present, update_hint))
     }
 }
 
@@ -279,13 +348,14 @@ mod test {
     use super::VerkleProof;
     use crate::database::{memory_db::MemoryDb, ReadOnlyHigherDb};
     use crate::proof::{prover, verifier};
+    use crate::VerkleError;
     use crate::{trie::Trie, TestConfig, TrieTrait};
     use bandersnatch::Fr;
 
     #[test]
     fn basic_proof_true() {
         let db = MemoryDb::new();
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let mut keys = Vec::new();
         for i in 0..=3 {
@@ -297,16 +367,15 @@ mod test {
         let root = vec![];
         let meta = trie.storage.get_branch_meta(&root).unwrap();
 
-        let proof = prover::create_verkle_proof(&trie.storage, keys.clone());
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone()).unwrap();
         let values: Vec<_> = keys.iter().map(|val| Some(*val)).collect();
-        let (ok, _) = proof.check(keys, values, meta.commitment);
-        assert!(ok);
+        assert!(proof.check(keys, values, meta.commitment).is_ok());
     }
     #[test]
     fn proof_of_absence_edge_case() {
         use ark_serialize::CanonicalSerialize;
         let db = MemoryDb::new();
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let absent_keys = vec![[3; 32]];
         let absent_values = vec![None];
@@ -314,16 +383,17 @@ mod test {
         let root = vec![];
         let meta = trie.storage.get_branch_meta(&root).unwrap();
 
-        let proof = prover::create_verkle_proof(&trie.storage, absent_keys.clone());
+        let proof = prover::create_verkle_proof(&trie.storage, absent_keys.clone()).unwrap();
 
-        let (ok, _) = proof.check(absent_keys, absent_values, meta.commitment);
-        assert!(ok);
+        assert!(proof
+            .check(absent_keys, absent_values, meta.commitment)
+            .is_ok());
     }
 
     #[test]
     fn prover_queries_match_verifier_queries() {
         let db = MemoryDb::new();
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let mut keys = Vec::new();
         for i in 0..=3 {
@@ -335,8 +405,8 @@ mod test {
         let root = vec![];
         let meta = trie.storage.get_branch_meta(&root).unwrap();
 
-        let (pq, _) = prover::create_prover_queries(&trie.storage, keys.clone());
-        let proof = prover::create_verkle_proof(&trie.storage, keys.clone());
+        let (pq, _, _) = prover::create_prover_queries(&trie.storage, keys.clone());
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone()).unwrap();
 
         let values: Vec<_> = keys.iter().map(|val| Some(*val)).collect();
         let (vq, _) =
@@ -352,7 +422,7 @@ mod test {
     #[test]
     fn simple_serialisation_consistency() {
         let db = MemoryDb::new();
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let mut keys = Vec::new();
         for i in 0..=3 {
@@ -364,11 +434,187 @@ mod test {
         let root = vec![];
         let meta = trie.storage.get_branch_meta(&root).unwrap();
 
-        let proof = prover::create_verkle_proof(&trie.storage, keys.clone());
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone()).unwrap();
 
         let mut bytes = Vec::new();
-        proof.write(&mut bytes);
+        proof.write(&mut bytes).unwrap();
         let deserialised_proof = VerkleProof::read(&bytes[..]).unwrap();
         assert_eq!(proof, deserialised_proof);
     }
+
+    #[test]
+    fn points_outside_subgroup_are_rejected() {
+        use super::validation::tests::point_outside_subgroup;
+        use crate::errors::PointError;
+        use ark_ec::AffineCurve;
+        use ark_serialize::CanonicalSerialize;
+
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+
+        let mut keys = Vec::new();
+        for i in 0..=3 {
+            let mut key_0 = [0u8; 32];
+            key_0[0] = i;
+            keys.push(key_0);
+            trie.insert_single(key_0, key_0);
+        }
+
+        let proof = prover::create_verkle_proof(&trie.storage, keys).unwrap();
+        let mut bytes = Vec::new();
+        proof.write(&mut bytes).unwrap();
+
+        let mut outside = [0u8; 32];
+        point_outside_subgroup()
+            .serialize(&mut outside[..])
+            .unwrap();
+
+        // Replace the first commitment, and the commitment to g(x) in the multipoint proof
+        let proof_len = proof.proof.to_bytes().unwrap().len();
+        let proof_start = bytes.len() - proof_len;
+        let comms_start = proof_start - 32 * proof.comms_sorted.len();
+        for start in [comms_start, proof_start] {
+            let mut bad_point = bytes.clone();
+            bad_point[start..start + 32].copy_from_slice(&outside);
+            assert!(matches!(
+                VerkleProof::read(&bad_point[..]),
+                Err(VerkleError::InvalidPoint(PointError::NotInSubgroup))
+            ));
+        }
+    }
+
+    #[test]
+    fn malformed_proofs_return_errors() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+
+        let mut keys = Vec::new();
+        for i in 0..=3 {
+            let mut key_0 = [0u8; 32];
+            key_0[0] = i;
+            keys.push(key_0);
+            trie.insert_single(key_0, key_0);
+        }
+        let root = vec![];
+        let meta = trie.storage.get_branch_meta(&root).unwrap();
+        let values: Vec<_> = keys.iter().map(|val| Some(*val)).collect();
+
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone()).unwrap();
+        let mut bytes = Vec::new();
+        proof.write_legacy(&mut bytes).unwrap();
+
+        // Truncated proofs cannot be deserialised
+        for len in [0, 3, 10, bytes.len() - 1] {
+            assert!(VerkleProof::read(&bytes[..len]).is_err());
+        }
+
+        // The number of stems with no proofs is zero, so the first depth byte comes after the two lengths
+        // Set the extension status to an unknown value
+        let mut bad_ext_status = bytes.clone();
+        bad_ext_status[8] |= 3;
+        assert!(matches!(
+            VerkleProof::read(&bad_ext_status[..]),
+            Err(VerkleError::InvalidProof(_))
+        ));
+
+        // Set the depth of the first stem to zero
+        let mut bad_depth = bytes.clone();
+        bad_depth[8] &= 0b0000_0111;
+        let proof = VerkleProof::read(&bad_depth[..]).unwrap();
+        assert!(matches!(
+            proof.check(keys.clone(), values.clone(), meta.commitment),
+            Err(VerkleError::InvalidProof(_))
+        ));
+
+        // Claiming values for keys that the proof shows are absent
+        let proof = VerkleProof::read(&bytes[..]).unwrap();
+        let mut absent_key = [0u8; 32];
+        absent_key[0] = 200;
+        let mut wrong_keys = keys.clone();
+        wrong_keys[3] = absent_key;
+        assert!(proof
+            .check(wrong_keys, values.clone(), meta.commitment)
+            .is_err());
+
+        // Mismatched number of keys and values
+        let proof = VerkleProof::read(&bytes[..]).unwrap();
+        assert!(matches!(
+            proof.check(keys.clone(), values[0..2].to_vec(), meta.commitment),
+            Err(VerkleError::LengthMismatch { keys: 4, values: 2 })
+        ));
+
+        // No keys
+        assert!(matches!(
+            prover::create_verkle_proof(&trie.storage, vec![]),
+            Err(VerkleError::NoKeys)
+        ));
+    }
+
+    #[test]
+    fn key_states_roundtrip() {
+        use super::{KeyNotFound, KeyState};
+
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+        trie.insert_single([0; 32], [1; 32]);
+        trie.insert_single([1; 32], [2; 32]);
+        let root = trie.root_commitment();
+
+        let mut stem_found = [0u8; 32];
+        stem_found[31] = 5;
+        let mut different_stem = [1u8; 32];
+        different_stem[30] = 0;
+        let keys = vec![[0; 32], stem_found, different_stem, [2; 32]];
+
+        let (proof, key_states) = trie
+            .create_verkle_proof_with_key_states(keys.clone().into_iter())
+            .unwrap();
+        assert_eq!(
+            key_states,
+            vec![
+                KeyState::Found([1; 32]),
+                KeyState::NotFound(KeyNotFound::StemFound),
+                KeyState::NotFound(KeyNotFound::DifferentStem([1; 31])),
+                KeyState::NotFound(KeyNotFound::Empty),
+            ]
+        );
+
+        let (present, _) = proof
+            .clone()
+            .check_key_states(keys.clone(), &key_states, root)
+            .unwrap();
+        assert_eq!(present, vec![true, false, false, false]);
+
+        // The proof shows that the stem of the second key is present, so it cannot be claimed to be empty
+        let mut wrong_states = key_states.clone();
+        wrong_states[1] = KeyState::NotFound(KeyNotFound::Empty);
+        assert!(matches!(
+            proof
+                .clone()
+                .check_key_states(keys.clone(), &wrong_states, root),
+            Err(VerkleError::InvalidProof(_))
+        ));
+
+        // The stem which the third key was found at is in the proof
+        let mut wrong_states = key_states;
+        wrong_states[2] = KeyState::NotFound(KeyNotFound::DifferentStem([3; 31]));
+        assert!(matches!(
+            proof.check_key_states(keys, &wrong_states, root),
+            Err(VerkleError::InvalidProof(_))
+        ));
+    }
+
+    #[test]
+    fn hint_with_too_many_depths() {
+        use super::VerificationHint;
+
+        // No stems, then a number of depths which is far more than the input has
+        let mut bytes = vec![0u8; 4];
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&[0u8; 10]);
+        assert!(matches!(
+            VerificationHint::read(&bytes[..]),
+            Err(VerkleError::Io(_))
+        ));
+    }
 }
@@ -0,0 +1,306 @@
+use super::{validation, verifier, VerkleProof};
+use crate::constants::{CRS, PRECOMPUTED_WEIGHTS, VERKLE_NODE_WIDTH};
+use crate::errors::VerkleError;
+use crate::trace::timed_span;
+use ark_ec::{msm::VariableBaseMSM, AffineCurve, ProjectiveCurve};
+use ark_ff::{batch_inversion, One, PrimeField, Zero};
+use ark_serialize::CanonicalDeserialize;
+use bandersnatch::{EdwardsProjective, Fr};
+use ipa_multipoint::{lagrange_basis::LagrangeBasis, transcript::Transcript};
+use rayon::prelude::*;
+
+// Verification of many independent proofs, such as one proof per block when a light client is syncing.
+//
+// A multipoint proof is checked by reducing its queries to one inner product argument, which ends with
+// checking that a multiscalar multiplication is the identity. Most of its cost is in the 256 generators
+// of the CRS, which are the same for every proof. So the final checks of all of the proofs are added
+// together with random weights, and done as one multiscalar multiplication. The sum is the identity if
+// every proof verifies, and with overwhelming probability it is not if any of them do not. Only then are the
+// proofs checked one by one with `VerkleProof::check`, to find the ones which did not verify.
+//
+// `MultiPointProof::check` does not expose its final check, so the challenges are reconstructed here with the
+// transcript of ipa_multipoint, in the same order as it does. `opening_check` must be kept in line with it,
+// which `opening_checks_match_the_proof_check` tests. If it is not, the combined check fails and every proof
+// is checked on its own, so a proof is only ever reported as failed by the verifier of ipa_multipoint.
+
+// A proof along with the keys, values and root that it is checked against
+#[derive(Debug, Clone)]
+pub struct BatchItem {
+    pub proof: VerkleProof,
+    pub keys: Vec<[u8; 32]>,
+    pub values: Vec<Option<[u8; 32]>>,
+    pub root: EdwardsProjective,
+}
+
+// Checks every proof in the batch. If any of them do not verify, the error has the indices of
+// those proofs, in increasing order. A proof which is malformed counts as a proof which did not verify
+pub fn verify_batch(items: Vec<BatchItem>) -> Result<(), VerkleError> {
+    let span = timed_span!("verify_batch", num_proofs = items.len(); num_failed);
+
+    let checks: Vec<Option<OpeningCheck>> = items
+        .par_iter()
+        .map(|item| opening_check(item).ok())
+        .collect();
+
+    if checks.iter().all(Option::is_some) && holds(checks.iter().flatten()) {
+        span.record("num_failed", 0);
+        return Ok(());
+    }
+
+    // At least one proof did not verify, so each of them is checked on its own to find which
+    let failed: Vec<usize> = items
+        .into_par_iter()
+        .enumerate()
+        .filter_map(|(index, item)| {
+            let result = item.proof.check(item.keys, item.values, item.root);
+            result.is_err().then_some(index)
+        })
+        .collect();
+    span.record("num_failed", failed.len());
+
+    if !failed.is_empty() {
+        return Err(VerkleError::BatchVerificationFailed(failed));
+    }
+    Ok(())
+}
+
+// The final check of the inner product argument of a proof, which is
+// sum(scalars[i] * points[i]) + sum(crs_scalars[i] * G[i]) + q_scalar * Q = 0
+struct OpeningCheck {
+    points: Vec<EdwardsProjective>,
+    scalars: Vec<Fr>,
+    crs_scalars: Vec<Fr>,
+    q_scalar: Fr,
+}
+
+// Reconstructs the verifier queries of the proof, and the challenges of its multipoint proof,
+// to find the check which `MultiPointProof::check` would make
+fn opening_check(item: &BatchItem) -> Result<OpeningCheck, VerkleError> {
+    if item.keys.len() != item.values.len() {
+        return Err(VerkleError::LengthMismatch {
+            keys: item.keys.len(),
+            values: item.values.len(),
+        });
+    }
+    let (queries, _) = verifier::create_verifier_queries(
+        item.proof.clone(),
+        item.keys.clone(),
+        item.values.clone(),
+        item.root,
+    )?;
+
+    // The multipoint proof is the commitment D, the L and R points of each round and the final scalar
+    let num_rounds = VERKLE_NODE_WIDTH.trailing_zeros() as usize;
+    let bytes = item.proof.proof.to_bytes()?;
+    if bytes.len() != (2 * num_rounds + 2) * validation::POINT_SIZE {
+        return Err(VerkleError::InvalidProof(
+            "the multipoint proof has an unexpected length",
+        ));
+    }
+    let (point_bytes, a) = bytes.split_at(bytes.len() - validation::POINT_SIZE);
+    let a = Fr::deserialize(a)?;
+    let points = point_bytes
+        .chunks(validation::POINT_SIZE)
+        .map(|chunk| validation::decode_point(chunk).map(|point| point.into_projective()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (d, l_and_r) = (points[0], &points[1..]);
+    let (ls, rs) = l_and_r.split_at(num_rounds);
+
+    let mut transcript = Transcript::new(b"vt");
+    transcript.domain_sep(b"multiproof");
+    for query in &queries {
+        transcript.append_point(&query.commitment, b"C");
+        transcript.append_scalar(&query.point, b"z");
+        transcript.append_scalar(&query.result, b"y");
+    }
+    let r = transcript.challenge_scalar(b"r");
+    transcript.append_point(&d, b"D");
+    let t = transcript.challenge_scalar(b"t");
+
+    // The queries are combined into E, with the weights r^i / (t - z_i)
+    let mut weights: Vec<Fr> = queries.iter().map(|query| t - query.point).collect();
+    if weights.iter().any(Zero::is_zero) {
+        return Err(VerkleError::ProofVerificationFailed);
+    }
+    batch_inversion(&mut weights);
+    let mut power_of_r = Fr::one();
+    for weight in weights.iter_mut() {
+        *weight *= power_of_r;
+        power_of_r *= r;
+    }
+    let output_point: Fr = weights
+        .iter()
+        .zip(&queries)
+        .map(|(weight, query)| *weight * query.result)
+        .sum();
+    let commitments: Vec<_> = queries.iter().map(|query| query.commitment).collect();
+    let e = msm(&commitments, &weights);
+    transcript.append_point(&e, b"E");
+
+    // The inner product argument shows that E - D, evaluated at t, is the output point
+    let c = e - d;
+    transcript.domain_sep(b"ipa");
+    transcript.append_point(&c, b"C");
+    transcript.append_scalar(&t, b"input point");
+    transcript.append_scalar(&output_point, b"output point");
+    let w = transcript.challenge_scalar(b"w");
+
+    let mut challenges = Vec::with_capacity(num_rounds);
+    for (l, r) in ls.iter().zip(rs) {
+        transcript.append_point(l, b"L");
+        transcript.append_point(r, b"R");
+        challenges.push(transcript.challenge_scalar(b"x"));
+    }
+    let mut inverse_challenges = challenges.clone();
+    batch_inversion(&mut inverse_challenges);
+
+    // The generators and the evaluation vector are folded to a single element. The i'th one is scaled
+    // by the product of the inverse challenges of the rounds whose bit is set in i, where the first round is
+    // the most significant bit
+    let mut folding_scalars = vec![Fr::one()];
+    for inverse in &inverse_challenges {
+        folding_scalars = folding_scalars
+            .into_iter()
+            .flat_map(|scalar| [scalar, scalar * inverse])
+            .collect();
+    }
+    let lagrange_coefficients =
+        LagrangeBasis::evaluate_lagrange_coefficients(&PRECOMPUTED_WEIGHTS, VERKLE_NODE_WIDTH, t);
+    let b_0: Fr = folding_scalars
+        .iter()
+        .zip(&lagrange_coefficients)
+        .map(|(scalar, coefficient)| *scalar * coefficient)
+        .sum();
+
+    // C + sum(x * L) + sum(R / x) + w * output_point * Q = a * <folding_scalars, G> + w * a * b_0 * Q
+    let points = std::iter::once(c)
+        .chain(ls.iter().copied())
+        .chain(rs.iter().copied())
+        .collect();
+    let scalars = std::iter::once(Fr::one())
+        .chain(challenges)
+        .chain(inverse_challenges)
+        .collect();
+    let crs_scalars = folding_scalars.iter().map(|scalar| -(a * scalar)).collect();
+    let q_scalar = w * (output_point - a * b_0);
+
+    Ok(OpeningCheck {
+        points,
+        scalars,
+        crs_scalars,
+        q_scalar,
+    })
+}
+
+// Adds the checks together with random weights, and checks the sum with one multiscalar multiplication.
+// The weights are derived from every check, so that a prover cannot choose proofs which cancel each other out
+fn holds<'a>(checks: impl Iterator<Item = &'a OpeningCheck> + Clone) -> bool {
+    let mut transcript = Transcript::new(b"verify_batch");
+    for check in checks.clone() {
+        for point in &check.points {
+            transcript.append_point(point, b"P");
+        }
+        transcript.append_scalar(&check.q_scalar, b"Q");
+    }
+
+    let mut points = Vec::new();
+    let mut scalars = Vec::new();
+    let mut crs_scalars = vec![Fr::zero(); VERKLE_NODE_WIDTH];
+    let mut q_scalar = Fr::zero();
+    for check in checks {
+        let weight = transcript.challenge_scalar(b"weight");
+        points.extend_from_slice(&check.points);
+        scalars.extend(check.scalars.iter().map(|scalar| weight * scalar));
+        for (sum, scalar) in crs_scalars.iter_mut().zip(&check.crs_scalars) {
+            *sum += weight * scalar;
+        }
+        q_scalar += weight * check.q_scalar;
+    }
+
+    points.extend_from_slice(&CRS.G);
+    scalars.extend(crs_scalars);
+    points.push(CRS.Q);
+    scalars.push(q_scalar);
+    msm(&points, &scalars).is_zero()
+}
+
+fn msm(points: &[EdwardsProjective], scalars: &[Fr]) -> EdwardsProjective {
+    let bases = EdwardsProjective::batch_normalization_into_affine(points);
+    let scalars: Vec<_> = scalars.iter().map(|scalar| scalar.into_repr()).collect();
+    VariableBaseMSM::multi_scalar_mul(&bases, &scalars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{holds, opening_check, verify_batch, BatchItem};
+    use crate::database::memory_db::MemoryDb;
+    use crate::proof::VerkleProof;
+    use crate::{TestConfig, Trie, TrieTrait, VerkleError};
+
+    // A proof for each of a sequence of tries, where every trie has one more key than the last
+    fn test_batch(num_proofs: u8) -> Vec<BatchItem> {
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        let mut items = Vec::new();
+        for i in 0..num_proofs {
+            trie.insert_single([i; 32], [i + 1; 32]);
+            let keys = vec![[i; 32], [255; 32]];
+            let proof = trie.create_verkle_proof(keys.clone().into_iter()).unwrap();
+            items.push(BatchItem {
+                proof,
+                keys,
+                values: vec![Some([i + 1; 32]), None],
+                root: trie.root_commitment(),
+            });
+        }
+        items
+    }
+
+    #[test]
+    fn batch_verifies() {
+        assert!(verify_batch(test_batch(4)).is_ok());
+        assert!(verify_batch(Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn batch_reports_failed_proofs() {
+        let mut items = test_batch(4);
+        // A wrong value, and a root from another block
+        items[1].values[0] = Some([0; 32]);
+        items[3].root = items[2].root;
+
+        match verify_batch(items) {
+            Err(VerkleError::BatchVerificationFailed(failed)) => assert_eq!(failed, vec![1, 3]),
+            other => panic!("expected the batch to fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn batch_reports_bad_opening_proofs() {
+        let mut items = test_batch(3);
+        // Change the final scalar of the inner product argument, so that only the opening proof is wrong
+        let mut bytes = Vec::new();
+        items[2].proof.write_legacy(&mut bytes).unwrap();
+        let scalar_start = bytes.len() - 32;
+        bytes[scalar_start] ^= 1;
+        items[2].proof = VerkleProof::read_legacy(&bytes[..]).unwrap();
+
+        match verify_batch(items) {
+            Err(VerkleError::BatchVerificationFailed(failed)) => assert_eq!(failed, vec![2]),
+            other => panic!("expected the batch to fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn opening_checks_match_the_proof_check() {
+        let mut items = test_batch(3);
+        items[1].values[0] = Some([0; 32]);
+
+        for item in items {
+            let holds = opening_check(&item)
+                .map(|check| holds(std::iter::once(&check)))
+                .unwrap_or(false);
+            let verifies = item.proof.check(item.keys, item.values, item.root).is_ok();
+            assert_eq!(holds, verifies);
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use super::{validation, VerificationHint, VerkleProof};
+use crate::errors::VerkleError;
+use ark_serialize::CanonicalSerialize;
+use ark_std::io::{Read, Write};
+use ipa_multipoint::multiproof::MultiPointProof;
+
+// The versioned encoding of a proof is:
+//
+// magic || version || flags || hint length || number of commitments || multipoint proof length
+//       || verification hint || commitments || multipoint proof || (keys and values)
+//
+// - The lengths are u32s in little endian, like the rest of the proof
+// - The verification hint is in the same layout as the legacy encoding
+// - The keys and values section is only present if the first bit of the flags is set.
+//   It is the number of keys as a u32, followed by the keys, then a byte for each value which
+//   is 1 if the value is present followed by the value, or 0 if it is not
+//
+// Decoding is strict, unknown versions and flags are rejected and the proof must be the whole input.
+//
+// The legacy encoding starts with the number of stems in the verification hint. Read as a number of stems,
+// the magic is over a billion, which cannot be in a legacy proof, so the two encodings can be told apart.
+
+pub const PROOF_MAGIC: [u8; 4] = [0xff, b'v', b'k', b'p'];
+pub const PROOF_VERSION: u8 = 1;
+
+const KEYS_VALUES_FLAG: u8 = 1;
+// The magic, version, flags and the three lengths
+pub(crate) const HEADER_SIZE: usize = PROOF_MAGIC.len() + 2 + 3 * 4;
+
+// The keys that a proof opens and the values that they were opened to, so that it can be checked on its own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeysValues {
+    pub keys: Vec<[u8; 32]>,
+    pub values: Vec<Option<[u8; 32]>>,
+}
+
+impl VerkleProof {
+    // Reads a proof in either the versioned or the legacy encoding.
+    // If the proof includes its keys and values, they are ignored
+    pub fn read<R: Read>(reader: R) -> Result<VerkleProof, VerkleError> {
+        let (proof, _) = VerkleProof::read_with_keys_values(reader)?;
+        Ok(proof)
+    }
+
+    // Reads a proof along with the keys and values that it opens, if they were encoded.
+    // Proofs in the legacy encoding never include them
+    pub fn read_with_keys_values<R: Read>(
+        mut reader: R,
+    ) -> Result<(VerkleProof, Option<KeysValues>), VerkleError> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != PROOF_MAGIC {
+            let proof = VerkleProof::read_legacy((&magic[..]).chain(reader))?;
+            return Ok((proof, None));
+        }
+
+        let mut version_and_flags = [0u8; 2];
+        reader.read_exact(&mut version_and_flags)?;
+        let [version, flags] = version_and_flags;
+        if version != PROOF_VERSION {
+            return Err(VerkleError::UnsupportedProofVersion(version));
+        }
+        if flags & !KEYS_VALUES_FLAG != 0 {
+            return Err(VerkleError::InvalidProof(
+                "unknown flags in the proof header",
+            ));
+        }
+
+        let hint_len = read_u32(&mut reader)?;
+        let num_comms = read_u32(&mut reader)?;
+        let proof_len = read_u32(&mut reader)?;
+
+        let hint_bytes = read_section(&mut reader, hint_len)?;
+        let mut hint_reader = &hint_bytes[..];
+        let verification_hint = VerificationHint::read(&mut hint_reader)?;
+        if !hint_reader.is_empty() {
+            return Err(VerkleError::InvalidProof(
+                "the verification hint is shorter than its length",
+            ));
+        }
+
+        let mut comms_sorted = Vec::new();
+        for _ in 0..num_comms {
+            let mut point_bytes = [0u8; validation::POINT_SIZE];
+            reader.read_exact(&mut point_bytes)?;
+            let point = validation::decode_point(&point_bytes)?;
+            comms_sorted.push(point.into());
+        }
+
+        let proof_bytes = read_section(&mut reader, proof_len)?;
+        validation::check_multiproof_points(&proof_bytes)?;
+        let proof = MultiPointProof::from_bytes(&proof_bytes, crate::constants::VERKLE_NODE_WIDTH)
+            .map_err(|_| VerkleError::InvalidProof("could not deserialise the multipoint proof"))?;
+
+        let keys_values = if flags & KEYS_VALUES_FLAG != 0 {
+            Some(read_keys_values(&mut reader)?)
+        } else {
+            None
+        };
+
+        let mut trailing = Vec::new();
+        reader.read_to_end(&mut trailing)?;
+        if !trailing.is_empty() {
+            return Err(VerkleError::TrailingBytes(trailing.len()));
+        }
+
+        let proof = VerkleProof {
+            verification_hint,
+            comms_sorted,
+            proof,
+        };
+        Ok((proof, keys_values))
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), VerkleError> {
+        self.write_envelope(None, writer)
+    }
+
+    // Writes the proof along with the keys and values that it opens, so that
+    // the reader does not need to know them in order to check the proof
+    pub fn write_with_keys_values<W: Write>(
+        &self,
+        keys_values: &KeysValues,
+        writer: W,
+    ) -> Result<(), VerkleError> {
+        if keys_values.keys.len() != keys_values.values.len() {
+            return Err(VerkleError::LengthMismatch {
+                keys: keys_values.keys.len(),
+                values: keys_values.values.len(),
+            });
+        }
+        self.write_envelope(Some(keys_values), writer)
+    }
+
+    fn write_envelope<W: Write>(
+        &self,
+        keys_values: Option<&KeysValues>,
+        mut writer: W,
+    ) -> Result<(), VerkleError> {
+        let mut hint_bytes = Vec::new();
+        self.verification_hint.write(&mut hint_bytes)?;
+        let proof_bytes = self.proof.to_bytes()?;
+
+        let flags = match keys_values {
+            Some(_) => KEYS_VALUES_FLAG,
+            None => 0,
+        };
+        writer.write_all(&PROOF_MAGIC)?;
+        writer.write_all(&[PROOF_VERSION, flags])?;
+        writer.write_all(&(hint_bytes.len() as u32).to_le_bytes())?;
+        writer.write_all(&(self.comms_sorted.len() as u32).to_le_bytes())?;
+        writer.write_all(&(proof_bytes.len() as u32).to_le_bytes())?;
+
+        writer.write_all(&hint_bytes)?;
+        for comm in &self.comms_sorted {
+            let mut comm_serialised = [0u8; validation::POINT_SIZE];
+            comm.serialize(&mut comm_serialised[..])?;
+            writer.write_all(&comm_serialised)?;
+        }
+        writer.write_all(&proof_bytes)?;
+
+        if let Some(keys_values) = keys_values {
+            write_keys_values(keys_values, &mut writer)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, VerkleError> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+// Reads a length prefixed section. The length is not trusted, so the buffer only grows
+// as bytes are read, rather than being allocated up front
+fn read_section<R: Read>(reader: &mut R, len: u32) -> Result<Vec<u8>, VerkleError> {
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len as usize {
+        return Err(VerkleError::InvalidProof(
+            "the proof is shorter than the lengths in its header",
+        ));
+    }
+    Ok(bytes)
+}
+
+fn read_keys_values<R: Read>(reader: &mut R) -> Result<KeysValues, VerkleError> {
+    let num_keys = read_u32(reader)?;
+
+    let mut keys = Vec::new();
+    for _ in 0..num_keys {
+        let mut key = [0u8; 32];
+        reader.read_exact(&mut key)?;
+        keys.push(key);
+    }
+
+    let mut values = Vec::new();
+    for _ in 0..num_keys {
+        let mut tag = [0u8; 1];
+        reader.read_exact(&mut tag)?;
+        let value = match tag[0] {
+            0 => None,
+            1 => {
+                let mut value = [0u8; 32];
+                reader.read_exact(&mut value)?;
+                Some(value)
+            }
+            _ => return Err(VerkleError::InvalidProof("unexpected value tag")),
+        };
+        values.push(value);
+    }
+
+    Ok(KeysValues { keys, values })
+}
+
+fn write_keys_values<W: Write>(
+    keys_values: &KeysValues,
+    writer: &mut W,
+) -> Result<(), VerkleError> {
+    writer.write_all(&(keys_values.keys.len() as u32).to_le_bytes())?;
+    for key in &keys_values.keys {
+        writer.write_all(key)?;
+    }
+    for value in &keys_values.values {
+        match value {
+            Some(value) => {
+                writer.write_all(&[1])?;
+                writer.write_all(value)?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeysValues, PROOF_MAGIC, PROOF_VERSION};
+    use crate::database::memory_db::MemoryDb;
+    use crate::proof::{prover, VerkleProof};
+    use crate::{TestConfig, Trie, TrieTrait, VerkleError};
+
+    fn test_proof() -> (VerkleProof, KeysValues) {
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        let mut keys = Vec::new();
+        for i in 0..=3 {
+            let mut key = [0u8; 32];
+            key[0] = i;
+            keys.push(key);
+            trie.insert_single(key, key);
+        }
+        let absent_key = [200; 32];
+        keys.push(absent_key);
+
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone()).unwrap();
+        let values = keys.iter().map(|key| trie.get(*key)).collect();
+        (proof, KeysValues { keys, values })
+    }
+
+    #[test]
+    fn envelope_roundtrip() {
+        let (proof, keys_values) = test_proof();
+
+        let mut bytes = Vec::new();
+        proof.write(&mut bytes).unwrap();
+        assert_eq!(bytes[0..4], PROOF_MAGIC);
+        assert_eq!(bytes[4], PROOF_VERSION);
+        let (decoded, decoded_keys_values) =
+            VerkleProof::read_with_keys_values(&bytes[..]).unwrap();
+        assert_eq!(decoded, proof);
+        assert_eq!(decoded_keys_values, None);
+
+        let mut bytes = Vec::new();
+        proof
+            .write_with_keys_values(&keys_values, &mut bytes)
+            .unwrap();
+        let (decoded, decoded_keys_values) =
+            VerkleProof::read_with_keys_values(&bytes[..]).unwrap();
+        assert_eq!(decoded, proof);
+        assert_eq!(decoded_keys_values, Some(keys_values));
+    }
+
+    #[test]
+    fn reads_legacy_encoding() {
+        let (proof, _) = test_proof();
+
+        let mut bytes = Vec::new();
+        proof.write_legacy(&mut bytes).unwrap();
+        assert_eq!(VerkleProof::read(&bytes[..]).unwrap(), proof);
+    }
+
+    #[test]
+    fn strict_decoding() {
+        let (proof, keys_values) = test_proof();
+        let mut bytes = Vec::new();
+        proof
+            .write_with_keys_values(&keys_values, &mut bytes)
+            .unwrap();
+
+        let mut unknown_version = bytes.clone();
+        unknown_version[4] = PROOF_VERSION + 1;
+        assert!(matches!(
+            VerkleProof::read(&unknown_version[..]),
+            Err(VerkleError::UnsupportedProofVersion(_))
+        ));
+
+        let mut unknown_flags = bytes.clone();
+        unknown_flags[5] |= 2;
+        assert!(matches!(
+            VerkleProof::read(&unknown_flags[..]),
+            Err(VerkleError::InvalidProof(_))
+        ));
+
+        let mut trailing = bytes.clone();
+        trailing.extend([0, 0, 0]);
+        assert!(matches!(
+            VerkleProof::read(&trailing[..]),
+            Err(VerkleError::TrailingBytes(3))
+        ));
+
+        // The hint length no longer matches the encoded hint
+        let mut bad_hint_len = bytes.clone();
+        bad_hint_len[6] += 1;
+        assert!(VerkleProof::read(&bad_hint_len[..]).is_err());
+
+        // The absent key is the last key, so the last byte is the tag of its value
+        let mut bad_tag = bytes.clone();
+        let last = bad_tag.len() - 1;
+        bad_tag[last] = 2;
+        assert!(matches!(
+            VerkleProof::read(&bad_tag[..]),
+            Err(VerkleError::InvalidProof(_))
+        ));
+
+        for len in [0, 4, 10, bytes.len() - 1] {
+            assert!(VerkleProof::read(&bytes[..len]).is_err());
+        }
+
+        let mismatched = KeysValues {
+            keys: keys_values.keys.clone(),
+            values: Vec::new(),
+        };
+        assert!(matches!(
+            proof.write_with_keys_values(&mismatched, Vec::new()),
+            Err(VerkleError::LengthMismatch { .. })
+        ));
+    }
+}
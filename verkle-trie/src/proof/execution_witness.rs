@@ -0,0 +1,341 @@
+use super::{
+    decode_depth_and_ext, encode_depth_and_ext, validation, KeysValues, VerificationHint,
+    VerkleProof,
+};
+use crate::constants::VERKLE_NODE_WIDTH;
+use crate::errors::VerkleError;
+use ark_serialize::CanonicalSerialize;
+use bandersnatch::EdwardsProjective;
+use ipa_multipoint::multiproof::MultiPointProof;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryInto;
+
+mod ssz;
+
+// The `ExecutionWitness` of EIP-6800, which is how proofs are exchanged with Ethereum clients.
+// It holds the state which a block accessed, grouped by stem, along with a proof for it.
+//
+// The containers and their SSZ encoding follow the EIP, so the witness has the same layout as in other clients.
+// The group elements are in the Banderwagon encoding that other clients use, see `validation::encode_banderwagon`,
+// and the final evaluation of the IPA proof is a scalar in little endian.
+
+// The number of rounds in the IPA proof, which is log2 of the width of a node
+pub const IPA_PROOF_DEPTH: usize = 8;
+// Limits from the EIP, a witness which exceeds them cannot be decoded
+pub const MAX_STEMS: usize = 1 << 16;
+pub const MAX_COMMITMENTS_PER_STEM: usize = 33;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuffixStateDiff {
+    pub suffix: u8,
+    pub current_value: Option<[u8; 32]>,
+    pub new_value: Option<[u8; 32]>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StemStateDiff {
+    pub stem: [u8; 31],
+    pub suffix_diffs: Vec<SuffixStateDiff>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpaProof {
+    pub cl: [[u8; 32]; IPA_PROOF_DEPTH],
+    pub cr: [[u8; 32]; IPA_PROOF_DEPTH],
+    pub final_evaluation: [u8; 32],
+}
+
+// This is the `VerkleProof` container of the EIP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WitnessProof {
+    pub other_stems: Vec<[u8; 31]>,
+    pub depth_extension_present: Vec<u8>,
+    // The commitments along the paths to the stems, sorted by path and without the root
+    pub commitments_by_path: Vec<[u8; 32]>,
+    // The commitment to the polynomial g(x) of the multipoint proof
+    pub d: [u8; 32],
+    pub ipa_proof: IpaProof,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionWitness {
+    pub state_diff: Vec<StemStateDiff>,
+    pub verkle_proof: WitnessProof,
+}
+
+impl ExecutionWitness {
+    // Creates a witness from a proof over `keys`, with the values they had when the proof was created.
+    // The new values are the values after the block was executed, if `None` then the keys were only read
+    pub fn from_proof(
+        proof: &VerkleProof,
+        keys: &[[u8; 32]],
+        current_values: &[Option<[u8; 32]>],
+        new_values: Option<&[Option<[u8; 32]>]>,
+    ) -> Result<ExecutionWitness, VerkleError> {
+        if keys.len() != current_values.len() {
+            return Err(VerkleError::LengthMismatch {
+                keys: keys.len(),
+                values: current_values.len(),
+            });
+        }
+        if let Some(new_values) = new_values {
+            if keys.len() != new_values.len() {
+                return Err(VerkleError::LengthMismatch {
+                    keys: keys.len(),
+                    values: new_values.len(),
+                });
+            }
+        }
+
+        // The state diff is sorted by stem, and then by suffix
+        let mut diffs_by_stem: BTreeMap<[u8; 31], BTreeMap<u8, SuffixStateDiff>> = BTreeMap::new();
+        for (i, (key, current_value)) in keys.iter().zip(current_values).enumerate() {
+            let stem: [u8; 31] = key[0..31].try_into().unwrap();
+            let suffix = key[31];
+            let diff = SuffixStateDiff {
+                suffix,
+                current_value: *current_value,
+                new_value: new_values.and_then(|new_values| new_values[i]),
+            };
+            if diffs_by_stem
+                .entry(stem)
+                .or_default()
+                .insert(suffix, diff)
+                .is_some()
+            {
+                return Err(VerkleError::DuplicateKeys);
+            }
+        }
+        let state_diff = diffs_by_stem
+            .into_iter()
+            .map(|(stem, diffs)| StemStateDiff {
+                stem,
+                suffix_diffs: diffs.into_values().collect(),
+            })
+            .collect();
+
+        Ok(ExecutionWitness {
+            state_diff,
+            verkle_proof: WitnessProof::from_proof(proof)?,
+        })
+    }
+
+    // Returns the proof in the witness, along with the keys it opens and their current values,
+    // which are what the proof is checked against
+    pub fn to_proof(&self) -> Result<(VerkleProof, KeysValues), VerkleError> {
+        let mut keys = Vec::new();
+        let mut values = Vec::new();
+        for stem_diff in &self.state_diff {
+            for suffix_diff in &stem_diff.suffix_diffs {
+                let mut key = [0u8; 32];
+                key[0..31].copy_from_slice(&stem_diff.stem);
+                key[31] = suffix_diff.suffix;
+                keys.push(key);
+                values.push(suffix_diff.current_value);
+            }
+        }
+
+        let proof = self.verkle_proof.to_proof()?;
+        Ok((proof, KeysValues { keys, values }))
+    }
+
+    pub fn to_ssz(&self) -> Vec<u8> {
+        ssz::encode_execution_witness(self)
+    }
+
+    pub fn from_ssz(bytes: &[u8]) -> Result<ExecutionWitness, VerkleError> {
+        ssz::decode_execution_witness(bytes)
+    }
+}
+
+impl WitnessProof {
+    pub fn from_proof(proof: &VerkleProof) -> Result<WitnessProof, VerkleError> {
+        let hint = &proof.verification_hint;
+        let depth_extension_present = hint
+            .depths
+            .iter()
+            .zip(&hint.extension_present)
+            .map(|(depth, ext_status)| encode_depth_and_ext(*depth, *ext_status))
+            .collect();
+
+        let commitments_by_path = proof
+            .comms_sorted
+            .iter()
+            .map(validation::encode_banderwagon)
+            .collect();
+
+        // The points of the multipoint proof are converted from the encoding of this library
+        let (d, mut ipa_proof) = split_multiproof(&proof.proof.to_bytes()?)?;
+        let d = to_banderwagon(&d)?;
+        for point in ipa_proof.cl.iter_mut().chain(ipa_proof.cr.iter_mut()) {
+            *point = to_banderwagon(point)?;
+        }
+
+        Ok(WitnessProof {
+            other_stems: hint.diff_stem_no_proof.iter().copied().collect(),
+            depth_extension_present,
+            commitments_by_path,
+            d,
+            ipa_proof,
+        })
+    }
+
+    // Decodes the proof, every point is validated in the same way as when a proof is read from bytes
+    pub fn to_proof(&self) -> Result<VerkleProof, VerkleError> {
+        let mut depths = Vec::with_capacity(self.depth_extension_present.len());
+        let mut extension_present = Vec::with_capacity(depths.capacity());
+        for byte in &self.depth_extension_present {
+            let (depth, ext_status) = decode_depth_and_ext(*byte)?;
+            depths.push(depth);
+            extension_present.push(ext_status);
+        }
+        let diff_stem_no_proof: BTreeSet<_> = self.other_stems.iter().copied().collect();
+        if diff_stem_no_proof.len() != self.other_stems.len() {
+            return Err(VerkleError::InvalidProof("other stems must be unique"));
+        }
+
+        let mut comms_sorted = Vec::with_capacity(self.commitments_by_path.len());
+        for comm in &self.commitments_by_path {
+            comms_sorted.push(validation::decode_banderwagon(comm)?.into());
+        }
+
+        let d = from_banderwagon(&self.d)?;
+        let mut ipa_proof = self.ipa_proof.clone();
+        for point in ipa_proof.cl.iter_mut().chain(ipa_proof.cr.iter_mut()) {
+            *point = from_banderwagon(point)?;
+        }
+        let proof_bytes = join_multiproof(&d, &ipa_proof);
+        let proof = MultiPointProof::from_bytes(&proof_bytes, VERKLE_NODE_WIDTH)
+            .map_err(|_| VerkleError::InvalidProof("could not deserialise the multipoint proof"))?;
+
+        Ok(VerkleProof {
+            verification_hint: VerificationHint {
+                depths,
+                extension_present,
+                diff_stem_no_proof,
+            },
+            comms_sorted,
+            proof,
+        })
+    }
+}
+
+fn to_banderwagon(bytes: &[u8; 32]) -> Result<[u8; 32], VerkleError> {
+    let point: EdwardsProjective = validation::decode_point(bytes)?.into();
+    Ok(validation::encode_banderwagon(&point))
+}
+
+fn from_banderwagon(bytes: &[u8; 32]) -> Result<[u8; 32], VerkleError> {
+    let point = validation::decode_banderwagon(bytes)?;
+    let mut serialised = [0u8; 32];
+    point.serialize(&mut serialised[..])?;
+    Ok(serialised)
+}
+
+// A serialised multipoint proof is the commitment to g(x), then the L points and R points
+// of the IPA proof, followed by the final evaluation
+fn split_multiproof(bytes: &[u8]) -> Result<([u8; 32], IpaProof), VerkleError> {
+    if bytes.len() != (2 * IPA_PROOF_DEPTH + 2) * 32 {
+        return Err(VerkleError::InvalidProof(
+            "the multipoint proof has an unexpected length",
+        ));
+    }
+    let mut chunks = bytes
+        .chunks_exact(32)
+        .map(|chunk| -> [u8; 32] { chunk.try_into().unwrap() });
+
+    let d = chunks.next().unwrap();
+    let mut cl = [[0u8; 32]; IPA_PROOF_DEPTH];
+    let mut cr = [[0u8; 32]; IPA_PROOF_DEPTH];
+    for point in cl.iter_mut().chain(cr.iter_mut()) {
+        *point = chunks.next().unwrap();
+    }
+    let final_evaluation = chunks.next().unwrap();
+
+    Ok((
+        d,
+        IpaProof {
+            cl,
+            cr,
+            final_evaluation,
+        },
+    ))
+}
+
+fn join_multiproof(d: &[u8; 32], ipa_proof: &IpaProof) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity((2 * IPA_PROOF_DEPTH + 2) * 32);
+    bytes.extend_from_slice(d);
+    for point in ipa_proof.cl.iter().chain(&ipa_proof.cr) {
+        bytes.extend_from_slice(point);
+    }
+    bytes.extend_from_slice(&ipa_proof.final_evaluation);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExecutionWitness;
+    use crate::database::{memory_db::MemoryDb, ReadOnlyHigherDb};
+    use crate::proof::prover;
+    use crate::proof::validation;
+    use crate::{TestConfig, Trie, TrieTrait, VerkleError};
+
+    #[test]
+    fn witness_roundtrip_verifies() {
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        let mut keys = Vec::new();
+        for i in 0..=3 {
+            let mut key = [0u8; 32];
+            key[0] = i;
+            key[31] = i;
+            trie.insert_single(key, [i; 32]);
+            keys.push(key);
+        }
+        // A key on an existing stem which is not in the trie, and a key on a stem which is not in the trie
+        let mut absent_suffix = keys[1];
+        absent_suffix[31] = 100;
+        keys.push(absent_suffix);
+        keys.push([200; 32]);
+
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+        let values: Vec<_> = keys.iter().map(|key| trie.get(*key)).collect();
+        let new_values: Vec<_> = keys.iter().map(|key| Some([key[0]; 32])).collect();
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone()).unwrap();
+
+        let witness =
+            ExecutionWitness::from_proof(&proof, &keys, &values, Some(&new_values)).unwrap();
+        // The stem of key 1 has two suffixes, the other stems have one each
+        assert_eq!(witness.state_diff.len(), 5);
+        assert_eq!(witness.state_diff[1].suffix_diffs.len(), 2);
+
+        // The commitments are in the Banderwagon encoding
+        let commitments = &witness.verkle_proof.commitments_by_path;
+        for (comm, expected) in commitments.iter().zip(&proof.comms_sorted) {
+            assert_eq!(*comm, validation::encode_banderwagon(expected));
+        }
+
+        let decoded = ExecutionWitness::from_ssz(&witness.to_ssz()).unwrap();
+        assert_eq!(decoded, witness);
+
+        let (decoded_proof, keys_values) = decoded.to_proof().unwrap();
+        assert_eq!(decoded_proof, proof);
+        assert!(decoded_proof
+            .check(keys_values.keys, keys_values.values, root)
+            .is_ok());
+    }
+
+    #[test]
+    fn duplicate_keys_are_rejected() {
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        trie.insert_single([1; 32], [1; 32]);
+        let keys = vec![[1; 32]];
+        let proof = prover::create_verkle_proof(&trie.storage, keys).unwrap();
+
+        let keys = [[1; 32], [1; 32]];
+        let values = [Some([1; 32]), Some([1; 32])];
+        assert!(matches!(
+            ExecutionWitness::from_proof(&proof, &keys, &values, None),
+            Err(VerkleError::DuplicateKeys)
+        ));
+    }
+}
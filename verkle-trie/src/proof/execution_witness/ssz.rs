@@ -0,0 +1,332 @@
+use super::{
+    ExecutionWitness, IpaProof, StemStateDiff, SuffixStateDiff, WitnessProof, IPA_PROOF_DEPTH,
+    MAX_COMMITMENTS_PER_STEM, MAX_STEMS,
+};
+use crate::constants::VERKLE_NODE_WIDTH;
+use crate::errors::VerkleError;
+use std::convert::TryInto;
+
+// SSZ encoding of the execution witness.
+//
+// Only the parts of SSZ which the witness uses are implemented:
+// - Fixed size fields of a container are encoded in place, variable size fields are replaced by
+//   a 4 byte little endian offset and their encoding is appended after the fixed size part
+// - Lists of fixed size elements are the concatenation of their elements, lists of variable
+//   size elements are a list of offsets followed by the elements
+// - `Union[None, Bytes32]` is a selector byte, followed by the value if the selector is 1
+//
+// Decoding is strict, offsets must point into the input in increasing order,
+// lists must not exceed their limits and every byte must be used.
+
+const OFFSET_SIZE: usize = 4;
+const IPA_PROOF_SIZE: usize = (2 * IPA_PROOF_DEPTH + 1) * 32;
+
+fn invalid(reason: &'static str) -> VerkleError {
+    VerkleError::InvalidProof(reason)
+}
+
+enum Field {
+    Fixed(Vec<u8>),
+    Variable(Vec<u8>),
+}
+
+fn encode_container(fields: Vec<Field>) -> Vec<u8> {
+    let fixed_len: usize = fields
+        .iter()
+        .map(|field| match field {
+            Field::Fixed(bytes) => bytes.len(),
+            Field::Variable(_) => OFFSET_SIZE,
+        })
+        .sum();
+
+    let mut fixed = Vec::with_capacity(fixed_len);
+    let mut variable = Vec::new();
+    for field in fields {
+        match field {
+            Field::Fixed(bytes) => fixed.extend(bytes),
+            Field::Variable(bytes) => {
+                let offset = (fixed_len + variable.len()) as u32;
+                fixed.extend(offset.to_le_bytes());
+                variable.extend(bytes);
+            }
+        }
+    }
+    fixed.extend(variable);
+    fixed
+}
+
+fn encode_variable_list(items: Vec<Vec<u8>>) -> Vec<u8> {
+    encode_container(items.into_iter().map(Field::Variable).collect())
+}
+
+fn encode_optional(value: &Option<[u8; 32]>) -> Vec<u8> {
+    match value {
+        Some(value) => {
+            let mut bytes = vec![1];
+            bytes.extend_from_slice(value);
+            bytes
+        }
+        None => vec![0],
+    }
+}
+
+fn encode_suffix_diff(diff: &SuffixStateDiff) -> Vec<u8> {
+    encode_container(vec![
+        Field::Fixed(vec![diff.suffix]),
+        Field::Variable(encode_optional(&diff.current_value)),
+        Field::Variable(encode_optional(&diff.new_value)),
+    ])
+}
+
+fn encode_stem_diff(diff: &StemStateDiff) -> Vec<u8> {
+    let suffix_diffs = diff.suffix_diffs.iter().map(encode_suffix_diff).collect();
+    encode_container(vec![
+        Field::Fixed(diff.stem.to_vec()),
+        Field::Variable(encode_variable_list(suffix_diffs)),
+    ])
+}
+
+fn encode_witness_proof(proof: &WitnessProof) -> Vec<u8> {
+    let mut ipa_proof = Vec::with_capacity(IPA_PROOF_SIZE);
+    for point in proof.ipa_proof.cl.iter().chain(&proof.ipa_proof.cr) {
+        ipa_proof.extend_from_slice(point);
+    }
+    ipa_proof.extend_from_slice(&proof.ipa_proof.final_evaluation);
+
+    encode_container(vec![
+        Field::Variable(proof.other_stems.concat()),
+        Field::Variable(proof.depth_extension_present.clone()),
+        Field::Variable(proof.commitments_by_path.concat()),
+        Field::Fixed(proof.d.to_vec()),
+        Field::Fixed(ipa_proof),
+    ])
+}
+
+pub(super) fn encode_execution_witness(witness: &ExecutionWitness) -> Vec<u8> {
+    let state_diff = witness.state_diff.iter().map(encode_stem_diff).collect();
+    encode_container(vec![
+        Field::Variable(encode_variable_list(state_diff)),
+        Field::Variable(encode_witness_proof(&witness.verkle_proof)),
+    ])
+}
+
+// The size of each field of a container, or `None` if the field has a variable size
+fn decode_container<'a>(
+    bytes: &'a [u8],
+    layout: &[Option<usize>],
+) -> Result<Vec<&'a [u8]>, VerkleError> {
+    let fixed_len: usize = layout.iter().map(|size| size.unwrap_or(OFFSET_SIZE)).sum();
+    if bytes.len() < fixed_len {
+        return Err(invalid("ssz container is shorter than its fixed size part"));
+    }
+
+    // The fixed size fields, and the start of each variable size field
+    let mut fields = Vec::with_capacity(layout.len());
+    let mut offsets = Vec::new();
+    let mut position = 0;
+    for size in layout {
+        match size {
+            Some(size) => {
+                fields.push(Some(&bytes[position..position + size]));
+                position += size;
+            }
+            None => {
+                let offset = read_offset(&bytes[position..])?;
+                offsets.push(offset);
+                fields.push(None);
+                position += OFFSET_SIZE;
+            }
+        }
+    }
+
+    let ends = variable_ends(bytes.len(), fixed_len, &offsets)?;
+    let mut variable = offsets
+        .iter()
+        .zip(ends)
+        .map(|(start, end)| &bytes[*start..end]);
+    Ok(fields
+        .into_iter()
+        .map(|field| field.unwrap_or_else(|| variable.next().unwrap()))
+        .collect())
+}
+
+// Returns the end of each variable size field, checking that the first one starts right after
+// the fixed size part, that they do not overlap and that they end at the end of the input
+fn variable_ends(
+    len: usize,
+    fixed_len: usize,
+    offsets: &[usize],
+) -> Result<Vec<usize>, VerkleError> {
+    match offsets.first() {
+        Some(first) if *first != fixed_len => {
+            return Err(invalid(
+                "ssz offset does not start after the fixed size part",
+            ))
+        }
+        None if len != fixed_len => return Err(invalid("unexpected bytes after ssz container")),
+        _ => {}
+    }
+    let mut ends: Vec<_> = offsets.iter().skip(1).copied().collect();
+    ends.push(len);
+    for (start, end) in offsets.iter().zip(&ends) {
+        if start > end || *end > len {
+            return Err(invalid("ssz offsets are out of order"));
+        }
+    }
+    Ok(ends)
+}
+
+fn read_offset(bytes: &[u8]) -> Result<usize, VerkleError> {
+    let offset: [u8; OFFSET_SIZE] = bytes
+        .get(0..OFFSET_SIZE)
+        .ok_or_else(|| invalid("ssz offset is truncated"))?
+        .try_into()
+        .unwrap();
+    Ok(u32::from_le_bytes(offset) as usize)
+}
+
+fn decode_variable_list(bytes: &[u8], limit: usize) -> Result<Vec<&[u8]>, VerkleError> {
+    if bytes.is_empty() {
+        return Ok(Vec::new());
+    }
+    // The first offset is the size of the offsets, which gives the number of elements
+    let first = read_offset(bytes)?;
+    let num_items = first / OFFSET_SIZE;
+    if num_items * OFFSET_SIZE != first || num_items == 0 || first > bytes.len() {
+        return Err(invalid("ssz list has an invalid first offset"));
+    }
+    if num_items > limit {
+        return Err(invalid("ssz list exceeds its limit"));
+    }
+    decode_container(bytes, &vec![None; num_items])
+}
+
+fn decode_fixed_list<const N: usize>(
+    bytes: &[u8],
+    limit: usize,
+) -> Result<Vec<[u8; N]>, VerkleError> {
+    let chunks = bytes.chunks_exact(N);
+    if !chunks.remainder().is_empty() {
+        return Err(invalid("ssz list is not a whole number of elements"));
+    }
+    if chunks.len() > limit {
+        return Err(invalid("ssz list exceeds its limit"));
+    }
+    Ok(chunks.map(|chunk| chunk.try_into().unwrap()).collect())
+}
+
+fn decode_optional(bytes: &[u8]) -> Result<Option<[u8; 32]>, VerkleError> {
+    match bytes.split_first() {
+        Some((0, [])) => Ok(None),
+        Some((1, value)) if value.len() == 32 => Ok(Some(value.try_into().unwrap())),
+        _ => Err(invalid(
+            "ssz optional value has an invalid selector or length",
+        )),
+    }
+}
+
+fn decode_suffix_diff(bytes: &[u8]) -> Result<SuffixStateDiff, VerkleError> {
+    let fields = decode_container(bytes, &[Some(1), None, None])?;
+    Ok(SuffixStateDiff {
+        suffix: fields[0][0],
+        current_value: decode_optional(fields[1])?,
+        new_value: decode_optional(fields[2])?,
+    })
+}
+
+fn decode_stem_diff(bytes: &[u8]) -> Result<StemStateDiff, VerkleError> {
+    let fields = decode_container(bytes, &[Some(31), None])?;
+    let suffix_diffs = decode_variable_list(fields[1], VERKLE_NODE_WIDTH)?
+        .into_iter()
+        .map(decode_suffix_diff)
+        .collect::<Result<_, _>>()?;
+    Ok(StemStateDiff {
+        stem: fields[0].try_into().unwrap(),
+        suffix_diffs,
+    })
+}
+
+fn decode_witness_proof(bytes: &[u8]) -> Result<WitnessProof, VerkleError> {
+    let fields = decode_container(bytes, &[None, None, None, Some(32), Some(IPA_PROOF_SIZE)])?;
+
+    let depth_extension_present = fields[1].to_vec();
+    if depth_extension_present.len() > MAX_STEMS {
+        return Err(invalid("ssz list exceeds its limit"));
+    }
+
+    let mut ipa_points = fields[4]
+        .chunks_exact(32)
+        .map(|chunk| chunk.try_into().unwrap());
+    let mut cl = [[0u8; 32]; IPA_PROOF_DEPTH];
+    let mut cr = [[0u8; 32]; IPA_PROOF_DEPTH];
+    for point in cl.iter_mut().chain(cr.iter_mut()) {
+        *point = ipa_points.next().unwrap();
+    }
+    let final_evaluation = ipa_points.next().unwrap();
+
+    Ok(WitnessProof {
+        other_stems: decode_fixed_list(fields[0], MAX_STEMS)?,
+        depth_extension_present,
+        commitments_by_path: decode_fixed_list(fields[2], MAX_STEMS * MAX_COMMITMENTS_PER_STEM)?,
+        d: fields[3].try_into().unwrap(),
+        ipa_proof: IpaProof {
+            cl,
+            cr,
+            final_evaluation,
+        },
+    })
+}
+
+pub(super) fn decode_execution_witness(bytes: &[u8]) -> Result<ExecutionWitness, VerkleError> {
+    let fields = decode_container(bytes, &[None, None])?;
+    let state_diff = decode_variable_list(fields[0], MAX_STEMS)?
+        .into_iter()
+        .map(decode_stem_diff)
+        .collect::<Result<_, _>>()?;
+    Ok(ExecutionWitness {
+        state_diff,
+        verkle_proof: decode_witness_proof(fields[1])?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_suffix_diff, encode_suffix_diff};
+    use crate::proof::execution_witness::SuffixStateDiff;
+
+    #[test]
+    fn suffix_diff_encoding() {
+        let diff = SuffixStateDiff {
+            suffix: 7,
+            current_value: None,
+            new_value: Some([2; 32]),
+        };
+        let bytes = encode_suffix_diff(&diff);
+
+        // The suffix, then the offsets of the two values, which start after the 9 byte fixed size part
+        let mut expected = vec![7, 9, 0, 0, 0, 10, 0, 0, 0, 0, 1];
+        expected.extend([2; 32]);
+        assert_eq!(bytes, expected);
+        assert_eq!(decode_suffix_diff(&bytes).unwrap(), diff);
+
+        // The first offset must point to the end of the fixed size part
+        let mut bad_offset = bytes.clone();
+        bad_offset[1] = 8;
+        assert!(decode_suffix_diff(&bad_offset).is_err());
+
+        // The offsets must be in order
+        let mut out_of_order = bytes.clone();
+        out_of_order[5] = 8;
+        assert!(decode_suffix_diff(&out_of_order).is_err());
+
+        // Unknown selector
+        let mut bad_selector = bytes.clone();
+        bad_selector[9] = 2;
+        assert!(decode_suffix_diff(&bad_selector).is_err());
+
+        // A value which is too long
+        let mut too_long = bytes;
+        too_long.push(0);
+        assert!(decode_suffix_diff(&too_long).is_err());
+    }
+}
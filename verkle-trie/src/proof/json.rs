@@ -0,0 +1,243 @@
+use super::execution_witness::{IpaProof, WitnessProof, IPA_PROOF_DEPTH};
+use super::{decode_depth_and_ext, encode_depth_and_ext, VerificationHint, VerkleProof};
+use crate::json::{from_hex, from_hex_array, to_hex};
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+
+// A proof has the same JSON layout as the `VerkleProof` of go-verkle, which is the proof
+// container of the execution witness. The commitments in a proof are sorted by path and do not
+// include the root, and the multipoint proof is split into D and the IPA proof.
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IpaProofJson {
+    cl: Vec<String>,
+    cr: Vec<String>,
+    final_evaluation: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerkleProofJson {
+    other_stems: Vec<String>,
+    depth_extension_present: String,
+    commitments_by_path: Vec<String>,
+    d: String,
+    // go-verkle does not use camel case for this field
+    #[serde(rename = "ipa_proof")]
+    ipa_proof: IpaProofJson,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerificationHintJson {
+    other_stems: Vec<String>,
+    depth_extension_present: String,
+}
+
+fn points_from_hex<E: de::Error>(hex_points: &[String]) -> Result<[[u8; 32]; IPA_PROOF_DEPTH], E> {
+    let points = hex_points
+        .iter()
+        .map(|point| from_hex_array(point))
+        .collect::<Result<Vec<_>, E>>()?;
+    points.try_into().map_err(|points: Vec<_>| {
+        E::custom(format!(
+            "expected {} ipa proof points, got {}",
+            IPA_PROOF_DEPTH,
+            points.len()
+        ))
+    })
+}
+
+impl Serialize for VerkleProof {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let proof = WitnessProof::from_proof(self).map_err(ser::Error::custom)?;
+        VerkleProofJson {
+            other_stems: proof.other_stems.iter().map(|stem| to_hex(stem)).collect(),
+            depth_extension_present: to_hex(&proof.depth_extension_present),
+            commitments_by_path: proof
+                .commitments_by_path
+                .iter()
+                .map(|comm| to_hex(comm))
+                .collect(),
+            d: to_hex(&proof.d),
+            ipa_proof: IpaProofJson {
+                cl: proof
+                    .ipa_proof
+                    .cl
+                    .iter()
+                    .map(|point| to_hex(point))
+                    .collect(),
+                cr: proof
+                    .ipa_proof
+                    .cr
+                    .iter()
+                    .map(|point| to_hex(point))
+                    .collect(),
+                final_evaluation: to_hex(&proof.ipa_proof.final_evaluation),
+            },
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for VerkleProof {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let json = VerkleProofJson::deserialize(deserializer)?;
+        let proof = WitnessProof {
+            other_stems: json
+                .other_stems
+                .iter()
+                .map(|stem| from_hex_array(stem))
+                .collect::<Result<_, _>>()?,
+            depth_extension_present: from_hex(&json.depth_extension_present)?,
+            commitments_by_path: json
+                .commitments_by_path
+                .iter()
+                .map(|comm| from_hex_array(comm))
+                .collect::<Result<_, _>>()?,
+            d: from_hex_array(&json.d)?,
+            ipa_proof: IpaProof {
+                cl: points_from_hex(&json.ipa_proof.cl)?,
+                cr: points_from_hex(&json.ipa_proof.cr)?,
+                final_evaluation: from_hex_array(&json.ipa_proof.final_evaluation)?,
+            },
+        };
+        proof.to_proof().map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for VerificationHint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let depth_extension_present: Vec<_> = self
+            .depths
+            .iter()
+            .zip(&self.extension_present)
+            .map(|(depth, ext_status)| encode_depth_and_ext(*depth, *ext_status))
+            .collect();
+        VerificationHintJson {
+            other_stems: self
+                .diff_stem_no_proof
+                .iter()
+                .map(|stem| to_hex(stem))
+                .collect(),
+            depth_extension_present: to_hex(&depth_extension_present),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for VerificationHint {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let json = VerificationHintJson::deserialize(deserializer)?;
+
+        let mut depths = Vec::new();
+        let mut extension_present = Vec::new();
+        for byte in from_hex::<D::Error>(&json.depth_extension_present)? {
+            let (depth, ext_status) = decode_depth_and_ext(byte).map_err(de::Error::custom)?;
+            depths.push(depth);
+            extension_present.push(ext_status);
+        }
+
+        let mut diff_stem_no_proof = BTreeSet::new();
+        for stem in &json.other_stems {
+            if !diff_stem_no_proof.insert(from_hex_array(stem)?) {
+                return Err(de::Error::custom("other stems must be unique"));
+            }
+        }
+
+        Ok(VerificationHint {
+            depths,
+            extension_present,
+            diff_stem_no_proof,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{memory_db::MemoryDb, ReadOnlyHigherDb};
+    use crate::proof::{prover, VerificationHint, VerkleProof};
+    use crate::{EdwardsProjective, TestConfig, Trie, TrieTrait};
+
+    // Returns a proof, along with the keys and values it opens and the root it is checked against
+    fn test_proof() -> (
+        VerkleProof,
+        Vec<[u8; 32]>,
+        Vec<Option<[u8; 32]>>,
+        EdwardsProjective,
+    ) {
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        let mut keys = Vec::new();
+        for i in 0..=3 {
+            let mut key = [0u8; 32];
+            key[0] = i;
+            keys.push(key);
+            trie.insert_single(key, key);
+        }
+        keys.push([200; 32]);
+
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone()).unwrap();
+        let values = keys.iter().map(|key| trie.get(*key)).collect();
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+        (proof, keys, values, root)
+    }
+
+    #[test]
+    fn proof_json_roundtrip() {
+        let (proof, keys, values, root) = test_proof();
+
+        let json = serde_json::to_value(&proof).unwrap();
+        for field in [
+            "otherStems",
+            "depthExtensionPresent",
+            "commitmentsByPath",
+            "d",
+        ] {
+            assert!(json.get(field).is_some(), "missing field {}", field);
+        }
+        assert_eq!(json["ipa_proof"]["cl"].as_array().unwrap().len(), 8);
+        assert!(json["d"].as_str().unwrap().starts_with("0x"));
+
+        let decoded: VerkleProof = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded, proof);
+        assert!(decoded.check(keys, values, root).is_ok());
+
+        let hint = &proof.verification_hint;
+        let json = serde_json::to_string(hint).unwrap();
+        assert_eq!(
+            serde_json::from_str::<VerificationHint>(&json).unwrap(),
+            *hint
+        );
+    }
+
+    #[test]
+    fn invalid_proof_json_is_rejected() {
+        let (proof, _, _, _) = test_proof();
+        let json = serde_json::to_value(&proof).unwrap();
+
+        let mut not_hex = json.clone();
+        not_hex["d"] = "0xzz".into();
+        assert!(serde_json::from_value::<VerkleProof>(not_hex).is_err());
+
+        let mut missing_point = json.clone();
+        missing_point["ipa_proof"]["cr"]
+            .as_array_mut()
+            .unwrap()
+            .pop();
+        assert!(serde_json::from_value::<VerkleProof>(missing_point).is_err());
+
+        // An x coordinate which is larger than the modulus
+        let mut invalid_point = json.clone();
+        invalid_point["d"] = format!("0x{}", "ff".repeat(32)).into();
+        assert!(serde_json::from_value::<VerkleProof>(invalid_point).is_err());
+
+        let mut duplicate_stems = json;
+        duplicate_stems["otherStems"] = serde_json::json!([
+            format!("0x{}", "01".repeat(31)),
+            format!("0x{}", "01".repeat(31))
+        ]);
+        assert!(serde_json::from_value::<VerkleProof>(duplicate_stems).is_err());
+    }
+}
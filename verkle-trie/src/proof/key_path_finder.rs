@@ -6,21 +6,26 @@ use crate::trie::BranchId;
 // the key would have been inserted. This gives the verifier enough information
 // to update the key.
 pub(crate) struct KeyPathFinder;
-#[derive(Debug, Copy, Clone)]
-pub(crate) enum KeyState {
+
+// The state of a key in the trie, when the proof for it was created
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeyState {
     // The key was found, we return its value
     Found([u8; 32]),
     NotFound(KeyNotFound),
 }
 
 impl KeyState {
-    pub(crate) fn different_stem(&self) -> Option<[u8; 31]> {
+    pub fn is_present(&self) -> bool {
+        matches!(self, KeyState::Found(_))
+    }
+    pub fn different_stem(&self) -> Option<[u8; 31]> {
         match self {
             KeyState::NotFound(KeyNotFound::DifferentStem(stem)) => Some(*stem),
             _ => None,
         }
     }
-    pub(crate) fn value(&self) -> Option<[u8; 32]> {
+    pub fn value(&self) -> Option<[u8; 32]> {
         match self {
             KeyState::Found(value) => Some(*value),
             _ => None,
@@ -28,8 +33,8 @@ impl KeyState {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub(crate) enum KeyNotFound {
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeyNotFound {
     // The key was not found, however the slot where we would
     // have inserted it, there is a different stem
     // An example of this happening is:
@@ -0,0 +1,423 @@
+use super::stateless_updater::{update_nodes, UpdatedNodes, ValueUpdate};
+use super::{ExtPresent, UpdateHint, VerkleProof};
+use crate::committer::Committer;
+use crate::errors::VerkleError;
+use bandersnatch::EdwardsProjective;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+// A sparse trie for stateless clients, which only has the nodes that a proof opened.
+//
+// It is built from a proof which has been checked against a root, so every node in it is known to be in the trie.
+// Keys can be read if the proof shows what their value is, or that their stem is not in the trie.
+// Reading any other key returns an error, since the partial trie cannot tell whether it is in the trie.
+//
+// Updates use the same delta logic as `verify_and_update`, and the nodes that an update modified are
+// written back into the partial trie, so that updates can be applied one after the other.
+
+#[derive(Debug, Clone, Copy)]
+struct StemNode {
+    stem: [u8; 31],
+    commitment: EdwardsProjective,
+    // C1 and C2 are only known if the proof opened them
+    c1: Option<EdwardsProjective>,
+    c2: Option<EdwardsProjective>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    // If all of the children of an inner node are known, the children which are not in the partial trie are empty
+    Branch {
+        commitment: EdwardsProjective,
+        all_children_known: bool,
+    },
+    Stem(Box<StemNode>),
+    Empty,
+}
+
+// The node that the path to a stem ends at
+enum StemSlot<'a> {
+    Stem(&'a StemNode),
+    Empty,
+}
+
+pub struct PartialTrie<C: Committer> {
+    root: EdwardsProjective,
+    nodes: BTreeMap<Vec<u8>, Node>,
+    // The keys which were opened, with `None` for keys that are not in the trie
+    values: BTreeMap<[u8; 32], Option<[u8; 32]>>,
+    committer: C,
+}
+
+impl<C: Committer> PartialTrie<C> {
+    // Checks the proof against the root, and builds the partial trie from the nodes it opened
+    pub fn from_proof(
+        proof: VerkleProof,
+        keys: Vec<[u8; 32]>,
+        values: Vec<Option<[u8; 32]>>,
+        root: EdwardsProjective,
+        committer: C,
+    ) -> Result<PartialTrie<C>, VerkleError> {
+        let hint = proof.check(keys.clone(), values.clone(), root)?;
+
+        let mut partial_trie = PartialTrie {
+            root,
+            nodes: BTreeMap::new(),
+            values: BTreeMap::new(),
+            committer,
+        };
+        partial_trie.insert_nodes(&hint)?;
+        for (key, value) in keys.into_iter().zip(values) {
+            if partial_trie.values.insert(key, value).is_some() {
+                return Err(VerkleError::DuplicateKeys);
+            }
+        }
+        Ok(partial_trie)
+    }
+
+    fn insert_nodes(&mut self, hint: &UpdateHint) -> Result<(), VerkleError> {
+        let commitment_at = |path: &[u8]| {
+            hint.commitments_by_path
+                .get(path)
+                .copied()
+                .ok_or(VerkleError::InvalidProof(
+                    "the update hint is missing a commitment",
+                ))
+        };
+
+        for (stem, (ext_status, depth)) in &hint.depths_and_ext_by_stem {
+            let depth = *depth as usize;
+            for i in 0..depth {
+                let node = Node::Branch {
+                    commitment: commitment_at(&stem[0..i])?,
+                    all_children_known: false,
+                };
+                self.nodes.insert(stem[0..i].to_vec(), node);
+            }
+
+            let slot = stem[0..depth].to_vec();
+            match ext_status {
+                ExtPresent::Present => {
+                    let mut c1_path = slot.clone();
+                    c1_path.push(2);
+                    let mut c2_path = slot.clone();
+                    c2_path.push(3);
+                    let node = StemNode {
+                        stem: *stem,
+                        commitment: commitment_at(&slot)?,
+                        c1: hint.commitments_by_path.get(&c1_path).copied(),
+                        c2: hint.commitments_by_path.get(&c2_path).copied(),
+                    };
+                    self.nodes.insert(slot, Node::Stem(Box::new(node)));
+                }
+                ExtPresent::DifferentStem => {
+                    let other_stem =
+                        hint.other_stems_by_prefix
+                            .get(&slot)
+                            .ok_or(VerkleError::InvalidProof(
+                                "the update hint is missing a stem",
+                            ))?;
+                    let node = StemNode {
+                        stem: *other_stem,
+                        commitment: commitment_at(&slot)?,
+                        c1: None,
+                        c2: None,
+                    };
+                    // If the other stem was also opened for one of its own keys, that opening has C1 and C2
+                    self.nodes.entry(slot).or_insert(Node::Stem(Box::new(node)));
+                }
+                ExtPresent::None => {
+                    self.nodes.insert(slot, Node::Empty);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn root_commitment(&self) -> EdwardsProjective {
+        self.root
+    }
+
+    // Returns the value of the key, or `None` if it is not in the trie.
+    // Returns an error if the proof that the partial trie was built from does not show either
+    pub fn get(&self, key: [u8; 32]) -> Result<Option<[u8; 32]>, VerkleError> {
+        if let Some(value) = self.values.get(&key) {
+            return Ok(*value);
+        }
+        // The key was not opened, however it is not in the trie if its stem is not
+        let stem: [u8; 31] = key[0..31].try_into().unwrap();
+        match self.find_stem(&stem)? {
+            (_, StemSlot::Stem(node)) if node.stem == stem => Err(VerkleError::KeyNotProven),
+            _ => Ok(None),
+        }
+    }
+
+    // Sets the values of the keys and updates the root. Only keys which can be read can be updated,
+    // and if the key is on a stem which is in the trie, the proof must have opened the half of the stem it is in
+    pub fn update(
+        &mut self,
+        updates: impl IntoIterator<Item = ([u8; 32], [u8; 32])>,
+    ) -> Result<EdwardsProjective, VerkleError> {
+        let mut keys = Vec::new();
+        let mut old_values = Vec::new();
+        let mut new_values = Vec::new();
+        for (key, value) in updates {
+            old_values.push(self.get(key)?);
+            keys.push(key);
+            new_values.push(value);
+        }
+        if keys.is_empty() {
+            return Ok(self.root);
+        }
+
+        let hint = self.update_hint(&keys)?;
+        let (root, updated_nodes) = update_nodes(
+            hint,
+            keys.clone(),
+            old_values,
+            new_values.iter().copied().map(ValueUpdate::Set).collect(),
+            self.root,
+            &self.committer,
+        )?;
+
+        self.apply(updated_nodes);
+        self.root = root;
+        for (key, value) in keys.into_iter().zip(new_values) {
+            self.values.insert(key, Some(value));
+        }
+        Ok(root)
+    }
+
+    // Finds the node that the path to the stem ends at, along with its path
+    fn find_stem(&self, stem: &[u8; 31]) -> Result<(Vec<u8>, StemSlot<'_>), VerkleError> {
+        let mut path = Vec::new();
+        loop {
+            match self.nodes.get(&path) {
+                Some(Node::Branch { .. }) => {}
+                Some(Node::Stem(node)) => return Ok((path, StemSlot::Stem(node))),
+                Some(Node::Empty) => return Ok((path, StemSlot::Empty)),
+                None => {
+                    // The path cannot be empty, since the root is a branch
+                    let parent = &path[0..path.len() - 1];
+                    return match self.nodes.get(parent) {
+                        Some(Node::Branch {
+                            all_children_known: true,
+                            ..
+                        }) => Ok((path, StemSlot::Empty)),
+                        _ => Err(VerkleError::KeyNotProven),
+                    };
+                }
+            }
+            if path.len() == stem.len() {
+                return Err(VerkleError::KeyNotProven);
+            }
+            path.push(stem[path.len()]);
+        }
+    }
+
+    // Creates the update hint for the keys from the nodes of the partial trie
+    fn update_hint(&self, keys: &[[u8; 32]]) -> Result<UpdateHint, VerkleError> {
+        let mut depths_and_ext_by_stem = BTreeMap::new();
+        let mut other_stems_by_prefix = BTreeMap::new();
+        for key in keys {
+            let stem: [u8; 31] = key[0..31].try_into().unwrap();
+            let (path, slot) = self.find_stem(&stem)?;
+            let ext_status = match slot {
+                StemSlot::Stem(node) if node.stem == stem => {
+                    let half = if key[31] < 128 { node.c1 } else { node.c2 };
+                    if half.is_none() {
+                        return Err(VerkleError::KeyNotProven);
+                    }
+                    ExtPresent::Present
+                }
+                StemSlot::Stem(node) => {
+                    other_stems_by_prefix.insert(path.clone(), node.stem);
+                    ExtPresent::DifferentStem
+                }
+                StemSlot::Empty => ExtPresent::None,
+            };
+            depths_and_ext_by_stem.insert(stem, (ext_status, path.len() as u8));
+        }
+
+        let mut commitments_by_path = BTreeMap::new();
+        commitments_by_path.insert(Vec::new(), self.root);
+        for (path, node) in &self.nodes {
+            match node {
+                Node::Branch { commitment, .. } => {
+                    commitments_by_path.insert(path.clone(), *commitment);
+                }
+                Node::Stem(node) => {
+                    commitments_by_path.insert(path.clone(), node.commitment);
+                    for (index, comm) in [(2, node.c1), (3, node.c2)] {
+                        if let Some(comm) = comm {
+                            let mut suffix_path = path.clone();
+                            suffix_path.push(index);
+                            commitments_by_path.insert(suffix_path, comm);
+                        }
+                    }
+                }
+                Node::Empty => {}
+            }
+        }
+
+        Ok(UpdateHint {
+            depths_and_ext_by_stem,
+            commitments_by_path,
+            other_stems_by_prefix,
+        })
+    }
+
+    fn apply(&mut self, updated_nodes: UpdatedNodes) {
+        // Stems which were moved down into a new subtree keep the commitments that are known for them
+        let mut known_stems: BTreeMap<[u8; 31], StemNode> = BTreeMap::new();
+        for node in self.nodes.values() {
+            if let Node::Stem(node) = node {
+                known_stems.insert(node.stem, **node);
+            }
+        }
+
+        // Nodes which were removed take their subtrees with them
+        for path in updated_nodes.removed {
+            self.nodes
+                .retain(|node_path, _| !node_path.starts_with(&path));
+            self.nodes.insert(path, Node::Empty);
+        }
+        for (path, commitment) in updated_nodes.branches {
+            let all_children_known = updated_nodes.new_branches.contains(&path)
+                || matches!(
+                    self.nodes.get(&path),
+                    Some(Node::Branch {
+                        all_children_known: true,
+                        ..
+                    })
+                );
+            let node = Node::Branch {
+                commitment,
+                all_children_known,
+            };
+            self.nodes.insert(path, node);
+        }
+        for (path, updated_stem) in updated_nodes.stems {
+            let known = known_stems.get(&updated_stem.stem);
+            let node = StemNode {
+                stem: updated_stem.stem,
+                commitment: updated_stem.commitment,
+                c1: updated_stem.c1.or_else(|| known.and_then(|node| node.c1)),
+                c2: updated_stem.c2.or_else(|| known.and_then(|node| node.c2)),
+            };
+            self.nodes.insert(path, Node::Stem(Box::new(node)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PartialTrie;
+    use crate::committer::test::TestCommitter;
+    use crate::database::memory_db::MemoryDb;
+    use crate::proof::prover;
+    use crate::{TestConfig, Trie, TrieTrait, VerkleError};
+
+    fn key(first: u8, second: u8, suffix: u8) -> [u8; 32] {
+        let mut key = [0u8; 32];
+        key[0] = first;
+        key[1] = second;
+        key[31] = suffix;
+        key
+    }
+
+    #[test]
+    fn reads_and_successive_updates() {
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        trie.insert_single(key(0, 0, 0), [1; 32]);
+        trie.insert_single(key(1, 0, 0), [2; 32]);
+
+        // A key which is present, a key on the same stem which is not,
+        // and a key which is at a different stem
+        let keys = vec![key(0, 0, 0), key(0, 0, 1), key(1, 1, 0)];
+        let values: Vec<_> = keys.iter().map(|key| trie.get(*key)).collect();
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone()).unwrap();
+        let mut partial_trie = PartialTrie::from_proof(
+            proof,
+            keys,
+            values,
+            trie.root_commitment(),
+            TestCommitter::default(),
+        )
+        .unwrap();
+
+        assert_eq!(partial_trie.get(key(0, 0, 0)).unwrap(), Some([1; 32]));
+        assert_eq!(partial_trie.get(key(0, 0, 1)).unwrap(), None);
+        assert_eq!(partial_trie.get(key(1, 1, 0)).unwrap(), None);
+        // Any key on the stem of the absent key is absent, but the proof does not show
+        // the values of the other keys on the stems that are in the trie
+        assert_eq!(partial_trie.get(key(1, 1, 7)).unwrap(), None);
+        assert!(matches!(
+            partial_trie.get(key(0, 0, 2)),
+            Err(VerkleError::KeyNotProven)
+        ));
+        assert!(matches!(
+            partial_trie.get(key(1, 0, 0)),
+            Err(VerkleError::KeyNotProven)
+        ));
+        assert!(matches!(
+            partial_trie.get(key(2, 0, 0)),
+            Err(VerkleError::KeyNotProven)
+        ));
+
+        // Update a value, then insert a key which splits the other stem, then update the inserted key
+        let updates = [
+            vec![(key(0, 0, 0), [3; 32])],
+            vec![(key(1, 1, 0), [4; 32]), (key(0, 0, 1), [5; 32])],
+            vec![(key(1, 1, 0), [6; 32])],
+        ];
+        for update in updates {
+            let root = partial_trie.update(update.clone()).unwrap();
+            trie.insert(update.into_iter());
+            assert_eq!(root, trie.root_commitment());
+        }
+        assert_eq!(partial_trie.get(key(1, 1, 0)).unwrap(), Some([6; 32]));
+
+        // Keys which were not proven cannot be updated
+        assert!(matches!(
+            partial_trie.update(vec![(key(2, 0, 0), [1; 32])]),
+            Err(VerkleError::KeyNotProven)
+        ));
+    }
+
+    #[test]
+    fn inserts_into_empty_slots() {
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        trie.insert_single(key(0, 0, 0), [1; 32]);
+        trie.insert_single(key(0, 1, 0), [2; 32]);
+
+        // An empty slot of the root, and an empty slot of the inner node at depth 1
+        let keys = vec![key(3, 0, 0), key(0, 2, 0), key(0, 2, 1)];
+        let values = vec![None; 3];
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone()).unwrap();
+        let mut partial_trie = PartialTrie::from_proof(
+            proof,
+            keys,
+            values,
+            trie.root_commitment(),
+            TestCommitter::default(),
+        )
+        .unwrap();
+
+        // The stems which are created can be updated again, since all of their commitments are known
+        let updates = [
+            vec![(key(3, 0, 0), [3; 32]), (key(0, 2, 0), [4; 32])],
+            vec![(key(0, 2, 1), [5; 32]), (key(3, 0, 0), [6; 32])],
+        ];
+        for update in updates {
+            let root = partial_trie.update(update.clone()).unwrap();
+            trie.insert(update.into_iter());
+            assert_eq!(root, trie.root_commitment());
+        }
+        // The proof only showed that the slots of the keys were empty, not the other slots of the root
+        assert!(matches!(
+            partial_trie.get(key(4, 0, 0)),
+            Err(VerkleError::KeyNotProven)
+        ));
+    }
+}
@@ -1,10 +1,14 @@
+use super::stats::{OpeningCounts, ProofStats, MULTIPOINT_PROOF_SIZE};
 use super::{VerificationHint, VerkleProof};
 use crate::{
     constants::CRS,
     database::ReadOnlyHigherDb,
+    errors::VerkleError,
+    proof::key_path_finder::KeyState,
     proof::opening_data::{OpeningData, Openings},
+    trace::timed_span,
 };
-use ark_serialize::CanonicalSerialize;
+use bandersnatch::EdwardsProjective;
 use ipa_multipoint::multiproof::MultiPoint;
 use ipa_multipoint::multiproof::ProverQuery;
 use itertools::Itertools;
@@ -13,34 +17,32 @@ use std::collections::BTreeSet;
 pub fn create_verkle_proof<Storage: ReadOnlyHigherDb>(
     storage: &Storage,
     keys: Vec<[u8; 32]>,
-) -> VerkleProof {
-    assert!(keys.len() > 0, "cannot create a proof with no keys");
+) -> Result<VerkleProof, VerkleError> {
+    let (proof, _) = create_verkle_proof_with_key_states(storage, keys)?;
+    Ok(proof)
+}
 
-    let (queries, verification_hint) = create_prover_queries(storage, keys);
-    print!("queries: ");
-    for x in queries.iter() {
-        let mut comm_serialised = [0u8; 32];
-        x.commitment.serialize(&mut comm_serialised[..]).unwrap();
-        print!("{} ", hex::encode(comm_serialised));
+// Creates a proof along with the state of each key, in the same order as the keys.
+// The values in the key states are the values that the proof opens the keys to
+pub fn create_verkle_proof_with_key_states<Storage: ReadOnlyHigherDb>(
+    storage: &Storage,
+    keys: Vec<[u8; 32]>,
+) -> Result<(VerkleProof, Vec<KeyState>), VerkleError> {
+    if keys.is_empty() {
+        return Err(VerkleError::NoKeys);
+    }
+    if storage.root_is_missing() {
+        return Err(VerkleError::MissingRoot);
     }
-    println!("");
 
-    // Commitments without duplicates and without the root, (implicitly) sorted by path, since the queries were
-    // processed by path order
-    let root_comm = queries
-        .first()
-        .expect("expected to have at least one query. The first query will be against the root")
-        .commitment;
+    let span =
+        timed_span!("create_verkle_proof", num_keys = keys.len(); num_queries, num_commitments);
 
-    let comms_sorted: Vec<_> = queries
-        .iter()
-        // Filter out the root commitment
-        .filter(|query| query.commitment != root_comm)
-        // Pull out the commitments from each query
-        .map(|query| query.commitment)
-        // Duplicate all commitments
-        .dedup()
-        .collect();
+    let (queries, verification_hint, key_states) = create_prover_queries(storage, keys);
+    span.record("num_queries", queries.len());
+
+    let comms_sorted = sorted_commitments(&queries);
+    span.record("num_commitments", comms_sorted.len());
 
     use crate::constants::{PRECOMPUTED_WEIGHTS, VERKLE_NODE_WIDTH};
     use ipa_multipoint::transcript::Transcript;
@@ -48,11 +50,58 @@ pub fn create_verkle_proof<Storage: ReadOnlyHigherDb>(
     let mut transcript = Transcript::new(b"vt");
     let proof = MultiPoint::open(CRS.clone(), &PRECOMPUTED_WEIGHTS, &mut transcript, queries);
 
-    VerkleProof {
+    let proof = VerkleProof {
         comms_sorted,
         verification_hint,
         proof,
+    };
+    Ok((proof, key_states))
+}
+
+// Computes the statistics of the proof for `keys` without creating the opening proof, which is most of the work
+// of creating a proof. The statistics are the same as those of the proof, since the size of the opening proof
+// does not depend on the keys
+pub fn estimate_proof_stats<Storage: ReadOnlyHigherDb>(
+    storage: &Storage,
+    keys: Vec<[u8; 32]>,
+) -> Result<ProofStats, VerkleError> {
+    if keys.is_empty() {
+        return Err(VerkleError::NoKeys);
     }
+    if storage.root_is_missing() {
+        return Err(VerkleError::MissingRoot);
+    }
+
+    let opening_data = OpeningData::collect_opening_data(keys, storage);
+    let opening_counts = OpeningCounts::from_opening_data(&opening_data);
+    let (queries, verification_hint, _) = queries_from_opening_data(storage, opening_data);
+    let num_commitments = sorted_commitments(&queries).len();
+
+    ProofStats::new(
+        opening_counts,
+        &verification_hint,
+        num_commitments,
+        MULTIPOINT_PROOF_SIZE,
+    )
+}
+
+// Commitments without duplicates and without the root, (implicitly) sorted by path, since the queries were
+// processed by path order
+fn sorted_commitments(queries: &[ProverQuery]) -> Vec<EdwardsProjective> {
+    let root_comm = queries
+        .first()
+        .expect("expected to have at least one query. The first query will be against the root")
+        .commitment;
+
+    queries
+        .iter()
+        // Filter out the root commitment
+        .filter(|query| query.commitment != root_comm)
+        // Pull out the commitments from each query
+        .map(|query| query.commitment)
+        // Duplicate all commitments
+        .dedup()
+        .collect()
 }
 
 // First we need to produce all of the key paths for a key
@@ -64,13 +113,21 @@ pub fn create_verkle_proof<Storage: ReadOnlyHigherDb>(
 pub(super) fn create_prover_queries<Storage: ReadOnlyHigherDb>(
     storage: &Storage,
     keys: Vec<[u8; 32]>,
-) -> (Vec<ProverQuery>, VerificationHint) {
+) -> (Vec<ProverQuery>, VerificationHint, Vec<KeyState>) {
     assert!(keys.len() > 0, "cannot create a proof with no keys");
 
     let opening_data = OpeningData::collect_opening_data(keys, storage);
+    queries_from_opening_data(storage, opening_data)
+}
+
+fn queries_from_opening_data<Storage: ReadOnlyHigherDb>(
+    storage: &Storage,
+    opening_data: OpeningData,
+) -> (Vec<ProverQuery>, VerificationHint, Vec<KeyState>) {
     let openings = opening_data.openings;
     let extension_present_by_stem = opening_data.extension_present_by_stem;
     let depths_by_stem = opening_data.depths_by_stem;
+    let key_states = opening_data.key_states;
 
     // Process all of the node openings data and create polynomial queries from them
     // We also collect all of the stems which are in the trie, however they do not have their own proofs
@@ -89,13 +146,6 @@ pub(super) fn create_prover_queries<Storage: ReadOnlyHigherDb>(
                 eo.open_query(false, false)
             }
         };
-        print!("\nopenings: {:?}, prover_q: ", openings);
-        for x in prover_q.iter() {
-            let mut comm_serialised = [0u8; 32];
-            x.commitment.serialize(&mut comm_serialised[..]).unwrap();
-            print!("{} ", hex::encode(comm_serialised));
-        }
-        println!("\n");
         queries.extend(prover_q);
     }
 
@@ -113,5 +163,6 @@ pub(super) fn create_prover_queries<Storage: ReadOnlyHigherDb>(
             extension_present,
             diff_stem_no_proof,
         },
+        key_states,
     )
 }
@@ -1,48 +1,96 @@
 use crate::constants::TWO_POW_128;
+use crate::errors::VerkleError;
 use crate::{committer::Committer, group_to_field, proof::ExtPresent};
 use ark_ff::{One, PrimeField, Zero};
 use bandersnatch::{EdwardsProjective, Fr};
-use std::collections::{BTreeMap, HashSet};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 
 use super::{UpdateHint, VerkleProof};
-// TODO fix all panics and return Results instead
-//
-// TODO: This needs to be modified more, ie to return a correct error variant
-// and to refactor panics into error variants
+
+// What an update does to the value of a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueUpdate {
+    // The key is only read
+    Unchanged,
+    Set([u8; 32]),
+    // The key is removed from the trie, in the same way as `Trie::delete`
+    Delete,
+}
+
 pub fn verify_and_update<C: Committer>(
     proof: VerkleProof,
     root: EdwardsProjective,
     keys: Vec<[u8; 32]>,
     values: Vec<Option<[u8; 32]>>,
-    updated_values: Vec<Option<[u8; 32]>>,
+    updated_values: Vec<ValueUpdate>,
     commiter: C,
-) -> Result<EdwardsProjective, ()> {
+) -> Result<EdwardsProjective, VerkleError> {
     // TODO: replace Clone with references if possible
-    let (ok, update_hint) = proof.check(keys.clone(), values.clone(), root);
-    if !ok {
-        return Err(());
-    }
-    let update_hint =
-        update_hint.expect("update hint should be `Some` if the proof passes verification");
-    let new_root = update_root(update_hint, keys, values, updated_values, root, commiter);
-    Ok(new_root)
+    let update_hint = proof.check(keys.clone(), values.clone(), root)?;
+    update_root(update_hint, keys, values, updated_values, root, commiter)
 }
 
 pub(crate) fn update_root<C: Committer>(
     hint: UpdateHint,
     keys: Vec<[u8; 32]>,
     values: Vec<Option<[u8; 32]>>,
-    updated_values: Vec<Option<[u8; 32]>>,
+    updated_values: Vec<ValueUpdate>,
     root: EdwardsProjective,
     committer: C,
-) -> EdwardsProjective {
-    assert_eq!(values.len(), updated_values.len());
-    assert_eq!(keys.len(), updated_values.len());
+) -> Result<EdwardsProjective, VerkleError> {
+    let (root, _) = update_nodes(hint, keys, values, updated_values, root, &committer)?;
+    Ok(root)
+}
+
+// A stem which was created, moved or modified by an update.
+// C1 and C2 are `None` if they were not modified and the update hint did not have them
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct UpdatedStem {
+    pub(crate) stem: [u8; 31],
+    pub(crate) commitment: EdwardsProjective,
+    pub(crate) c1: Option<EdwardsProjective>,
+    pub(crate) c2: Option<EdwardsProjective>,
+}
+
+// The nodes which an update modified, so that a partial trie can apply the update to its own nodes
+#[derive(Debug, Default)]
+pub(crate) struct UpdatedNodes {
+    // The new commitments of the inner nodes which were modified or created, by path
+    pub(crate) branches: BTreeMap<Vec<u8>, EdwardsProjective>,
+    // The inner nodes which were created by the update. All of their children are known
+    pub(crate) new_branches: BTreeSet<Vec<u8>>,
+    pub(crate) stems: BTreeMap<Vec<u8>, UpdatedStem>,
+    // The paths which no longer have a node, because a stem was deleted or moved up by a collapse
+    pub(crate) removed: BTreeSet<Vec<u8>>,
+}
+
+// Computes the new root, along with every node that the update modified
+pub(crate) fn update_nodes<C: Committer>(
+    hint: UpdateHint,
+    keys: Vec<[u8; 32]>,
+    values: Vec<Option<[u8; 32]>>,
+    updated_values: Vec<ValueUpdate>,
+    root: EdwardsProjective,
+    committer: &C,
+) -> Result<(EdwardsProjective, UpdatedNodes), VerkleError> {
+    if keys.len() != values.len() {
+        return Err(VerkleError::LengthMismatch {
+            keys: keys.len(),
+            values: values.len(),
+        });
+    }
+    if keys.len() != updated_values.len() {
+        return Err(VerkleError::LengthMismatch {
+            keys: keys.len(),
+            values: updated_values.len(),
+        });
+    }
 
     // check that keys are unique
-    let keys_unique = has_unique_elements(keys.iter());
-    // TODO return an error instead of panic
-    assert!(keys_unique);
+    if !has_unique_elements(keys.iter()) {
+        return Err(VerkleError::DuplicateKeys);
+    }
     // TODO Check root against the root in commitments by path
 
     // type Prefix = Vec<u8>;
@@ -57,8 +105,6 @@ pub(crate) fn update_root<C: Committer>(
     // }
     // let all_data: BTreeMap<Prefix, UpdateData> = BTreeMap::new();
 
-    // Maps suffix -> (old_value, new_value)
-    type SuffixUpdate = BTreeMap<u8, (Option<[u8; 32]>, [u8; 32])>;
     // Maps stem -> SuffixUpdate
     let mut updated_stems: BTreeMap<[u8; 31], SuffixUpdate> = BTreeMap::new();
     // First, not all of the keys will need to be updated, so we filter
@@ -68,8 +114,11 @@ pub(crate) fn update_root<C: Committer>(
         let suffix = key[31];
 
         let updated_value = match updated_value {
-            Some(x) => x,
-            None => continue,
+            ValueUpdate::Unchanged => continue,
+            ValueUpdate::Set(value) => Some(value),
+            // Deleting a key which is not in the trie does not change it
+            ValueUpdate::Delete if old_value.is_none() => continue,
+            ValueUpdate::Delete => None,
         };
 
         // if let Some(val) = old_value {
@@ -86,158 +135,74 @@ pub(crate) fn update_root<C: Committer>(
 
     // TODO: Prefix can be &'a [u8] instead of Vec<u8> which avoids unnecessary allocations... This may be unneeded when we switch to SmallVec32
     let mut updated_stems_by_prefix: BTreeMap<Vec<u8>, HashSet<[u8; 31]>> = BTreeMap::new();
-    let mut updated_commitents_by_stem: BTreeMap<[u8; 31], (EdwardsProjective, Fr)> =
-        BTreeMap::new();
+    let mut updated_stems_by_stem: BTreeMap<[u8; 31], UpdatedStem> = BTreeMap::new();
+    // The stems which no longer have any leaves, so they are removed from the trie
+    let mut emptied_stems: BTreeSet<[u8; 31]> = BTreeSet::new();
 
     for (stem, suffix_update) in updated_stems {
-        let (ext_pres, depth) = hint.depths_and_ext_by_stem[&stem];
+        let (ext_pres, depth) = match hint.depths_and_ext_by_stem.get(&stem) {
+            Some(ext_and_depth) => *ext_and_depth,
+            None => {
+                return Err(VerkleError::InvalidProof(
+                    "the update hint is missing a stem",
+                ))
+            }
+        };
+        if depth == 0 || depth as usize > stem.len() {
+            return Err(VerkleError::InvalidProof("stem depth is out of range"));
+        }
         let prefix = stem[0..depth as usize].to_vec();
         updated_stems_by_prefix
             .entry(prefix.clone())
             .or_insert(HashSet::new())
             .insert(stem);
 
-        if ext_pres == ExtPresent::Present {
-            let ext_path = stem[0..depth as usize].to_vec(); // It is the prefix
-
-            let mut C_1_delta_update = EdwardsProjective::default();
-            let mut C_2_delta_update = EdwardsProjective::default();
-
-            // TODO abstract this into a function, since it's duplicated
-            for (suffix, (old_value, new_value)) in suffix_update {
-                // Split values into low_16 and high_16
-                let new_value_low_16 = new_value[0..16].to_vec();
-                let new_value_high_16 = new_value[16..32].to_vec();
-
-                let (old_value_low_16, old_value_high_16) = match old_value {
-                    Some(val) => (
-                        Fr::from_le_bytes_mod_order(&val[0..16]) + TWO_POW_128,
-                        Fr::from_le_bytes_mod_order(&val[16..32]),
-                    ),
-                    None => (Fr::zero(), Fr::zero()), // The extension can be present, but it's suffix can be missing
-                };
-
-                // We need to compute two deltas
-                let delta_low =
-                    Fr::from_le_bytes_mod_order(&new_value_low_16) + TWO_POW_128 - old_value_low_16;
-                let delta_high =
-                    Fr::from_le_bytes_mod_order(&new_value_high_16) - old_value_high_16;
-
-                let position = suffix;
-                let is_c1_comm_update = if position < 128 { true } else { false };
-
-                let pos_mod_128 = position % 128;
-
-                let low_index = 2 * pos_mod_128 as usize;
-                let high_index = low_index + 1;
-
-                let generator_low = committer.scalar_mul(delta_low, low_index);
-                let generator_high = committer.scalar_mul(delta_high, high_index);
-
-                if is_c1_comm_update {
-                    C_1_delta_update += generator_low + generator_high;
-                } else {
-                    C_2_delta_update += generator_low + generator_high;
-                }
-            }
-            // Compute the delta for C1 and C2, so that we can update the extension commitment
-            let mut hash_c1_delta = Fr::zero();
-            let mut hash_c2_delta = Fr::zero();
-            if !C_1_delta_update.is_zero() {
-                let mut c1_path = ext_path.clone();
-                c1_path.push(2);
-
-                let old_c1_comm = hint.commitments_by_path[&c1_path];
-                let new_c1_commitment = old_c1_comm + C_1_delta_update;
-                let hash_c1_new = group_to_field(&new_c1_commitment);
-                let hash_c1_old = group_to_field(&old_c1_comm);
-                hash_c1_delta = hash_c1_new - hash_c1_old;
+        let updated_stem = if ext_pres == ExtPresent::Present {
+            let has_deletions = suffix_update
+                .values()
+                .any(|(_, new_value)| new_value.is_none());
+            let (c1_delta, c2_delta) = suffix_deltas(suffix_update, committer);
+            let updated_stem = update_stem(&hint, stem, prefix, c1_delta, c2_delta, committer)?;
+            if has_deletions && is_empty_stem(&updated_stem, committer) {
+                emptied_stems.insert(stem);
             }
-            if !C_2_delta_update.is_zero() {
-                let mut c2_path = ext_path.clone();
-                c2_path.push(3);
-
-                let old_c2_comm = hint.commitments_by_path[&c2_path];
-                let new_c2_commitment = old_c2_comm + C_2_delta_update;
-                let hash_c2_new = group_to_field(&new_c2_commitment);
-                let hash_c2_old = group_to_field(&old_c2_comm);
-                hash_c2_delta = hash_c2_new - hash_c2_old;
-            }
-
-            let mut stem_comm_update = EdwardsProjective::default();
-            stem_comm_update += committer.scalar_mul(hash_c1_delta, 2);
-            stem_comm_update += committer.scalar_mul(hash_c2_delta, 3);
-
-            let stem_comm_old = hint.commitments_by_path[&ext_path];
-            let stem_comm_new = stem_comm_old + stem_comm_update;
-            let hash_stem_comm_new = group_to_field(&stem_comm_new);
-
-            // Note that we have been given a stem to which we know is in the trie (ext_pres) and
-            // we have computed all of the updates for that particular stem
-            updated_commitents_by_stem.insert(stem, (stem_comm_new, hash_stem_comm_new));
+            updated_stem
         } else {
+            // The stem is either at an empty slot, or it will share the slot with the stem that is there.
+            // In the second case, both stems are moved into a subtree when the prefix is processed
             if ext_pres == ExtPresent::DifferentStem {
-                let other_stem = hint.other_stems_by_prefix[&prefix];
+                let other_stem = match hint.other_stems_by_prefix.get(&prefix) {
+                    Some(other_stem) => *other_stem,
+                    None => {
+                        return Err(VerkleError::InvalidProof(
+                            "the update hint is missing the stem at the prefix",
+                        ))
+                    }
+                };
                 updated_stems_by_prefix
                     .entry(prefix)
                     .or_insert(HashSet::new())
                     .insert(other_stem);
-
-                // Since this stem was not present in the trie, we need to make its initial stem commitment
-                //
-                // This is similar to the case of ExtPres::Present, except that the old_value is zero, so we can ignore it
-                // TODO we could take this for loop out of the if statement and then use the if statement for the rest
-                let mut C_1 = EdwardsProjective::zero();
-                let mut C_2 = EdwardsProjective::zero();
-                for (suffix, (old_value, new_value)) in suffix_update {
-                    assert!(old_value.is_none(), "since the extension was not present in the trie, the suffix cannot have any previous values");
-
-                    // Split values into low_16 and high_16
-                    let new_value_low_16 = new_value[0..16].to_vec();
-                    let new_value_high_16 = new_value[16..32].to_vec();
-
-                    // We need to compute two deltas
-                    let value_low = Fr::from_le_bytes_mod_order(&new_value_low_16) + TWO_POW_128;
-                    let value_high = Fr::from_le_bytes_mod_order(&new_value_high_16);
-
-                    let position = suffix;
-                    let is_c1_comm_update = if position < 128 { true } else { false };
-
-                    let pos_mod_128 = position % 128;
-
-                    let low_index = 2 * pos_mod_128 as usize;
-                    let high_index = low_index + 1;
-
-                    let generator_low = committer.scalar_mul(value_low, low_index);
-                    let generator_high = committer.scalar_mul(value_high, high_index);
-
-                    if is_c1_comm_update {
-                        C_1 += generator_low + generator_high;
-                    } else {
-                        C_2 += generator_low + generator_high;
-                    }
-                }
-
-                let stem_comm_0 = Fr::one(); // TODO: We can get rid of this and just add SRS[0]
-                let stem_comm_1 = Fr::from_le_bytes_mod_order(&stem);
-                let stem_comm_2 = group_to_field(&C_1);
-                let stem_comm_3 = group_to_field(&C_2);
-                let stem_comm = committer.commit_sparse(vec![
-                    (stem_comm_0, 0),
-                    (stem_comm_1, 1),
-                    (stem_comm_2, 2),
-                    (stem_comm_3, 3),
-                ]);
-                let hash_stem_comm = group_to_field(&stem_comm);
-                updated_commitents_by_stem.insert(stem, (stem_comm, hash_stem_comm));
             }
-        }
 
-        //We have now processed all of the necessary extension proof edits that need to be completed.
-        // let recompute the root
+            // Since the extension was not present in the trie, the suffixes cannot have any previous values
+            if suffix_update
+                .values()
+                .any(|(old_value, _)| old_value.is_some())
+            {
+                return Err(VerkleError::InvalidProof(
+                    "value is present, however the proof does not have its stem",
+                ));
+            }
+            // The old values are zero, so the deltas are the new C1 and C2 commitments
+            let (c1, c2) = suffix_deltas(suffix_update, committer);
+            new_stem(stem, c1, c2, committer)
+        };
+        updated_stems_by_stem.insert(stem, updated_stem);
     }
 
     let mut tree = SparseVerkleTree::new(root);
+    let mut updated_nodes = UpdatedNodes::default();
 
     for (prefix, stems) in updated_stems_by_prefix {
         // First fetch the old commitment for this prefix
@@ -247,17 +212,32 @@ pub(crate) fn update_root<C: Committer>(
             None => Fr::zero(),
         };
 
-        if stems.len() == 1 {
-            let stem = stems.iter().next().unwrap();
-            let (_, new_hash_value) = updated_commitents_by_stem[stem];
+        let stems: Vec<[u8; 31]> = stems
+            .into_iter()
+            .filter(|stem| !emptied_stems.contains(stem))
+            .collect();
+        if stems.is_empty() {
+            // The only stem at the prefix was deleted
+            updated_nodes.removed.insert(prefix.clone());
+            tree.update_prefix(
+                &hint.commitments_by_path,
+                committer,
+                prefix,
+                old_hash_value,
+                Fr::zero(),
+            )?;
+        } else if stems.len() == 1 {
+            let updated_stem = updated_stems_by_stem[&stems[0]];
+            let new_hash_value = group_to_field(&updated_stem.commitment);
+            updated_nodes.stems.insert(prefix.clone(), updated_stem);
 
             tree.update_prefix(
                 &hint.commitments_by_path,
-                &committer,
+                committer,
                 prefix.clone(),
                 old_hash_value,
                 new_hash_value,
-            );
+            )?;
         } else {
             // If we have more than one stem to be processed for a prefix, we need to build a subtree and
             // then update the prefix with the root of the subtree
@@ -267,29 +247,339 @@ pub(crate) fn update_root<C: Committer>(
             //
             // Get all of the stems and their commitments
             let mut elements = Vec::new();
+            let mut subtree_stems = BTreeMap::new();
             for stem in stems {
-                let updated_comm = updated_commitents_by_stem.get(&stem);
-                let stem_comm = match updated_comm {
-                    Some((comm, _)) => *comm,
-                    None => hint.commitments_by_path[&prefix],
+                let updated_stem = match updated_stems_by_stem.get(&stem) {
+                    Some(updated_stem) => *updated_stem,
+                    // The stem which was at the prefix is moved down into the subtree
+                    None => match hint.commitments_by_path.get(&prefix) {
+                        Some(commitment) => UpdatedStem {
+                            stem,
+                            commitment: *commitment,
+                            c1: None,
+                            c2: None,
+                        },
+                        None => {
+                            return Err(VerkleError::InvalidProof(
+                                "the update hint is missing a commitment",
+                            ))
+                        }
+                    },
                 };
-                elements.push((stem, stem_comm))
+                elements.push((stem, updated_stem.commitment));
+                subtree_stems.insert(stem, updated_stem);
             }
-            let subtree_root_comm = build_subtree(prefix.clone(), elements, &committer);
+            let (subtree_root_comm, subtree) = build_subtree(prefix.clone(), elements, committer);
             let new_hash_value = group_to_field(&subtree_root_comm);
 
+            for (path, node) in subtree {
+                match node {
+                    SubtreeNode::Inner(commitment) => {
+                        updated_nodes.new_branches.insert(path.clone());
+                        updated_nodes.branches.insert(path, commitment);
+                    }
+                    SubtreeNode::Stem(stem) => {
+                        updated_nodes.stems.insert(path, subtree_stems[&stem]);
+                    }
+                }
+            }
+
             tree.update_prefix(
                 &hint.commitments_by_path,
-                &committer,
+                committer,
                 prefix.clone(),
                 old_hash_value,
                 new_hash_value,
-            );
+            )?;
+        }
+    }
+
+    updated_nodes
+        .branches
+        .extend(tree.updated_commitments_by_path.clone());
+
+    collapse_branches(&hint, &mut tree, &mut updated_nodes, committer)?;
+
+    Ok((tree.root, updated_nodes))
+}
+
+// A node of the trie after the update, as far as the update hint and the update show it
+enum UpdatedNode {
+    Branch(EdwardsProjective),
+    Stem(Box<UpdatedStem>),
+}
+
+impl UpdatedNode {
+    fn commitment(&self) -> EdwardsProjective {
+        match self {
+            UpdatedNode::Branch(commitment) => *commitment,
+            UpdatedNode::Stem(stem) => stem.commitment,
+        }
+    }
+}
+
+// Removing a stem can leave a branch node with a single stem as a child. The trie replaces such a
+// branch node with the stem, and then checks its parent in the same way, so the update does too.
+//
+// The children of a branch node are only known if they were opened by the proof or created by the update.
+// The commitments of the known children are subtracted from the commitment of the branch node, and if
+// nothing is left then they are all of its children. Otherwise the branch node has a child which is not known,
+// so it is only known not to collapse if it also has a known child
+fn collapse_branches<C: Committer>(
+    hint: &UpdateHint,
+    tree: &mut SparseVerkleTree,
+    updated_nodes: &mut UpdatedNodes,
+    committer: &C,
+) -> Result<(), VerkleError> {
+    let mut hint_stems: BTreeMap<Vec<u8>, [u8; 31]> = hint.other_stems_by_prefix.clone();
+    for (stem, (ext_status, depth)) in &hint.depths_and_ext_by_stem {
+        if *ext_status == ExtPresent::Present {
+            hint_stems.insert(stem[0..*depth as usize].to_vec(), *stem);
+        }
+    }
+
+    // The deepest branch nodes are checked first, since collapsing them can collapse their parents.
+    // The root is never collapsed
+    let mut candidates: BTreeSet<(Reverse<usize>, Vec<u8>)> = updated_nodes
+        .removed
+        .iter()
+        .filter(|path| path.len() > 1)
+        .map(|path| (Reverse(path.len() - 1), path[0..path.len() - 1].to_vec()))
+        .collect();
+
+    while let Some(candidate) = candidates.iter().next().cloned() {
+        candidates.remove(&candidate);
+        let (_, branch_path) = candidate;
+
+        let branch_comm = match node_after_update(hint, &hint_stems, updated_nodes, &branch_path) {
+            Some(UpdatedNode::Branch(commitment)) => commitment,
+            _ => continue,
+        };
+
+        let mut remainder = branch_comm;
+        let mut children = Vec::new();
+        for index in 0..=u8::MAX {
+            let mut child_path = branch_path.clone();
+            child_path.push(index);
+            if let Some(node) = node_after_update(hint, &hint_stems, updated_nodes, &child_path) {
+                remainder -=
+                    committer.scalar_mul(group_to_field(&node.commitment()), index as usize);
+                children.push((child_path, node));
+            }
+        }
+        if !remainder.is_zero() {
+            if children.is_empty() {
+                return Err(VerkleError::KeyNotProven);
+            }
+            continue;
+        }
+
+        let new_hash_value = match children.as_slice() {
+            // All of the children were deleted
+            [] => {
+                updated_nodes.removed.insert(branch_path.clone());
+                Fr::zero()
+            }
+            [(child_path, UpdatedNode::Stem(stem))] => {
+                updated_nodes.stems.remove(child_path);
+                updated_nodes.removed.insert(child_path.clone());
+                updated_nodes.stems.insert(branch_path.clone(), **stem);
+                group_to_field(&stem.commitment)
+            }
+            _ => continue,
+        };
+        updated_nodes.branches.remove(&branch_path);
+        updated_nodes.new_branches.remove(&branch_path);
+        tree.updated_commitments_by_path.remove(&branch_path);
+
+        tree.update_prefix(
+            &hint.commitments_by_path,
+            committer,
+            branch_path.clone(),
+            group_to_field(&branch_comm),
+            new_hash_value,
+        )?;
+        // The ancestors of the branch node are only updated in the sparse tree
+        for (path, commitment) in &tree.updated_commitments_by_path {
+            updated_nodes.branches.insert(path.clone(), *commitment);
+        }
+
+        if branch_path.len() > 1 {
+            let parent = branch_path[0..branch_path.len() - 1].to_vec();
+            candidates.insert((Reverse(parent.len()), parent));
+        }
+    }
+    Ok(())
+}
+
+// The node at the path after the update, or `None` if there is no node there or it is not known
+fn node_after_update(
+    hint: &UpdateHint,
+    hint_stems: &BTreeMap<Vec<u8>, [u8; 31]>,
+    updated_nodes: &UpdatedNodes,
+    path: &[u8],
+) -> Option<UpdatedNode> {
+    if updated_nodes.removed.contains(path) {
+        return None;
+    }
+    if let Some(stem) = updated_nodes.stems.get(path) {
+        return Some(UpdatedNode::Stem(Box::new(*stem)));
+    }
+    if let Some(commitment) = updated_nodes.branches.get(path) {
+        return Some(UpdatedNode::Branch(*commitment));
+    }
+    let commitment = *hint.commitments_by_path.get(path)?;
+    match hint_stems.get(path) {
+        Some(stem) => Some(UpdatedNode::Stem(Box::new(UpdatedStem {
+            stem: *stem,
+            commitment,
+            c1: None,
+            c2: None,
+        }))),
+        None => Some(UpdatedNode::Branch(commitment)),
+    }
+}
+
+// Maps suffix -> (old_value, new_value), where a new value of `None` deletes the key
+type SuffixUpdate = BTreeMap<u8, (Option<[u8; 32]>, Option<[u8; 32]>)>;
+
+// Computes the change in C1 and C2 from updating the values at the suffixes of a stem.
+// A value which is not in the trie contributes zero, and a value which is zero
+// still contributes 2^128, since it is a value which is in the trie
+fn suffix_deltas<C: Committer>(
+    suffix_update: SuffixUpdate,
+    committer: &C,
+) -> (EdwardsProjective, EdwardsProjective) {
+    let mut c1_delta = EdwardsProjective::zero();
+    let mut c2_delta = EdwardsProjective::zero();
+
+    for (suffix, (old_value, new_value)) in suffix_update {
+        // Split values into low_16 and high_16
+        let (old_value_low_16, old_value_high_16) = match old_value {
+            Some(val) => (
+                Fr::from_le_bytes_mod_order(&val[0..16]) + TWO_POW_128,
+                Fr::from_le_bytes_mod_order(&val[16..32]),
+            ),
+            None => (Fr::zero(), Fr::zero()), // The extension can be present, but it's suffix can be missing
+        };
+
+        let (new_value_low_16, new_value_high_16) = match new_value {
+            Some(val) => (
+                Fr::from_le_bytes_mod_order(&val[0..16]) + TWO_POW_128,
+                Fr::from_le_bytes_mod_order(&val[16..32]),
+            ),
+            // The old value is subtracted from the commitment when the key is deleted
+            None => (Fr::zero(), Fr::zero()),
+        };
+
+        // We need to compute two deltas
+        let delta_low = new_value_low_16 - old_value_low_16;
+        let delta_high = new_value_high_16 - old_value_high_16;
+
+        let pos_mod_128 = suffix % 128;
+        let low_index = 2 * pos_mod_128 as usize;
+        let high_index = low_index + 1;
+
+        let delta = committer.scalar_mul(delta_low, low_index)
+            + committer.scalar_mul(delta_high, high_index);
+        if suffix < 128 {
+            c1_delta += delta;
+        } else {
+            c2_delta += delta;
+        }
+    }
+
+    (c1_delta, c2_delta)
+}
+
+// Applies the change in C1 and C2 to a stem which is in the trie at `ext_path`
+fn update_stem<C: Committer>(
+    hint: &UpdateHint,
+    stem: [u8; 31],
+    ext_path: Vec<u8>,
+    c1_delta: EdwardsProjective,
+    c2_delta: EdwardsProjective,
+    committer: &C,
+) -> Result<UpdatedStem, VerkleError> {
+    let missing_commitment =
+        || VerkleError::InvalidProof("the update hint is missing a commitment");
+
+    let mut stem_comm_new = match hint.commitments_by_path.get(&ext_path) {
+        Some(comm) => *comm,
+        None => return Err(missing_commitment()),
+    };
+    let mut updated_stem = UpdatedStem {
+        stem,
+        commitment: stem_comm_new,
+        c1: None,
+        c2: None,
+    };
+
+    // C1 is at index 2 of the stem commitment and C2 is at index 3.
+    // If only one half of the stem was opened, the commitment to the other half is not in the hint
+    for (index, delta) in [(2u8, c1_delta), (3u8, c2_delta)] {
+        let mut suffix_path = ext_path.clone();
+        suffix_path.push(index);
+        let old_comm = hint.commitments_by_path.get(&suffix_path).copied();
+
+        let new_comm = if delta.is_zero() {
+            old_comm
+        } else {
+            let old_comm = old_comm.ok_or_else(missing_commitment)?;
+            let new_comm = old_comm + delta;
+            let hash_delta = group_to_field(&new_comm) - group_to_field(&old_comm);
+            stem_comm_new += committer.scalar_mul(hash_delta, index as usize);
+            Some(new_comm)
+        };
+        if index == 2 {
+            updated_stem.c1 = new_comm;
+        } else {
+            updated_stem.c2 = new_comm;
         }
     }
 
-    // There are two types of updates that we need to distinguish, an update where the key was None (Other stem) and an update where the key was some
-    tree.root
+    updated_stem.commitment = stem_comm_new;
+    Ok(updated_stem)
+}
+
+// A stem without any leaves only commits to the marker and the stem, since C1 and C2 are zero
+fn is_empty_stem<C: Committer>(updated_stem: &UpdatedStem, committer: &C) -> bool {
+    let empty_stem_comm = committer.commit_sparse(vec![
+        (Fr::one(), 0),
+        (Fr::from_le_bytes_mod_order(&updated_stem.stem), 1),
+    ]);
+    updated_stem.commitment == empty_stem_comm
+}
+
+// Commits to a stem which was not in the trie
+fn new_stem<C: Committer>(
+    stem: [u8; 31],
+    c1: EdwardsProjective,
+    c2: EdwardsProjective,
+    committer: &C,
+) -> UpdatedStem {
+    let stem_comm_0 = Fr::one(); // TODO: We can get rid of this and just add SRS[0]
+    let stem_comm_1 = Fr::from_le_bytes_mod_order(&stem);
+    let stem_comm_2 = group_to_field(&c1);
+    let stem_comm_3 = group_to_field(&c2);
+    let commitment = committer.commit_sparse(vec![
+        (stem_comm_0, 0),
+        (stem_comm_1, 1),
+        (stem_comm_2, 2),
+        (stem_comm_3, 3),
+    ]);
+    UpdatedStem {
+        stem,
+        commitment,
+        c1: Some(c1),
+        c2: Some(c2),
+    }
+}
+
+// A node of a subtree built by `build_subtree`, with its commitment if it is an inner node
+enum SubtreeNode {
+    Inner(EdwardsProjective),
+    Stem([u8; 31]),
 }
 
 // Build a subtree from a set of stems and their commitments
@@ -304,11 +594,13 @@ pub(crate) fn update_root<C: Committer>(
 //
 // We could _not_ pass in the prefix and slice off stem[prefix.len()..], then compute the root
 // as if we were starting from a tree of depth=0
+//
+// Returns the root of the subtree and all of its nodes, by their path from the root of the trie
 fn build_subtree<C: Committer>(
     prefix: Vec<u8>,
     elements: Vec<([u8; 31], EdwardsProjective)>,
     committer: &C,
-) -> EdwardsProjective {
+) -> (EdwardsProjective, BTreeMap<Vec<u8>, SubtreeNode>) {
     let mut tree: BTreeMap<Vec<u8>, Node> = BTreeMap::new();
 
     // Insert the root
@@ -362,9 +654,8 @@ fn build_subtree<C: Committer>(
     }
 
     for (stem, commitment) in elements {
-        let mut depth = prefix.len();
         // Everything before the prefix is irrelevant to the subtree
-        let relative_stem = &stem[depth..];
+        let mut depth = prefix.len();
 
         let mut path = vec![];
         let mut current_node = tree[&path].clone();
@@ -494,7 +785,20 @@ fn build_subtree<C: Committer>(
         }
     }
 
-    tree.get(&vec![]).unwrap().inner().commitment
+    let root = tree.get(&vec![]).unwrap().inner().commitment;
+    let nodes = tree
+        .into_iter()
+        .map(|(path, node)| {
+            let mut full_path = prefix.clone();
+            full_path.extend(path);
+            let node = match node {
+                Node::Inner(inner) => SubtreeNode::Inner(inner.commitment),
+                Node::Stem(stem) => SubtreeNode::Stem(stem.id),
+            };
+            (full_path, node)
+        })
+        .collect();
+    (root, nodes)
 }
 
 struct SparseVerkleTree {
@@ -510,6 +814,7 @@ impl SparseVerkleTree {
         }
     }
 
+    // Updates the inner nodes above the prefix, after the hash of the node at the prefix changed from `old_value` to `new_value`
     fn update_prefix<C: Committer>(
         &mut self,
         commitments_by_path: &BTreeMap<Vec<u8>, EdwardsProjective>,
@@ -517,31 +822,37 @@ impl SparseVerkleTree {
         mut prefix: Vec<u8>,
         old_value: Fr,
         new_value: Fr,
-    ) {
-        // TODO check edge case, when prefix.is_empty is passed in
+    ) -> Result<(), VerkleError> {
+        // The root is always an inner node, so a stem or a subtree cannot replace it
+        if prefix.is_empty() {
+            return Err(VerkleError::InvalidProof(
+                "cannot update the root as a stem",
+            ));
+        }
 
         // First lets compute the delta between the old_value and the new value
         let mut delta = new_value - old_value;
 
-        let mut current_parent_comm = None;
-
         // Now lets fetch the parent node's commitment and recursively update each parent
-
-        while !prefix.is_empty() {
-            let child_index = prefix.pop().unwrap();
+        while let Some(child_index) = prefix.pop() {
             // If we have never updated the parent node before,
             // then it will be the old commitment
             // If we have then it will be in updated commitments
-            let parent_comm = self.updated_commitments_by_path.get(&prefix);
-            let old_parent_comm = match parent_comm {
+            let old_parent_comm = match self.updated_commitments_by_path.get(&prefix) {
                 Some(comm) => *comm,
-                None => commitments_by_path[&prefix],
+                None => match commitments_by_path.get(&prefix) {
+                    Some(comm) => *comm,
+                    None => {
+                        return Err(VerkleError::InvalidProof(
+                            "the update hint is missing an inner node",
+                        ))
+                    }
+                },
             };
 
             // Update the parent_comm at the child index
             let comm_update = committer.scalar_mul(delta, child_index as usize);
             let new_parent_comm = old_parent_comm + comm_update;
-            current_parent_comm = Some(new_parent_comm);
 
             self.updated_commitments_by_path
                 .insert(prefix.clone(), new_parent_comm);
@@ -549,7 +860,8 @@ impl SparseVerkleTree {
             delta = group_to_field(&new_parent_comm) - group_to_field(&old_parent_comm)
         }
 
-        self.root = current_parent_comm.unwrap();
+        self.root = self.updated_commitments_by_path[&prefix];
+        Ok(())
     }
 }
 
@@ -572,14 +884,16 @@ mod test {
     use crate::database::memory_db::MemoryDb;
     use crate::database::ReadOnlyHigherDb;
     use crate::proof::prover;
-    use crate::proof::stateless_updater::update_root;
+    use crate::proof::stateless_updater::{update_root, verify_and_update, ValueUpdate};
+    use crate::trie_fuzzer::BasicPRNG;
+    use crate::VerkleError;
     use crate::{committer::test::TestCommitter, trie::Trie, TrieTrait};
     use crate::{group_to_field, TestConfig};
 
     #[test]
     fn basic_update() {
         let db = MemoryDb::new();
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let mut keys = Vec::new();
         for i in 0..2 {
@@ -591,19 +905,21 @@ mod test {
         let root = vec![];
         let meta = trie.storage.get_branch_meta(&root).unwrap();
 
-        let proof = prover::create_verkle_proof(&trie.storage, keys.clone());
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone()).unwrap();
         let values: Vec<_> = keys.iter().map(|val| Some(*val)).collect();
-        let (ok, updated_hint) = proof.check(keys.clone(), values.clone(), meta.commitment);
-        assert!(ok);
+        let updated_hint = proof
+            .check(keys.clone(), values.clone(), meta.commitment)
+            .unwrap();
 
         let new_root_comm = update_root(
-            updated_hint.unwrap(),
+            updated_hint,
             keys.clone(),
             values,
-            vec![Some([0u8; 32]), None],
+            vec![ValueUpdate::Set([0u8; 32]), ValueUpdate::Unchanged],
             meta.commitment,
             TestCommitter::default(),
-        );
+        )
+        .unwrap();
 
         let mut got_bytes = [0u8; 32];
         group_to_field(&new_root_comm)
@@ -620,7 +936,7 @@ mod test {
     #[test]
     fn basic_update_using_subtree() {
         let db = MemoryDb::new();
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let key_a = [0u8; 32];
         trie.insert_single(key_a, key_a);
@@ -637,20 +953,26 @@ mod test {
         let root = vec![];
         let meta = trie.storage.get_branch_meta(&root).unwrap();
 
-        let proof = prover::create_verkle_proof(&trie.storage, keys.clone());
-        let (ok, updated_hint) = proof.check(keys.clone(), values.clone(), meta.commitment);
-        assert!(ok);
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone()).unwrap();
+        let updated_hint = proof
+            .check(keys.clone(), values.clone(), meta.commitment)
+            .unwrap();
 
-        let updated_values = vec![None, None, Some(key_c)];
+        let updated_values = vec![
+            ValueUpdate::Unchanged,
+            ValueUpdate::Unchanged,
+            ValueUpdate::Set(key_c),
+        ];
 
         let new_root_comm = update_root(
-            updated_hint.unwrap(),
+            updated_hint,
             keys,
             values,
             updated_values,
             meta.commitment,
             TestCommitter::default(),
-        );
+        )
+        .unwrap();
 
         let mut got_bytes = [0u8; 32];
         group_to_field(&new_root_comm)
@@ -668,7 +990,7 @@ mod test {
     fn basic_update3() {
         // traverse the subtree twice
         let db = MemoryDb::new();
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let key_a = [0u8; 32];
         trie.insert_single(key_a, key_a);
@@ -678,30 +1000,32 @@ mod test {
 
         let mut keys = vec![key_a, key_b];
         let mut values = vec![Some(key_a), Some(key_b)];
-        let mut updated_values = vec![None, None];
+        let mut updated_values = vec![ValueUpdate::Unchanged, ValueUpdate::Unchanged];
         for i in 1..=30 {
             let mut key = [0u8; 32];
             key[i] = 1;
             keys.push(key);
             values.push(None);
-            updated_values.push(Some(key))
+            updated_values.push(ValueUpdate::Set(key))
         }
 
         let root = vec![];
         let meta = trie.storage.get_branch_meta(&root).unwrap();
 
-        let proof = prover::create_verkle_proof(&trie.storage, keys.clone());
-        let (ok, updated_hint) = proof.check(keys.clone(), values.clone(), meta.commitment);
-        assert!(ok);
+        let proof = prover::create_verkle_proof(&trie.storage, keys.clone()).unwrap();
+        let updated_hint = proof
+            .check(keys.clone(), values.clone(), meta.commitment)
+            .unwrap();
 
         let new_root_comm = update_root(
-            updated_hint.unwrap(),
+            updated_hint,
             keys.clone(),
             values,
             updated_values,
             meta.commitment,
             TestCommitter::default(),
-        );
+        )
+        .unwrap();
 
         let mut got_bytes = [0u8; 32];
         group_to_field(&new_root_comm)
@@ -718,4 +1042,230 @@ mod test {
         expected_root.serialize(&mut expected_bytes[..]).unwrap();
         assert_eq!(got_bytes, expected_bytes)
     }
+
+    // Updates the keys statelessly, from a proof over the keys, and checks that the new root
+    // is the root of the trie after the same values are inserted and deleted
+    fn assert_update_matches_trie(
+        trie: &mut Trie<MemoryDb, TestCommitter>,
+        keys: Vec<[u8; 32]>,
+        updated_values: Vec<ValueUpdate>,
+    ) {
+        let root = trie.root_commitment();
+        let values: Vec<_> = keys.iter().map(|key| trie.get(*key)).collect();
+        let proof = trie.create_verkle_proof(keys.clone().into_iter()).unwrap();
+
+        let new_root = verify_and_update(
+            proof,
+            root,
+            keys.clone(),
+            values,
+            updated_values.clone(),
+            TestCommitter::default(),
+        )
+        .unwrap();
+
+        for (key, updated_value) in keys.into_iter().zip(updated_values) {
+            match updated_value {
+                ValueUpdate::Unchanged => {}
+                ValueUpdate::Set(value) => trie.insert_single(key, value),
+                ValueUpdate::Delete => {
+                    trie.delete(key);
+                }
+            }
+        }
+        assert_eq!(new_root, trie.root_commitment());
+    }
+
+    #[test]
+    fn insertion_topologies() {
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[1] = 1;
+        trie.insert(vec![(key_a, [1; 32]), (key_b, [2; 32])].into_iter());
+
+        // An empty slot of the root
+        let root_slot = [5u8; 32];
+        // An empty slot of the inner node at depth 1
+        let mut deep_slot = [0u8; 32];
+        deep_slot[1] = 9;
+        // Two new stems in the same empty slot
+        let mut shared_slot_a = [0u8; 32];
+        shared_slot_a[1] = 7;
+        let mut shared_slot_b = shared_slot_a;
+        shared_slot_b[20] = 1;
+        // The same stem as a key in the trie
+        let mut same_stem = key_a;
+        same_stem[31] = 200;
+        // A stem which shares 29 bytes with key_b, so a chain of inner nodes is needed
+        let mut chain = key_b;
+        chain[29] = 1;
+
+        let keys = vec![
+            key_a,
+            root_slot,
+            deep_slot,
+            shared_slot_a,
+            shared_slot_b,
+            same_stem,
+            chain,
+        ];
+        let updated_values = vec![
+            // Setting a value to zero keeps the key in the trie
+            ValueUpdate::Set([0; 32]),
+            ValueUpdate::Set([3; 32]),
+            ValueUpdate::Set([4; 32]),
+            ValueUpdate::Set([5; 32]),
+            ValueUpdate::Set([0; 32]),
+            ValueUpdate::Set([6; 32]),
+            ValueUpdate::Set([7; 32]),
+        ];
+        assert_update_matches_trie(&mut trie, keys, updated_values);
+
+        // Keys which are only read, along with a key at a stem which was created by the last update
+        let mut on_new_stem = chain;
+        on_new_stem[31] = 1;
+        let keys = vec![key_b, root_slot, on_new_stem, [9; 32]];
+        let updated_values = vec![
+            ValueUpdate::Unchanged,
+            ValueUpdate::Set([8; 32]),
+            ValueUpdate::Set([9; 32]),
+            ValueUpdate::Unchanged,
+        ];
+        assert_update_matches_trie(&mut trie, keys, updated_values);
+    }
+
+    #[test]
+    fn deletions() {
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[31] = 200;
+        // A stem which shares 5 bytes with the stem of key_a, so there is a chain of branch nodes above them
+        let mut key_c = [0u8; 32];
+        key_c[5] = 1;
+        let key_d = [1u8; 32];
+
+        let new_trie = || {
+            let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+            for key in [key_a, key_b, key_c, key_d] {
+                trie.insert_single(key, key);
+            }
+            trie
+        };
+
+        // A leaf of a stem which keeps its other leaf, and a key which is not in the trie
+        let mut trie = new_trie();
+        let keys = vec![key_b, [2u8; 32]];
+        let updated_values = vec![ValueUpdate::Delete, ValueUpdate::Delete];
+        assert_update_matches_trie(&mut trie, keys, updated_values);
+
+        // The stem of key_c is removed, so the chain collapses and the stem of key_a moves up to the root
+        let mut trie = new_trie();
+        let keys = vec![key_a, key_c];
+        let updated_values = vec![ValueUpdate::Unchanged, ValueUpdate::Delete];
+        assert_update_matches_trie(&mut trie, keys, updated_values);
+
+        // Every leaf is deleted
+        let mut trie = new_trie();
+        let keys = vec![key_a, key_b, key_c, key_d];
+        let updated_values = vec![ValueUpdate::Delete; 4];
+        assert_update_matches_trie(&mut trie, keys, updated_values);
+
+        // A stem is emptied and a new stem takes its place, along with a stem below it
+        let mut trie = new_trie();
+        let mut key_e = key_d;
+        key_e[2] = 7;
+        let mut key_f = key_d;
+        key_f[1] = 2;
+        let keys = vec![key_d, key_e, key_f];
+        let updated_values = vec![
+            ValueUpdate::Delete,
+            ValueUpdate::Set([5; 32]),
+            ValueUpdate::Set([6; 32]),
+        ];
+        assert_update_matches_trie(&mut trie, keys, updated_values);
+
+        // Without key_a, the proof does not show whether the chain above key_c collapses
+        let trie = new_trie();
+        let root = trie.root_commitment();
+        let proof = trie.create_verkle_proof(vec![key_c].into_iter()).unwrap();
+        let result = verify_and_update(
+            proof,
+            root,
+            vec![key_c],
+            vec![Some(key_c)],
+            vec![ValueUpdate::Delete],
+            TestCommitter::default(),
+        );
+        assert!(matches!(result, Err(VerkleError::KeyNotProven)));
+    }
+
+    // A key which shares a prefix of a random length with one of `keys`,
+    // and one of a few suffixes so that keys often share a stem
+    fn random_key(prng: &mut BasicPRNG, keys: &[[u8; 32]]) -> [u8; 32] {
+        let bytes = prng.rand_bytes();
+        if keys.is_empty() {
+            return bytes;
+        }
+        let mut key = keys[bytes[0] as usize % keys.len()];
+        let shared = if bytes[2] % 3 == 0 {
+            31
+        } else {
+            bytes[1] as usize % 31
+        };
+        key[shared..31].copy_from_slice(&bytes[shared..31]);
+        key[31] = bytes[31] % 4;
+        key
+    }
+
+    fn random_value(prng: &mut BasicPRNG) -> [u8; 32] {
+        let value = prng.rand_bytes();
+        if value[0] % 4 == 0 {
+            [0; 32]
+        } else {
+            value
+        }
+    }
+
+    #[test]
+    fn random_updates_match_the_trie() {
+        let mut prng = BasicPRNG::default();
+        for _ in 0..16 {
+            let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+            let mut keys_in_trie = Vec::new();
+            for _ in 0..8 {
+                let key = random_key(&mut prng, &keys_in_trie);
+                trie.insert_single(key, random_value(&mut prng));
+                keys_in_trie.push(key);
+            }
+
+            // Keys in the trie, and keys around them. Some of the keys are only read
+            let mut keys = Vec::new();
+            let mut updated_values = Vec::new();
+            for _ in 0..8 {
+                let key = random_key(&mut prng, &keys_in_trie);
+                if keys.contains(&key) {
+                    continue;
+                }
+                let value = random_value(&mut prng);
+                keys.push(key);
+                updated_values.push(match value[1] % 4 {
+                    0 => ValueUpdate::Unchanged,
+                    1 => ValueUpdate::Delete,
+                    _ => ValueUpdate::Set(value),
+                });
+            }
+
+            // Deleting a stem can collapse its parent, which the proof only shows if it opens the other children
+            if updated_values.contains(&ValueUpdate::Delete) {
+                for key in &keys_in_trie {
+                    if !keys.contains(key) {
+                        keys.push(*key);
+                        updated_values.push(ValueUpdate::Unchanged);
+                    }
+                }
+            }
+            assert_update_matches_trie(&mut trie, keys, updated_values);
+        }
+    }
 }
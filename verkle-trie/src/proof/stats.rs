@@ -0,0 +1,184 @@
+use super::execution_witness::IPA_PROOF_DEPTH;
+use super::opening_data::{OpeningData, Openings};
+use super::validation::POINT_SIZE;
+use super::{encoding, ExtPresent, VerificationHint, VerkleProof};
+use crate::errors::VerkleError;
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+
+// The commitment to g(x), the L and R points of the IPA proof and the final scalar
+pub(crate) const MULTIPOINT_PROOF_SIZE: usize = (2 * IPA_PROOF_DEPTH + 2) * POINT_SIZE;
+
+// The composition of a proof, and how its encoding is split between its parts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProofStats {
+    // The number of inner nodes which are opened
+    pub branch_openings: usize,
+    // The number of stems which are only opened to show that a key is at a different stem
+    pub extension_openings: usize,
+    // The number of stems which are opened at the suffixes of keys
+    pub suffix_openings: usize,
+    // The number of commitments in the proof, which does not include the root
+    pub unique_commitments: usize,
+    // The deepest stem in the proof, or the deepest inner node if no stem is found
+    pub max_depth: u8,
+    pub hint_bytes: usize,
+    pub commitment_bytes: usize,
+    pub multipoint_proof_bytes: usize,
+}
+
+// The openings which the prover creates for a set of keys
+pub(crate) struct OpeningCounts {
+    branch_openings: usize,
+    extension_openings: usize,
+    suffix_openings: usize,
+    max_depth: u8,
+}
+
+impl OpeningCounts {
+    pub(crate) fn from_opening_data(opening_data: &OpeningData) -> OpeningCounts {
+        let mut counts = OpeningCounts {
+            branch_openings: 0,
+            extension_openings: 0,
+            suffix_openings: 0,
+            max_depth: opening_data
+                .depths_by_stem
+                .values()
+                .copied()
+                .max()
+                .unwrap_or(0),
+        };
+        for openings in opening_data.openings.values() {
+            match openings {
+                Openings::Branch(_) => counts.branch_openings += 1,
+                Openings::Extension(_) => counts.extension_openings += 1,
+                Openings::Suffix(_) => counts.suffix_openings += 1,
+            }
+        }
+        counts
+    }
+}
+
+impl ProofStats {
+    pub(crate) fn new(
+        counts: OpeningCounts,
+        verification_hint: &VerificationHint,
+        num_commitments: usize,
+        multipoint_proof_bytes: usize,
+    ) -> Result<ProofStats, VerkleError> {
+        let mut hint_bytes = Vec::new();
+        verification_hint.write(&mut hint_bytes)?;
+
+        Ok(ProofStats {
+            branch_openings: counts.branch_openings,
+            extension_openings: counts.extension_openings,
+            suffix_openings: counts.suffix_openings,
+            unique_commitments: num_commitments,
+            max_depth: counts.max_depth,
+            hint_bytes: hint_bytes.len(),
+            commitment_bytes: num_commitments * POINT_SIZE,
+            multipoint_proof_bytes,
+        })
+    }
+
+    // The size of the proof in the versioned encoding, without its keys and values
+    pub fn encoded_size(&self) -> usize {
+        encoding::HEADER_SIZE
+            + self.hint_bytes
+            + self.commitment_bytes
+            + self.multipoint_proof_bytes
+    }
+}
+
+impl VerkleProof {
+    // Returns the statistics of a proof over `keys`.
+    // Which nodes were opened is not in the proof, it is derived from the keys and the verification hint
+    // in the same way as the verifier does
+    pub fn stats(&self, keys: &[[u8; 32]]) -> Result<ProofStats, VerkleError> {
+        let stems: BTreeSet<[u8; 31]> = keys
+            .iter()
+            .map(|key| key[0..31].try_into().unwrap())
+            .collect();
+        let hint = &self.verification_hint;
+        if stems.len() != hint.depths.len() || stems.len() != hint.extension_present.len() {
+            return Err(VerkleError::InvalidProof(
+                "the number of depths does not match the number of stems",
+            ));
+        }
+
+        // A stem at depth `d` has `d` inner nodes above it, which is also the case for the
+        // empty slot that a key is shown to be absent at
+        let mut branch_paths = BTreeSet::new();
+        let mut suffix_openings = 0;
+        for ((stem, depth), ext_status) in
+            stems.iter().zip(&hint.depths).zip(&hint.extension_present)
+        {
+            if *depth as usize > stem.len() {
+                return Err(VerkleError::InvalidProof("stem depth is out of range"));
+            }
+            for i in 0..*depth as usize {
+                branch_paths.insert(&stem[0..i]);
+            }
+            if *ext_status == ExtPresent::Present {
+                suffix_openings += 1;
+            }
+        }
+
+        let counts = OpeningCounts {
+            branch_openings: branch_paths.len(),
+            extension_openings: hint.diff_stem_no_proof.len(),
+            suffix_openings,
+            max_depth: hint.depths.iter().copied().max().unwrap_or(0),
+        };
+        ProofStats::new(
+            counts,
+            hint,
+            self.comms_sorted.len(),
+            self.proof.to_bytes()?.len(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::memory_db::MemoryDb;
+    use crate::{TestConfig, Trie, TrieTrait};
+
+    #[test]
+    fn stats_match_the_proof() {
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        trie.insert_single([0; 32], [1; 32]);
+        trie.insert_single([5; 32], [2; 32]);
+        // Two stems which share 30 bytes, so there are inner nodes down to a depth of 31
+        trie.insert_single([1; 32], [3; 32]);
+        let mut same_prefix = [1u8; 32];
+        same_prefix[30] = 0;
+        trie.insert_single(same_prefix, [4; 32]);
+
+        // A key which is in the trie and a key which is at its stem, which only need one suffix opening
+        let mut at_opened_stem = [0u8; 32];
+        at_opened_stem[30] = 1;
+        // A key which is at a stem that is not otherwise opened
+        let mut at_other_stem = [5u8; 32];
+        at_other_stem[30] = 6;
+        // A key in an empty slot at the root, and one in an empty slot of the inner node at depth 29
+        let mut deep_absent = [1u8; 32];
+        deep_absent[29] = 7;
+        let keys = vec![[0; 32], at_opened_stem, at_other_stem, [2; 32], deep_absent];
+
+        let estimate = trie.estimate_proof_stats(keys.clone().into_iter()).unwrap();
+        let proof = trie.create_verkle_proof(keys.clone().into_iter()).unwrap();
+        let stats = proof.stats(&keys).unwrap();
+        assert_eq!(stats, estimate);
+
+        // The root and the inner nodes on the path to the deep absent key
+        assert_eq!(stats.branch_openings, 30);
+        assert_eq!(stats.suffix_openings, 1);
+        assert_eq!(stats.extension_openings, 1);
+        assert_eq!(stats.max_depth, 30);
+
+        let mut bytes = Vec::new();
+        proof.write(&mut bytes).unwrap();
+        assert_eq!(stats.encoded_size(), bytes.len());
+    }
+}
@@ -0,0 +1,277 @@
+use crate::errors::{PointError, VerkleError};
+use ark_ec::{models::TEModelParameters, ProjectiveCurve};
+use ark_ff::{BigInteger, Field, One, PrimeField, SquareRootField, Zero};
+use ark_serialize::{CanonicalDeserializeWithFlags, CanonicalSerialize, EdwardsFlags};
+use bandersnatch::{EdwardsAffine, EdwardsParameters, EdwardsProjective, Fq};
+
+// The points in a proof are chosen by the prover, so the verifier cannot assume that they are
+// elements of the prime order subgroup. Bandersnatch has a cofactor of 4, so a point which is on the curve
+// can still have a small order component, which would let a malicious prover open a commitment to
+// more than one value. Every point is therefore checked to be:
+// - the canonical encoding of its x coordinate and sign, so that a point has exactly one encoding
+// - on the curve
+// - in the prime order subgroup
+//
+// The identity is accepted, since it is the commitment to an empty polynomial.
+
+pub(crate) const POINT_SIZE: usize = 32;
+
+// Decodes a compressed point, rejecting anything which is not the canonical encoding of a subgroup element
+pub(crate) fn decode_point(bytes: &[u8]) -> Result<EdwardsAffine, VerkleError> {
+    if bytes.len() != POINT_SIZE {
+        return Err(VerkleError::InvalidPoint(PointError::NonCanonical));
+    }
+    // This fails if the x coordinate is not less than the modulus
+    let (x, flags) = Fq::deserialize_with_flags::<_, EdwardsFlags>(bytes)
+        .map_err(|_| VerkleError::InvalidPoint(PointError::NonCanonical))?;
+
+    let point = if x.is_zero() {
+        EdwardsAffine::zero()
+    } else {
+        EdwardsAffine::get_point_from_x(x, flags.is_positive())
+            .ok_or(VerkleError::InvalidPoint(PointError::NotOnCurve))?
+    };
+    check_point(&point)?;
+
+    // The identity can be encoded with either sign, only the encoding produced by serialisation is accepted
+    let mut encoded = [0u8; POINT_SIZE];
+    point.serialize(&mut encoded[..])?;
+    if encoded[..] != *bytes {
+        return Err(VerkleError::InvalidPoint(PointError::NonCanonical));
+    }
+
+    Ok(point)
+}
+
+// Banderwagon is the quotient of the curve by the point of order two (0, -1), so a point (x, y) and
+// (-x, -y) are the same element. It is the encoding which other clients use for the points in the
+// witness of EIP-6800: the x coordinate in big endian, of the representative whose y coordinate is
+// lexicographically largest.
+pub(crate) fn encode_banderwagon(point: &EdwardsProjective) -> [u8; POINT_SIZE] {
+    let point = point.into_affine();
+    let x = if point.y > -point.y {
+        point.x
+    } else {
+        -point.x
+    };
+
+    let mut bytes = [0u8; POINT_SIZE];
+    bytes.copy_from_slice(&x.into_repr().to_bytes_be());
+    bytes
+}
+
+// Decodes a Banderwagon element into the representative which is in the prime order subgroup.
+// Only x coordinates for which 1 - ax^2 is a square are elements of the group
+pub(crate) fn decode_banderwagon(bytes: &[u8; POINT_SIZE]) -> Result<EdwardsAffine, VerkleError> {
+    let x = Fq::from_be_bytes_mod_order(bytes);
+    if x.into_repr().to_bytes_be()[..] != bytes[..] {
+        return Err(VerkleError::InvalidPoint(PointError::NonCanonical));
+    }
+
+    let point = EdwardsAffine::get_point_from_x(x, true)
+        .ok_or(VerkleError::InvalidPoint(PointError::NotOnCurve))?;
+    if !(Fq::one() - EdwardsParameters::COEFF_A * x.square())
+        .legendre()
+        .is_qr()
+    {
+        return Err(VerkleError::InvalidPoint(PointError::NotInSubgroup));
+    }
+
+    // Exactly one of the two representatives is in the prime order subgroup
+    let point = if point.is_in_correct_subgroup_assuming_on_curve() {
+        point
+    } else {
+        EdwardsAffine::new(-point.x, -point.y)
+    };
+    check_point(&point)?;
+    Ok(point)
+}
+
+pub(crate) fn check_point(point: &EdwardsAffine) -> Result<(), VerkleError> {
+    if !point.is_on_curve() {
+        return Err(VerkleError::InvalidPoint(PointError::NotOnCurve));
+    }
+    if !point.is_in_correct_subgroup_assuming_on_curve() {
+        return Err(VerkleError::InvalidPoint(PointError::NotInSubgroup));
+    }
+    Ok(())
+}
+
+// A serialised multipoint proof is the commitment to g(x), followed by the L and R points
+// of the inner product argument and finally the scalar which the polynomial was reduced to.
+// So every 32 byte chunk except for the last one is a point
+pub(crate) fn check_multiproof_points(bytes: &[u8]) -> Result<(), VerkleError> {
+    let chunks = bytes.chunks_exact(POINT_SIZE);
+    if !chunks.remainder().is_empty() || chunks.len() == 0 {
+        return Err(VerkleError::InvalidProof(
+            "the multipoint proof has an unexpected length",
+        ));
+    }
+    let num_points = chunks.len() - 1;
+    for chunk in chunks.take(num_points) {
+        let _ = decode_point(chunk)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{decode_banderwagon, decode_point, encode_banderwagon, POINT_SIZE};
+    use crate::errors::{PointError, VerkleError};
+    use ark_ec::{models::TEModelParameters, AffineCurve, ProjectiveCurve};
+    use ark_ff::{BigInteger, Field, FpParameters, One, PrimeField, SquareRootField, Zero};
+    use ark_serialize::CanonicalSerialize;
+    use bandersnatch::{EdwardsAffine, EdwardsParameters, Fq, Fr};
+
+    // Returns a point which is on the curve but outside of the prime order subgroup,
+    // by adding a small order point to the generator
+    pub(crate) fn point_outside_subgroup() -> EdwardsAffine {
+        let mut x = Fq::one();
+        loop {
+            if let Some(point) = EdwardsAffine::get_point_from_x(x, true) {
+                // Removing the prime order component leaves a point of order 2 or 4
+                let small = point.mul(<Fr as PrimeField>::Params::MODULUS);
+                if !small.is_zero() {
+                    return EdwardsAffine::prime_subgroup_generator() + small.into_affine();
+                }
+            }
+            x += Fq::one();
+        }
+    }
+
+    fn encode(point: &EdwardsAffine) -> [u8; POINT_SIZE] {
+        let mut bytes = [0u8; POINT_SIZE];
+        point.serialize(&mut bytes[..]).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn decodes_subgroup_points() {
+        let generator = EdwardsAffine::prime_subgroup_generator();
+        assert_eq!(decode_point(&encode(&generator)).unwrap(), generator);
+
+        let identity = EdwardsAffine::zero();
+        assert_eq!(decode_point(&encode(&identity)).unwrap(), identity);
+    }
+
+    #[test]
+    fn rejects_invalid_points() {
+        let outside = point_outside_subgroup();
+        assert!(matches!(
+            decode_point(&encode(&outside)),
+            Err(VerkleError::InvalidPoint(PointError::NotInSubgroup))
+        ));
+
+        // The identity with the sign bit set decodes to the identity, but it is not its canonical encoding
+        let mut identity = encode(&EdwardsAffine::zero());
+        identity[POINT_SIZE - 1] |= 1 << 7;
+        assert!(matches!(
+            decode_point(&identity),
+            Err(VerkleError::InvalidPoint(PointError::NonCanonical))
+        ));
+
+        // An x coordinate which is larger than the modulus
+        let too_large = [0xff; POINT_SIZE];
+        assert!(matches!(
+            decode_point(&too_large),
+            Err(VerkleError::InvalidPoint(PointError::NonCanonical))
+        ));
+
+        // Find an x coordinate which does not have a point on the curve
+        let mut x = Fq::one();
+        while EdwardsAffine::get_point_from_x(x, true).is_some() {
+            x += Fq::one();
+        }
+        let mut not_on_curve = [0u8; POINT_SIZE];
+        x.serialize(&mut not_on_curve[..]).unwrap();
+        assert!(matches!(
+            decode_point(&not_on_curve),
+            Err(VerkleError::InvalidPoint(PointError::NotOnCurve))
+        ));
+    }
+
+    fn from_hex(hex_str: &str) -> [u8; POINT_SIZE] {
+        hex::decode(hex_str).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn banderwagon_encoding() {
+        // The encoding of the generator, as published with the specification of Banderwagon
+        let generator = EdwardsAffine::prime_subgroup_generator();
+        let encoded = encode_banderwagon(&generator.into_projective());
+        assert_eq!(
+            encoded,
+            from_hex("4a2c7486fd924882bf02c6908de395122843e3e05264d7991e18e7985dad51e9")
+        );
+        assert_eq!(decode_banderwagon(&encoded).unwrap(), generator);
+
+        // The first point of the CRS of EIP-6800 is an element of the group
+        let first_crs_point =
+            from_hex("01587ad1336675eb912550ec2a28eb8923b824b490dd2ba82e48f14590a298a0");
+        let point = decode_banderwagon(&first_crs_point).unwrap();
+        assert_eq!(
+            encode_banderwagon(&point.into_projective()),
+            first_crs_point
+        );
+
+        // Both representatives of an element have the same encoding
+        let other_representative = EdwardsAffine::new(-generator.x, -generator.y);
+        assert_eq!(
+            encode_banderwagon(&other_representative.into_projective()),
+            encoded
+        );
+
+        let identity = EdwardsAffine::zero();
+        assert_eq!(encode_banderwagon(&identity.into_projective()), [0u8; 32]);
+        assert_eq!(decode_banderwagon(&[0u8; 32]).unwrap(), identity);
+
+        for scalar in [2u64, 3, 1000] {
+            let point = generator.mul(Fr::from(scalar)).into_affine();
+            let encoded = encode_banderwagon(&point.into_projective());
+            assert_eq!(decode_banderwagon(&encoded).unwrap(), point);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_banderwagon_elements() {
+        // An x coordinate which is not less than the modulus
+        let mut too_large = [0xff; POINT_SIZE];
+        assert!(matches!(
+            decode_banderwagon(&too_large),
+            Err(VerkleError::InvalidPoint(PointError::NonCanonical))
+        ));
+        too_large.copy_from_slice(&<Fq as PrimeField>::Params::MODULUS.to_bytes_be());
+        assert!(matches!(
+            decode_banderwagon(&too_large),
+            Err(VerkleError::InvalidPoint(PointError::NonCanonical))
+        ));
+
+        // A point on the curve which is outside of the group
+        let mut x = Fq::one();
+        while EdwardsAffine::get_point_from_x(x, true).is_none()
+            || (Fq::one() - EdwardsParameters::COEFF_A * x.square())
+                .legendre()
+                .is_qr()
+        {
+            x += Fq::one();
+        }
+        let mut outside_bytes = [0u8; POINT_SIZE];
+        outside_bytes.copy_from_slice(&x.into_repr().to_bytes_be());
+        assert!(matches!(
+            decode_banderwagon(&outside_bytes),
+            Err(VerkleError::InvalidPoint(PointError::NotInSubgroup))
+        ));
+
+        // An x coordinate which does not have a point on the curve
+        let mut x = Fq::one();
+        while EdwardsAffine::get_point_from_x(x, true).is_some() {
+            x += Fq::one();
+        }
+        let mut not_on_curve = [0u8; POINT_SIZE];
+        not_on_curve.copy_from_slice(&x.into_repr().to_bytes_be());
+        assert!(matches!(
+            decode_banderwagon(&not_on_curve),
+            Err(VerkleError::InvalidPoint(PointError::NotOnCurve))
+        ));
+    }
+}
@@ -1,6 +1,7 @@
 use super::VerkleProof;
 use crate::{
     constants::TWO_POW_128,
+    errors::VerkleError,
     group_to_field,
     proof::{ExtPresent, UpdateHint},
 };
@@ -13,12 +14,15 @@ use std::{
 };
 
 // TODO Document this better and refactor
+//
+// The proof may have been sent by a peer, so any inconsistency between the proof
+// and the keys and values, must return an error instead of panicking
 pub fn create_verifier_queries(
     proof: VerkleProof,
     keys: Vec<[u8; 32]>,
     values: Vec<Option<[u8; 32]>>,
     root: EdwardsProjective,
-) -> Result<Option<(Vec<VerifierQuery>, UpdateHint)>, ()> {
+) -> Result<(Vec<VerifierQuery>, UpdateHint), VerkleError> {
     let commitments_sorted_by_path: Vec<_> =
         std::iter::once(root).chain(proof.comms_sorted).collect();
 
@@ -28,6 +32,14 @@ pub fn create_verifier_queries(
         .map(|key| key[0..31].try_into().unwrap())
         .collect();
 
+    if stems.len() != proof.verification_hint.depths.len()
+        || stems.len() != proof.verification_hint.extension_present.len()
+    {
+        return Err(VerkleError::InvalidProof(
+            "the number of depths does not match the number of stems",
+        ));
+    }
+
     let mut depths_and_ext_by_stem: BTreeMap<[u8; 31], (ExtPresent, u8)> = BTreeMap::new();
 
     let mut stems_with_extension: BTreeSet<[u8; 31]> = BTreeSet::new();
@@ -47,6 +59,10 @@ pub fn create_verifier_queries(
         .zip(proof.verification_hint.depths)
         .zip(proof.verification_hint.extension_present)
     {
+        // A stem is at least one level below the root and cannot be deeper than its length
+        if depth == 0 || depth as usize > stem.len() {
+            return Err(VerkleError::InvalidProof("stem depth is out of range"));
+        }
         depths_and_ext_by_stem.insert(stem, (ext_pres, depth));
 
         if ext_pres == ExtPresent::Present {
@@ -56,9 +72,6 @@ pub fn create_verifier_queries(
 
     for (key, value) in keys.into_iter().zip(values) {
         let stem: [u8; 31] = key[0..31].try_into().unwrap();
-        if !depths_and_ext_by_stem.contains_key(&stem) {
-            return Err(());
-        }
         let (extpres, depth) = depths_and_ext_by_stem[&stem];
 
         // Add branch node information, we know that if the stem has depth `d`
@@ -111,7 +124,9 @@ pub fn create_verifier_queries(
                 // Since this stem points to a different stem,
                 // the value was never set
                 if value.is_some() {
-                    return Ok(None);
+                    return Err(VerkleError::InvalidProof(
+                        "value is present, however the proof is for a different stem",
+                    ));
                 }
 
                 // Check if this stem already has an extension proof
@@ -124,7 +139,11 @@ pub fn create_verifier_queries(
                 // depth cannot be 31 because then that would mean that stem[...depth]
                 // is looking for it's tem. This is not possible, because we have already
                 // noted that ExtPresent is DifferentStem
-                assert!(depth != stem.len() as u8);
+                if depth == stem.len() as u8 {
+                    return Err(VerkleError::InvalidProof(
+                        "a different stem cannot be at the same depth as the full stem",
+                    ));
+                }
 
                 let mut other_stem = None;
                 let mut found: Vec<_> = stems_with_extension
@@ -132,8 +151,9 @@ pub fn create_verifier_queries(
                     .filter(|x| x[0..depth as usize] == stem[0..depth as usize])
                     .collect();
                 if found.len() > 1 {
-                    // TODO return error instead when we change the func signature to return Result instead of bool
-                    panic!("found more than one instance of stem_with_extension at depth {}, see: {:?}", depth, found)
+                    return Err(VerkleError::InvalidProof(
+                        "found more than one stem with an extension under the same prefix",
+                    ));
                 } else if found.len() == 1 {
                     other_stem = found.pop();
                 }
@@ -145,9 +165,9 @@ pub fn create_verifier_queries(
                         .iter()
                         .filter(|x| x[0..depth as usize] == stem[0..depth as usize])
                         .collect();
-                    let encountered_stem = found.pop().expect(
+                    let encountered_stem = found.pop().ok_or(VerkleError::InvalidProof(
                         "ExtPresent::DifferentStem flag but we cannot find the encountered stem",
-                    );
+                    ))?;
                     other_stem = Some(encountered_stem);
 
                     other_stems_used.insert(*encountered_stem);
@@ -165,28 +185,27 @@ pub fn create_verifier_queries(
         } else if extpres == ExtPresent::None {
             // If the extension was not present, then the value should be None
             if value.is_some() {
-                return Ok(None);
+                return Err(VerkleError::InvalidProof(
+                    "value is present, however the proof has no extension for the stem",
+                ));
             }
 
-            //TODO: we may need to rewrite the prover/verifier algorithm to fix this if statement properly.
-            // This is a special case. If the depth == 1 and the there is no stem to prove the proof of absence
-            // then this means that the path should point to the root node.
-            //
-            // TODO: Fix in python codebase and check for this in go code
-            if depth == 1 {
-                let root_path = vec![];
-                leaf_values_by_path_and_z.insert((root_path, stem[depth as usize - 1]), Fr::zero());
-            } else {
-                leaf_values_by_path_and_z.insert(
-                    (stem[0..depth as usize].to_vec(), stem[depth as usize - 1]),
-                    Fr::zero(),
-                );
-            }
+            // The slot for the stem is empty, so the inner node above it opens to zero at the index of the slot
+            let parent_path = stem[0..depth as usize - 1].to_vec();
+            leaf_values_by_path_and_z.insert((parent_path, stem[depth as usize - 1]), Fr::zero());
         }
     }
 
-    assert!(proof.verification_hint.diff_stem_no_proof == other_stems_used);
-    assert!(commitments_sorted_by_path.len() == all_paths.len());
+    if proof.verification_hint.diff_stem_no_proof != other_stems_used {
+        return Err(VerkleError::InvalidProof(
+            "the proof contains stems which are not used",
+        ));
+    }
+    if commitments_sorted_by_path.len() != all_paths.len() {
+        return Err(VerkleError::InvalidProof(
+            "the number of commitments does not match the number of paths",
+        ));
+    }
 
     let commitments_by_path: BTreeMap<Vec<_>, EdwardsProjective> = all_paths
         .into_iter()
@@ -207,7 +226,14 @@ pub fn create_verifier_queries(
         child_path.push(*z);
         let y = match leaf_values_by_path_and_z.get(&(path.clone(), *z)) {
             Some(val) => *val,
-            None => group_to_field(&commitments_by_path[&child_path]),
+            None => match commitments_by_path.get(&child_path) {
+                Some(comm) => group_to_field(comm),
+                None => {
+                    return Err(VerkleError::InvalidProof(
+                        "missing the commitment for a child node",
+                    ))
+                }
+            },
         };
 
         ys_by_path_and_z.insert((path.clone(), *z), y);
@@ -235,5 +261,5 @@ pub fn create_verifier_queries(
         other_stems_by_prefix,
     };
 
-    Ok(Some((queries, update_hint)))
+    Ok((queries, update_hint))
 }
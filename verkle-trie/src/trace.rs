@@ -0,0 +1,62 @@
+// Structured tracing for the expensive operations of the trie: inserting, flushing, and creating and verifying proofs.
+//
+// Spans are only emitted when the `tracing` feature is enabled. Without it, `timed_span!` returns a
+// `TimedSpan` which does nothing, so the call sites do not need to be feature gated.
+//
+// Every span has an `elapsed_ms` field which is recorded when the span is dropped. Counts which are
+// only known part way through an operation are declared after a `;` and recorded with `TimedSpan::record`.
+
+pub(crate) struct TimedSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+    #[cfg(feature = "tracing")]
+    start: std::time::Instant,
+}
+
+impl TimedSpan {
+    #[cfg(feature = "tracing")]
+    pub(crate) fn enter(span: tracing::Span) -> TimedSpan {
+        TimedSpan {
+            span: span.entered(),
+            start: std::time::Instant::now(),
+        }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn disabled() -> TimedSpan {
+        TimedSpan {}
+    }
+
+    // The field must have been declared when the span was created
+    pub(crate) fn record(&self, field: &'static str, value: usize) {
+        #[cfg(feature = "tracing")]
+        self.span.record(field, value as u64);
+        #[cfg(not(feature = "tracing"))]
+        let _ = (field, value);
+    }
+}
+
+impl Drop for TimedSpan {
+    fn drop(&mut self) {
+        #[cfg(feature = "tracing")]
+        self.span
+            .record("elapsed_ms", self.start.elapsed().as_millis() as u64);
+    }
+}
+
+macro_rules! timed_span {
+    ($name:literal $(, $field:ident = $value:expr)* $(; $($later:ident),+)?) => {{
+        #[cfg(feature = "tracing")]
+        let span = $crate::trace::TimedSpan::enter(tracing::debug_span!(
+            $name,
+            $($field = $value as u64,)*
+            $($($later = tracing::field::Empty,)+)?
+            elapsed_ms = tracing::field::Empty
+        ));
+        #[cfg(not(feature = "tracing"))]
+        let span = $crate::trace::TimedSpan::disabled();
+        span
+    }};
+}
+
+pub(crate) use timed_span;
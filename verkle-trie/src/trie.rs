@@ -1,10 +1,18 @@
 use crate::constants::{CRS, TWO_POW_128};
-use crate::database::{BranchMeta, Flush, Meta, ReadWriteHigherDb, StemMeta};
+use crate::database::{
+    BranchChild, BranchMeta, Flush, Meta, ReadOnlyHigherDb, ReadWriteHigherDb, StemMeta,
+};
 use crate::{committer::Committer, Config};
-use crate::{group_to_field, TrieTrait};
+use crate::{errors::VerkleError, group_to_field, trace::timed_span, TrieTrait};
 use ark_ff::{PrimeField, Zero};
 use bandersnatch::{EdwardsProjective, Fr};
 
+mod batch;
+mod checkpoint;
+mod iter;
+pub use checkpoint::Checkpoint;
+pub use iter::{StemIter, TrieIter};
+
 #[derive(Debug, Clone)]
 // The trie implements the logic to insert values, fetch values, and create paths to said values
 pub struct Trie<Storage, PolyCommit: Committer> {
@@ -15,17 +23,59 @@ pub struct Trie<Storage, PolyCommit: Committer> {
 // Implementation of the trie trait that should be considered the public API for the trie
 impl<S: ReadWriteHigherDb, P: Committer> TrieTrait for Trie<S, P> {
     fn insert(&mut self, kv: impl Iterator<Item = (crate::Key, crate::Value)>) {
+        let span = timed_span!("insert"; num_keys);
+        let mut num_keys = 0;
         for (key_bytes, value_bytes) in kv {
             let ins = self.create_insert_instructions(key_bytes, value_bytes);
             self.process_instructions(ins);
+            num_keys += 1;
         }
+        span.record("num_keys", num_keys);
     }
 
     fn get(&self, key: crate::Key) -> Option<crate::Value> {
-        self.storage.get_leaf(key)
+        Trie::get(self, key)
+    }
+
+    fn delete(&mut self, key: crate::Key) -> Option<crate::Value> {
+        self.delete_leaf(key)
     }
 
     fn root_hash(&self) -> Fr {
+        Trie::root_hash(self)
+    }
+
+    fn create_verkle_proof(
+        &self,
+        keys: impl Iterator<Item = [u8; 32]>,
+    ) -> Result<crate::proof::VerkleProof, VerkleError> {
+        Trie::create_verkle_proof(self, keys)
+    }
+
+    fn root_commitment(&self) -> EdwardsProjective {
+        Trie::root_commitment(self)
+    }
+}
+
+// Reading from the trie only needs read access to the storage, so these methods are also
+// available to tries which are opened read-only, such as a trie over archived state
+impl<Storage: ReadOnlyHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit> {
+    // Opens a trie which can only be read from, the storage must already have a root
+    pub fn open_read_only(config: Config<Storage, PolyCommit>) -> Result<Self, VerkleError> {
+        if config.db.root_is_missing() {
+            return Err(VerkleError::MissingRoot);
+        }
+        Ok(Trie {
+            storage: config.db,
+            committer: config.committer,
+        })
+    }
+
+    pub fn get(&self, key: crate::Key) -> Option<crate::Value> {
+        self.storage.get_leaf(key)
+    }
+
+    pub fn root_hash(&self) -> Fr {
         // This covers the case when the tree is empty
         // If the number of stems is zero, then this branch will return zero
         let root_node = self
@@ -35,15 +85,33 @@ impl<S: ReadWriteHigherDb, P: Committer> TrieTrait for Trie<S, P> {
         root_node.hash_commitment
     }
 
-    fn create_verkle_proof(
+    pub fn create_verkle_proof(
         &self,
         keys: impl Iterator<Item = [u8; 32]>,
-    ) -> crate::proof::VerkleProof {
+    ) -> Result<crate::proof::VerkleProof, VerkleError> {
         use crate::proof::prover;
         prover::create_verkle_proof(&self.storage, keys.collect())
     }
 
-    fn root_commitment(&self) -> EdwardsProjective {
+    // Creates a proof along with the state of each key, so that the values do not need to be fetched separately
+    pub fn create_verkle_proof_with_key_states(
+        &self,
+        keys: impl Iterator<Item = [u8; 32]>,
+    ) -> Result<(crate::proof::VerkleProof, Vec<crate::proof::KeyState>), VerkleError> {
+        use crate::proof::prover;
+        prover::create_verkle_proof_with_key_states(&self.storage, keys.collect())
+    }
+
+    // Returns the statistics of the proof for these keys, without creating the proof
+    pub fn estimate_proof_stats(
+        &self,
+        keys: impl Iterator<Item = [u8; 32]>,
+    ) -> Result<crate::proof::ProofStats, VerkleError> {
+        use crate::proof::prover;
+        prover::estimate_proof_stats(&self.storage, keys.collect())
+    }
+
+    pub fn root_commitment(&self) -> EdwardsProjective {
         // TODO: This is needed for proofs, can we remove the root hash as the root?
         let root_node = self.storage.get_branch_meta(&vec![]).unwrap();
         return root_node.commitment;
@@ -126,7 +194,7 @@ enum Ins {
 
 impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit> {
     // Creates a new Trie object
-    pub fn new(config: Config<Storage, PolyCommit>) -> Self {
+    pub fn new(config: Config<Storage, PolyCommit>) -> Result<Self, VerkleError> {
         // TODO: We should have a way to populate the cache from the persistent db here.
         // TODO: we first check if it is an new database and if it is not
         // TODO: then we pull in all nodes on level 3 or lower
@@ -137,13 +205,16 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
         // Add the root node to the database with the root index, if the database does not have it
         // If the root is missing, then it means it is a fresh database
         if db.root_is_missing() {
-            let old_val = db.insert_branch(vec![], BranchMeta::zero(), 0);
-            assert!(old_val.is_none());
+            db.insert_branch(vec![], BranchMeta::zero(), 0);
         }
-        Trie {
+        // The database did not store the root, so we would not be able to use the trie
+        if db.root_is_missing() {
+            return Err(VerkleError::MissingRoot);
+        }
+        Ok(Trie {
             storage: db,
             committer: pc,
-        }
+        })
     }
 
     // Inserting a leaf in the trie is done in two steps
@@ -491,7 +562,8 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
 #[derive(Debug)]
 pub(crate) struct LeafUpdated {
     old_val: Option<Vec<u8>>,
-    new_value: Vec<u8>,
+    // This is None when the leaf has been deleted
+    new_value: Option<Vec<u8>>,
     key: Vec<u8>,
 }
 #[derive(Debug)]
@@ -526,7 +598,7 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
 
         Some(LeafUpdated {
             old_val,
-            new_value: value.to_vec(),
+            new_value: Some(value.to_vec()),
             key: key.to_vec(),
         })
 
@@ -537,16 +609,23 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
         // If a leaf is updated, then we need to update the stem.
         // In particular, we need to update the commitment for that stem and the stem value
         //
-        // There are two cases here:
+        // There are three cases here:
         // - old_value is None. So there was a fresh update
         // - old_value as Some and we have modified a value
-        // We can treat both cases as one because to compute the delta we do (new_value - old_value)
+        // - new_value is None. So the leaf was deleted
+        // We can treat all cases as one because to compute the delta we do (new_value - old_value)
         // When the value has not changed, it's (new_value - 0)
+        // When the value was deleted, it's (0 - old_value)
         //
 
         // Split values into low_16 and high_16
-        let new_value_low_16 = update_leaf.new_value[0..16].to_vec();
-        let new_value_high_16 = update_leaf.new_value[16..32].to_vec();
+        let (new_value_low_16, new_value_high_16) = match update_leaf.new_value {
+            Some(val) => (
+                Fr::from_le_bytes_mod_order(&val[0..16]) + TWO_POW_128,
+                Fr::from_le_bytes_mod_order(&val[16..32]),
+            ),
+            None => (Fr::zero(), Fr::zero()),
+        };
 
         let (old_value_low_16, old_value_high_16) = match update_leaf.old_val {
             Some(val) => (
@@ -557,9 +636,8 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
         };
 
         // We need to compute two deltas
-        let delta_low =
-            Fr::from_le_bytes_mod_order(&new_value_low_16) + TWO_POW_128 - old_value_low_16;
-        let delta_high = Fr::from_le_bytes_mod_order(&new_value_high_16) - old_value_high_16;
+        let delta_low = new_value_low_16 - old_value_low_16;
+        let delta_high = new_value_high_16 - old_value_high_16;
 
         // We need to compute which group elements in the srs are being used
         // We know that the first 128 values are mapped to the first 256 group elements
@@ -715,6 +793,130 @@ impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit
     }
 }
 
+impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit> {
+    // Deleting a leaf undoes what an insert did:
+    // - The leaf is removed and its value is subtracted from C_1 or C_2 and the stem commitment
+    // - If the stem no longer has any leaves, the stem is removed from its branch
+    // - If this leaves a branch node (that is not the root) whose only child is a stem,
+    //   the branch node would never have been created by an insert, so we replace it with the stem.
+    //   This is repeated up the trie, undoing the chain of branch nodes created by `ChainInsert`
+    // - Finally, the deltas are propagated up to the root
+    //
+    // The resulting root is the same as the root of a trie which never had the key.
+    // If the key is not in the trie, this function returns None and leaves the trie untouched
+    fn delete_leaf(&mut self, key: [u8; 32]) -> Option<[u8; 32]> {
+        let stem: [u8; 31] = key[0..31].try_into().unwrap();
+
+        // Find the path to the branch node which holds the stem
+        let mut branch_path = Vec::new();
+        loop {
+            let index = key[branch_path.len()];
+            match self.storage.get_branch_child(&branch_path, index)? {
+                BranchChild::Branch(_) => branch_path.push(index),
+                BranchChild::Stem(stem_id) => {
+                    if stem_id != stem {
+                        return None;
+                    }
+                    break;
+                }
+            }
+        }
+
+        let old_value = self.storage.get_leaf(key)?;
+        self.storage.remove_leaf(key);
+
+        let depth = branch_path.len() as u8 + 1;
+        let stem_update = self.update_stem_table(
+            LeafUpdated {
+                old_val: Some(old_value.to_vec()),
+                new_value: None,
+                key: key.to_vec(),
+            },
+            depth,
+        );
+
+        // Track the child of the branch node at `branch_path` which has changed
+        let mut child_index = key[branch_path.len()];
+        let mut old_child_hash = stem_update
+            .old_val
+            .expect("the stem has a leaf, so it must have a commitment");
+        let mut new_child_hash = stem_update.new_val;
+
+        if self.storage.get_stem_children(stem).is_empty() {
+            // The stem is empty, so the branch node no longer commits to it
+            self.storage.remove_stem(stem);
+            let mut stem_path = branch_path.clone();
+            stem_path.push(child_index);
+            self.storage.remove_branch_child(stem_path);
+            new_child_hash = Fr::zero();
+        }
+
+        // Collapse branch nodes which only have a single stem as a child
+        while !branch_path.is_empty() {
+            let children = self.storage.get_branch_children(&branch_path);
+            let (remaining_index, remaining_stem) = match children.as_slice() {
+                [(index, BranchChild::Stem(stem_id))] => (*index, *stem_id),
+                _ => break,
+            };
+
+            // The stored commitment is the one that the parent currently commits to
+            let old_branch_hash = self
+                .storage
+                .get_branch_meta(&branch_path)
+                .unwrap()
+                .hash_commitment;
+            let stem_hash = self
+                .storage
+                .get_stem_meta(remaining_stem)
+                .unwrap()
+                .hash_stem_commitment;
+
+            let mut remaining_stem_path = branch_path.clone();
+            remaining_stem_path.push(remaining_index);
+            self.storage.remove_branch_child(remaining_stem_path);
+            self.storage.remove_branch_child(branch_path.clone());
+
+            // The stem now takes the place of the branch node in the parent
+            let depth = branch_path.len() as u8;
+            self.storage
+                .add_stem_as_branch_child(branch_path.clone(), remaining_stem, depth);
+
+            child_index = branch_path.pop().unwrap();
+            old_child_hash = old_branch_hash;
+            new_child_hash = stem_hash;
+        }
+
+        // Propagate the update up to the root
+        loop {
+            let branch_meta = self.storage.get_branch_meta(&branch_path).unwrap();
+
+            let delta = new_child_hash - old_child_hash;
+            let updated_comm =
+                branch_meta.commitment + self.committer.scalar_mul(delta, child_index as usize);
+            let hash_updated_comm = group_to_field(&updated_comm);
+
+            let depth = branch_path.len() as u8;
+            self.storage.insert_branch(
+                branch_path.clone(),
+                BranchMeta {
+                    commitment: updated_comm,
+                    hash_commitment: hash_updated_comm,
+                },
+                depth,
+            );
+
+            child_index = match branch_path.pop() {
+                Some(index) => index,
+                None => break,
+            };
+            old_child_hash = branch_meta.hash_commitment;
+            new_child_hash = hash_updated_comm;
+        }
+
+        Some(old_value)
+    }
+}
+
 impl<Storage: ReadWriteHigherDb + Flush, PolyCommit: Committer> Trie<Storage, PolyCommit> {
     // TODO: maybe make this private, and automatically flush
     // TODO after each insert. This will promote users to use insert()
@@ -778,7 +980,7 @@ mod tests {
     fn insert_key0value0() {
         let db = MemoryDb::new();
 
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let key = [0u8; 32];
         let stem: [u8; 31] = key[0..31].try_into().unwrap();
@@ -835,7 +1037,7 @@ mod tests {
         use crate::database::ReadOnlyHigherDb;
 
         let db = MemoryDb::new();
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let key = [
             1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
@@ -900,7 +1102,7 @@ mod tests {
     fn insert_same_stem_two_leaves() {
         use crate::database::ReadOnlyHigherDb;
         let db = MemoryDb::new();
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let key_a = [
             1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
@@ -989,7 +1191,7 @@ mod tests {
         use crate::database::ReadOnlyHigherDb;
 
         let db = MemoryDb::new();
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let key_a = [0u8; 32];
         let stem_a: [u8; 31] = key_a[0..31].try_into().unwrap();
@@ -1016,7 +1218,7 @@ mod tests {
     // Test where keys create the longest path
     fn insert_longest_path() {
         let db = MemoryDb::new();
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let key_a = [0u8; 32];
         let mut key_b = [0u8; 32];
@@ -1036,7 +1238,7 @@ mod tests {
     // Test where keys create the longest path and the new key traverses that path
     fn insert_and_traverse_longest_path() {
         let db = MemoryDb::new();
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let key_a = [0u8; 32];
         let ins = trie.create_insert_instructions(key_a, key_a);
@@ -1069,7 +1271,7 @@ mod tests {
         // An empty tree should return zero as the root
 
         let db = MemoryDb::new();
-        let trie = Trie::new(TestConfig::new(db));
+        let trie = Trie::new(TestConfig::new(db)).unwrap();
 
         assert_eq!(trie.root_hash(), Fr::zero())
     }
@@ -1077,7 +1279,7 @@ mod tests {
     #[test]
     fn simple_insert() {
         let db = MemoryDb::new();
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let key_a = [
             1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
@@ -1099,7 +1301,7 @@ mod tests {
     #[test]
     fn simple_update() {
         let db = MemoryDb::new();
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let key_a = [
             1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
@@ -1142,7 +1344,7 @@ mod tests {
         let temp_dir = tempdir().unwrap();
 
         let db = MemoryDb::new();
-        let mut trie = Trie::new(TestConfig::new(db));
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
 
         let tree_key_version: [u8; 32] = [
             121, 85, 7, 198, 131, 230, 143, 90, 165, 129, 173, 81, 186, 89, 19, 191, 13, 107, 197,
@@ -1196,4 +1398,132 @@ mod tests {
         let val = trie.get(tree_key_code_keccak).unwrap();
         let val = trie.get(tree_key_code_size).unwrap();
     }
+
+    #[test]
+    fn delete_only_key() {
+        let db = MemoryDb::new();
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+
+        let key = [1u8; 32];
+        trie.insert_single(key, key);
+
+        assert_eq!(trie.delete(key), Some(key));
+        assert_eq!(trie.get(key), None);
+        assert_eq!(trie.root_hash(), Fr::zero());
+        assert!(trie.storage.stem_table.is_empty());
+        assert_eq!(trie.storage.branch_table.len(), 1);
+    }
+
+    #[test]
+    fn delete_leaf_same_stem() {
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[31] = 200;
+
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        trie.insert(vec![(key_a, [1u8; 32]), (key_b, [2u8; 32])].into_iter());
+        assert_eq!(trie.delete(key_b), Some([2u8; 32]));
+
+        let mut expected = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        expected.insert_single(key_a, [1u8; 32]);
+
+        assert_eq!(trie.root_commitment(), expected.root_commitment());
+        let stem: [u8; 31] = key_a[0..31].try_into().unwrap();
+        assert_eq!(
+            trie.storage.get_stem_meta(stem),
+            expected.storage.get_stem_meta(stem)
+        );
+    }
+
+    #[test]
+    fn delete_collapses_chain() {
+        // These keys share 30 path indices, so inserting both creates a long chain of branch nodes
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[30] = 1;
+
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        trie.insert(vec![(key_a, key_a), (key_b, key_b)].into_iter());
+        trie.delete(key_b);
+
+        let mut expected = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        expected.insert_single(key_a, key_a);
+
+        assert_eq!(trie.root_commitment(), expected.root_commitment());
+        assert_eq!(trie.storage.branch_table.len(), 2);
+        assert_eq!(
+            trie.storage.get_branch_child(&[], 0).unwrap().stem(),
+            Some(key_a[0..31].try_into().unwrap())
+        );
+    }
+
+    #[test]
+    fn delete_collapses_until_shared_branch() {
+        let key_a = [0u8; 32];
+        let mut key_b = [0u8; 32];
+        key_b[5] = 1;
+        let mut key_c = [0u8; 32];
+        key_c[30] = 1;
+
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        trie.insert(vec![(key_a, key_a), (key_b, key_b), (key_c, key_c)].into_iter());
+        trie.delete(key_c);
+
+        let mut expected = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        expected.insert(vec![(key_a, key_a), (key_b, key_b)].into_iter());
+
+        assert_eq!(trie.root_commitment(), expected.root_commitment());
+        assert_eq!(
+            trie.storage.branch_table.len(),
+            expected.storage.branch_table.len()
+        );
+    }
+
+    #[test]
+    fn delete_missing_key() {
+        let key_a = [0u8; 32];
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        trie.insert_single(key_a, key_a);
+        let root = trie.root_commitment();
+
+        // Same stem, but the suffix was never inserted
+        let mut same_stem = key_a;
+        same_stem[31] = 1;
+        // Different stem, at the position of an existing stem
+        let mut different_stem = key_a;
+        different_stem[30] = 1;
+        // Empty position in the root
+        let empty = [1u8; 32];
+
+        for key in [same_stem, different_stem, empty] {
+            assert_eq!(trie.delete(key), None);
+            assert_eq!(trie.root_commitment(), root);
+        }
+    }
+
+    #[test]
+    fn delete_then_reinsert() {
+        let keys: Vec<[u8; 32]> = (0u8..16)
+            .map(|i| {
+                let mut key = [0u8; 32];
+                key[0] = i % 4;
+                key[1] = i;
+                key[31] = i;
+                key
+            })
+            .collect();
+
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        trie.insert(keys.iter().map(|key| (*key, *key)));
+        let root = trie.root_commitment();
+
+        trie.delete_many(keys[8..].iter().copied());
+
+        let mut expected = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        expected.insert(keys[..8].iter().map(|key| (*key, *key)));
+        assert_eq!(trie.root_commitment(), expected.root_commitment());
+
+        trie.insert(keys[8..].iter().map(|key| (*key, *key)));
+        assert_eq!(trie.root_commitment(), root);
+    }
 }
@@ -0,0 +1,403 @@
+use super::{path_difference, Trie};
+use crate::committer::Committer;
+use crate::constants::{CRS, TWO_POW_128};
+use crate::database::{BranchChild, BranchMeta, ReadWriteHigherDb, StemMeta};
+use crate::{group_to_field, trace::timed_span, Key, Stem, Value};
+use ark_ff::{PrimeField, Zero};
+use bandersnatch::{EdwardsProjective, Fr};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+// A branch node, identified by its path, along with the indices of its children which changed
+type DirtyBranch = (Vec<u8>, BTreeSet<u8>);
+
+// Batch insertion produces the same trie as inserting each key sequentially,
+// however each touched node is only committed to once:
+//
+// - First, the new stems are placed in the trie. This only modifies the structure of the trie,
+//   and for each child that moved or changed, we record the hash that its parent committed to.
+// - Then, the commitments for each stem are computed from the aggregated leaf deltas.
+//   Stems are independent of each other, so this is done in parallel.
+// - Finally, the branch nodes are updated from the deepest level to the root.
+//   Each branch node applies a single delta for all of its changed children, and since
+//   branches on the same level are in independent subtrees, each level is done in parallel.
+impl<Storage: ReadWriteHigherDb + Sync, PolyCommit: Committer + Sync> Trie<Storage, PolyCommit> {
+    pub fn insert_batch(&mut self, kv: impl Iterator<Item = (Key, Value)>) {
+        let span = timed_span!("insert_batch"; num_keys, num_stems);
+
+        // Group the leaves by stem, if a key is inserted twice the last value is kept
+        let mut leaves_by_stem: BTreeMap<Stem, BTreeMap<u8, Value>> = BTreeMap::new();
+        let mut num_keys = 0;
+        for (key, value) in kv {
+            num_keys += 1;
+            let stem: Stem = key[0..31].try_into().unwrap();
+            leaves_by_stem
+                .entry(stem)
+                .or_default()
+                .insert(key[31], value);
+        }
+
+        // Leaves which are being set to their current value do not modify the trie
+        for (stem, leaves) in leaves_by_stem.iter_mut() {
+            leaves.retain(|suffix, value| {
+                let mut key = [0u8; 32];
+                key[0..31].copy_from_slice(stem);
+                key[31] = *suffix;
+                self.storage.get_leaf(key) != Some(*value)
+            });
+        }
+        leaves_by_stem.retain(|_, leaves| !leaves.is_empty());
+        span.record("num_keys", num_keys);
+        span.record("num_stems", leaves_by_stem.len());
+
+        if leaves_by_stem.is_empty() {
+            return;
+        }
+
+        // Maps the path of a child node to the hash that its parent committed to before this batch
+        let mut old_child_hashes: HashMap<Vec<u8>, Fr> = HashMap::new();
+        let mut depths_by_stem: HashMap<Stem, u8> = HashMap::new();
+        for stem in leaves_by_stem.keys() {
+            self.place_stem(*stem, &mut old_child_hashes, &mut depths_by_stem);
+        }
+
+        let stem_updates: Vec<_> = leaves_by_stem
+            .into_par_iter()
+            .map(|(stem, leaves)| {
+                let stem_meta = self.compute_stem_meta(stem, &leaves);
+                (stem, leaves, stem_meta)
+            })
+            .collect();
+
+        for (stem, leaves, stem_meta) in stem_updates {
+            let depth = depths_by_stem[&stem];
+            for (suffix, value) in leaves {
+                let mut key = [0u8; 32];
+                key[0..31].copy_from_slice(&stem);
+                key[31] = suffix;
+                self.storage.insert_leaf(key, value, depth);
+            }
+            self.storage.insert_stem(stem, stem_meta, depth);
+        }
+
+        // Every ancestor of a changed child needs to be updated
+        let mut dirty_children: BTreeMap<Vec<u8>, BTreeSet<u8>> = BTreeMap::new();
+        for child_path in old_child_hashes.keys() {
+            for depth in 1..=child_path.len() {
+                dirty_children
+                    .entry(child_path[0..depth - 1].to_vec())
+                    .or_default()
+                    .insert(child_path[depth - 1]);
+            }
+        }
+
+        let mut dirty_by_depth: BTreeMap<usize, Vec<DirtyBranch>> = BTreeMap::new();
+        for (branch_id, children) in dirty_children {
+            dirty_by_depth
+                .entry(branch_id.len())
+                .or_default()
+                .push((branch_id, children));
+        }
+
+        for (_, branches) in dirty_by_depth.into_iter().rev() {
+            let branch_updates: Vec<_> = branches
+                .into_par_iter()
+                .map(|(branch_id, children)| {
+                    let old_meta = self.storage.get_branch_meta(&branch_id).unwrap();
+
+                    let deltas: Vec<_> = children
+                        .into_iter()
+                        .map(|index| {
+                            let mut child_path = branch_id.clone();
+                            child_path.push(index);
+                            let new_hash = self.child_hash(&branch_id, index);
+                            (new_hash - old_child_hashes[&child_path], index as usize)
+                        })
+                        .collect();
+
+                    let commitment = old_meta.commitment + self.committer.commit_sparse(deltas);
+                    let new_meta = BranchMeta {
+                        commitment,
+                        hash_commitment: group_to_field(&commitment),
+                    };
+                    (branch_id, old_meta.hash_commitment, new_meta)
+                })
+                .collect();
+
+            for (branch_id, old_hash, new_meta) in branch_updates {
+                // Branches created in this batch have already recorded what their parent committed to
+                old_child_hashes
+                    .entry(branch_id.clone())
+                    .or_insert(old_hash);
+                let depth = branch_id.len() as u8;
+                self.storage.insert_branch(branch_id, new_meta, depth);
+            }
+        }
+    }
+
+    // Places the stem in the trie, if it is not already there
+    // The commitments of the nodes are not modified
+    fn place_stem(
+        &mut self,
+        stem: Stem,
+        old_child_hashes: &mut HashMap<Vec<u8>, Fr>,
+        depths_by_stem: &mut HashMap<Stem, u8>,
+    ) {
+        let mut branch_id = Vec::new();
+        loop {
+            let index = stem[branch_id.len()];
+            let mut child_path = branch_id.clone();
+            child_path.push(index);
+            let depth = child_path.len() as u8;
+
+            let existing_stem = match self.storage.get_branch_child(&branch_id, index) {
+                None => {
+                    old_child_hashes
+                        .entry(child_path.clone())
+                        .or_insert(Fr::zero());
+                    self.storage
+                        .add_stem_as_branch_child(child_path, stem, depth);
+                    depths_by_stem.insert(stem, depth);
+                    return;
+                }
+                Some(BranchChild::Branch(_)) => {
+                    branch_id = child_path;
+                    continue;
+                }
+                Some(BranchChild::Stem(existing_stem)) => existing_stem,
+            };
+
+            if existing_stem == stem {
+                let old_hash = self
+                    .storage
+                    .get_stem_meta(stem)
+                    .unwrap()
+                    .hash_stem_commitment;
+                old_child_hashes.entry(child_path).or_insert(old_hash);
+                depths_by_stem.insert(stem, depth);
+                return;
+            }
+
+            // The slot is taken by a different stem, so we need to add branch nodes
+            // until the two stems diverge.
+            // If the existing stem was placed in this batch, then its slot has already been recorded
+            if !old_child_hashes.contains_key(&child_path) {
+                let old_hash = self
+                    .storage
+                    .get_stem_meta(existing_stem)
+                    .unwrap()
+                    .hash_stem_commitment;
+                old_child_hashes.insert(child_path.clone(), old_hash);
+            }
+
+            let (shared_path, existing_index, new_index) = path_difference(existing_stem, stem);
+            for depth in child_path.len()..=shared_path.len() {
+                let new_branch_id = stem[0..depth].to_vec();
+                // All nodes below the top of the chain are new
+                old_child_hashes
+                    .entry(new_branch_id.clone())
+                    .or_insert(Fr::zero());
+                self.storage
+                    .insert_branch(new_branch_id, BranchMeta::zero(), depth as u8);
+            }
+
+            let depth = shared_path.len() as u8 + 1;
+            for (stem_id, index) in [(existing_stem, existing_index), (stem, new_index)] {
+                let mut stem_path = shared_path.clone();
+                stem_path.push(index.unwrap());
+                old_child_hashes.insert(stem_path.clone(), Fr::zero());
+                self.storage
+                    .add_stem_as_branch_child(stem_path, stem_id, depth);
+                depths_by_stem.insert(stem_id, depth);
+            }
+            return;
+        }
+    }
+
+    // Computes the new metadata for the stem, by aggregating the deltas for all of its leaves
+    fn compute_stem_meta(&self, stem: Stem, leaves: &BTreeMap<u8, Value>) -> StemMeta {
+        let (c_1, hash_c1, c_2, hash_c2, stem_commitment) = match self.storage.get_stem_meta(stem) {
+            Some(meta) => (
+                meta.C_1,
+                meta.hash_c1,
+                meta.C_2,
+                meta.hash_c2,
+                meta.stem_commitment,
+            ),
+            None => (
+                EdwardsProjective::zero(),
+                Fr::zero(),
+                EdwardsProjective::zero(),
+                Fr::zero(),
+                CRS[0]
+                    + self
+                        .committer
+                        .scalar_mul(Fr::from_le_bytes_mod_order(&stem), 1),
+            ),
+        };
+
+        let mut c1_deltas = Vec::new();
+        let mut c2_deltas = Vec::new();
+        for (suffix, value) in leaves {
+            let mut key = [0u8; 32];
+            key[0..31].copy_from_slice(&stem);
+            key[31] = *suffix;
+
+            let (old_value_low_16, old_value_high_16) = match self.storage.get_leaf(key) {
+                Some(val) => (
+                    Fr::from_le_bytes_mod_order(&val[0..16]) + TWO_POW_128,
+                    Fr::from_le_bytes_mod_order(&val[16..32]),
+                ),
+                None => (Fr::zero(), Fr::zero()),
+            };
+            let delta_low =
+                Fr::from_le_bytes_mod_order(&value[0..16]) + TWO_POW_128 - old_value_low_16;
+            let delta_high = Fr::from_le_bytes_mod_order(&value[16..32]) - old_value_high_16;
+
+            let low_index = 2 * (suffix % 128) as usize;
+            let deltas = if *suffix < 128 {
+                &mut c1_deltas
+            } else {
+                &mut c2_deltas
+            };
+            deltas.push((delta_low, low_index));
+            deltas.push((delta_high, low_index + 1));
+        }
+
+        let updated_c_1 = c_1 + self.committer.commit_sparse(c1_deltas);
+        let updated_c_2 = c_2 + self.committer.commit_sparse(c2_deltas);
+        let new_hash_c1 = group_to_field(&updated_c_1);
+        let new_hash_c2 = group_to_field(&updated_c_2);
+
+        let updated_stem_comm = stem_commitment
+            + self
+                .committer
+                .commit_sparse(vec![(new_hash_c1 - hash_c1, 2), (new_hash_c2 - hash_c2, 3)]);
+
+        StemMeta {
+            C_1: updated_c_1,
+            hash_c1: new_hash_c1,
+            C_2: updated_c_2,
+            hash_c2: new_hash_c2,
+            stem_commitment: updated_stem_comm,
+            hash_stem_commitment: group_to_field(&updated_stem_comm),
+        }
+    }
+
+    // Returns the hash of the commitment for the child, this is the value that the parent commits to
+    fn child_hash(&self, branch_id: &[u8], index: u8) -> Fr {
+        match self.storage.get_branch_child(branch_id, index) {
+            Some(BranchChild::Stem(stem_id)) => {
+                self.storage
+                    .get_stem_meta(stem_id)
+                    .unwrap()
+                    .hash_stem_commitment
+            }
+            Some(BranchChild::Branch(branch_meta)) => branch_meta.hash_commitment,
+            None => Fr::zero(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::memory_db::MemoryDb;
+    use crate::trie::Trie;
+    use crate::{Key, TestConfig, TrieTrait, Value};
+
+    fn random_keys(seed: u8, num_keys: usize) -> Vec<Key> {
+        use sha2::Digest;
+        (0..num_keys)
+            .map(|i| {
+                let mut hasher = sha2::Sha256::new();
+                hasher.update(&[seed]);
+                hasher.update(&(i as u64).to_le_bytes());
+                hasher.finalize().into()
+            })
+            .collect()
+    }
+
+    // Checks that inserting the batches one at a time, gives the same trie as inserting
+    // each key sequentially
+    fn assert_same_as_sequential(batches: Vec<Vec<(Key, Value)>>) {
+        let mut sequential = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        let mut batched = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+
+        for batch in batches {
+            sequential.insert(batch.clone().into_iter());
+            batched.insert_batch(batch.into_iter());
+
+            assert_eq!(sequential.root_commitment(), batched.root_commitment());
+        }
+
+        assert_eq!(sequential.storage.leaf_table, batched.storage.leaf_table);
+        assert_eq!(sequential.storage.stem_table, batched.storage.stem_table);
+
+        let mut sequential_paths: Vec<_> = sequential.storage.branch_table.keys().collect();
+        let mut batched_paths: Vec<_> = batched.storage.branch_table.keys().collect();
+        sequential_paths.sort();
+        batched_paths.sort();
+        assert_eq!(sequential_paths, batched_paths);
+    }
+
+    #[test]
+    fn batch_random_keys() {
+        let keys = random_keys(0, 200);
+        let kv: Vec<_> = keys.iter().map(|key| (*key, *key)).collect();
+        assert_same_as_sequential(vec![kv]);
+    }
+
+    #[test]
+    fn batch_shared_stems_and_long_paths() {
+        let mut kv = Vec::new();
+        for i in 0u8..10 {
+            // Many leaves on the same stem, on both C_1 and C_2
+            let mut key = [0u8; 32];
+            key[31] = i * 25;
+            kv.push((key, [i; 32]));
+
+            // Stems which share long prefixes, so that chains of branch nodes are created
+            let mut key = [0u8; 32];
+            key[30] = i + 1;
+            kv.push((key, [i; 32]));
+
+            let mut key = [0u8; 32];
+            key[5] = 1;
+            key[20] = i;
+            kv.push((key, [i; 32]));
+        }
+        assert_same_as_sequential(vec![kv]);
+    }
+
+    #[test]
+    fn batch_on_existing_trie() {
+        let keys = random_keys(1, 100);
+        let first: Vec<_> = keys.iter().map(|key| (*key, *key)).collect();
+
+        // Updates, no-op updates, duplicate keys and keys which extend existing stems
+        let mut second = Vec::new();
+        for (i, key) in keys.iter().enumerate().take(50) {
+            let value = if i % 2 == 0 { [i as u8; 32] } else { *key };
+            second.push((*key, value));
+
+            let mut sibling = *key;
+            sibling[31] = sibling[31].wrapping_add(1);
+            second.push((sibling, [1u8; 32]));
+
+            let mut neighbour = *key;
+            neighbour[2] = neighbour[2].wrapping_add(1);
+            second.push((neighbour, [2u8; 32]));
+        }
+        second.push((keys[0], [9u8; 32]));
+        second.extend(random_keys(2, 50).into_iter().map(|key| (key, key)));
+
+        assert_same_as_sequential(vec![first, second]);
+    }
+
+    #[test]
+    fn batch_with_no_changes() {
+        let keys = random_keys(3, 10);
+        let kv: Vec<_> = keys.iter().map(|key| (*key, *key)).collect();
+        assert_same_as_sequential(vec![kv.clone(), kv, vec![]]);
+    }
+}
@@ -0,0 +1,154 @@
+use super::Trie;
+use crate::committer::Committer;
+use crate::database::{JournaledDb, ReadWriteHigherDb};
+
+// Checkpoints allow the trie to be rolled back to a previous root, for example when a block is reorged.
+//
+// While a checkpoint is open, the journaled database records the previous value of every node
+// that is written to. Reverting writes those values back, so the previous root commitment is restored
+// exactly, without recomputing it. Checkpoints can be nested; reverting a checkpoint also reverts
+// every checkpoint that was opened after it.
+
+// A handle to the state of the trie at the time the checkpoint was opened.
+// Every checkpoint has a unique id, so a handle to a checkpoint which has been closed never refers to another one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint(u64);
+
+impl<Storage: ReadWriteHigherDb, PolyCommit: Committer> Trie<JournaledDb<Storage>, PolyCommit> {
+    // Opens a new checkpoint, the returned handle can be used to revert all changes made after this call
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        Checkpoint(self.storage.open_checkpoint())
+    }
+
+    // Reverts all changes made since `checkpoint` was opened, closing it and every checkpoint opened after it.
+    // If the checkpoint has already been reverted or committed, this is a no-op and false is returned
+    pub fn revert_to(&mut self, checkpoint: Checkpoint) -> bool {
+        self.storage.revert_checkpoint(checkpoint.0)
+    }
+
+    // Keeps the changes made since `checkpoint` was opened, closing it and every checkpoint opened after it.
+    // If there is an outer checkpoint, reverting it will still revert these changes.
+    // If the checkpoint has already been reverted or committed, this is a no-op and false is returned
+    pub fn commit_checkpoint(&mut self, checkpoint: Checkpoint) -> bool {
+        self.storage.commit_checkpoint(checkpoint.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{memory_db::MemoryDb, JournaledDb};
+    use crate::trie::Trie;
+    use crate::{Key, TestConfig, TrieTrait};
+
+    fn key(first: u8, last: u8) -> Key {
+        let mut key = [0u8; 32];
+        key[0] = first;
+        key[1] = last;
+        key[31] = last;
+        key
+    }
+
+    fn assert_same_tables(a: &MemoryDb, b: &MemoryDb) {
+        assert_eq!(a.leaf_table, b.leaf_table);
+        assert_eq!(a.stem_table, b.stem_table);
+
+        let mut a_paths: Vec<_> = a.branch_table.keys().collect();
+        let mut b_paths: Vec<_> = b.branch_table.keys().collect();
+        a_paths.sort();
+        b_paths.sort();
+        assert_eq!(a_paths, b_paths);
+    }
+
+    #[test]
+    fn revert_restores_previous_root() {
+        let db = JournaledDb::new(MemoryDb::new());
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+
+        trie.insert((0..10).map(|i| (key(i, i), [i; 32])));
+        let root = trie.root_commitment();
+        let before = trie.storage.storage.clone();
+
+        let checkpoint = trie.checkpoint();
+        // Update existing values, add new stems which share a path with existing ones and delete
+        trie.insert((0..10).map(|i| (key(i, i), [i + 1; 32])));
+        trie.insert_batch((0..10).map(|i| (key(i, i + 100), [i; 32])));
+        trie.delete(key(3, 3));
+        assert_ne!(trie.root_commitment(), root);
+
+        assert!(trie.revert_to(checkpoint));
+        assert_eq!(trie.root_commitment(), root);
+        assert_eq!(trie.get(key(5, 5)), Some([5; 32]));
+        assert_eq!(trie.get(key(5, 105)), None);
+        assert_same_tables(&trie.storage.storage, &before);
+    }
+
+    #[test]
+    fn nested_checkpoints() {
+        let db = JournaledDb::new(MemoryDb::new());
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+
+        trie.insert_single(key(1, 1), [1; 32]);
+        let root_0 = trie.root_commitment();
+
+        let outer = trie.checkpoint();
+        trie.insert_single(key(1, 2), [2; 32]);
+        let root_1 = trie.root_commitment();
+
+        let inner = trie.checkpoint();
+        trie.insert_single(key(2, 2), [3; 32]);
+        assert!(trie.revert_to(inner));
+        assert_eq!(trie.root_commitment(), root_1);
+
+        // Committing the inner checkpoint merges its changes into the outer checkpoint
+        let inner = trie.checkpoint();
+        trie.insert_single(key(3, 3), [4; 32]);
+        assert!(trie.commit_checkpoint(inner));
+        assert_eq!(trie.storage.num_checkpoints(), 1);
+
+        assert!(trie.revert_to(outer));
+        assert_eq!(trie.root_commitment(), root_0);
+        assert_eq!(trie.get(key(3, 3)), None);
+        assert_eq!(trie.storage.num_checkpoints(), 0);
+    }
+
+    #[test]
+    fn committed_changes_are_kept() {
+        let db = JournaledDb::new(MemoryDb::new());
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+
+        let checkpoint = trie.checkpoint();
+        trie.insert_single(key(1, 1), [1; 32]);
+        let root = trie.root_commitment();
+        assert!(trie.commit_checkpoint(checkpoint));
+
+        // The checkpoint is closed, so there is nothing to revert
+        assert!(!trie.revert_to(checkpoint));
+        assert_eq!(trie.root_commitment(), root);
+        assert_eq!(trie.get(key(1, 1)), Some([1; 32]));
+    }
+
+    #[test]
+    fn stale_checkpoints_are_ignored() {
+        let db = JournaledDb::new(MemoryDb::new());
+        let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+
+        let reverted = trie.checkpoint();
+        trie.insert_single(key(1, 1), [1; 32]);
+        assert!(trie.revert_to(reverted));
+        let committed = trie.checkpoint();
+        assert!(trie.commit_checkpoint(committed));
+
+        // This checkpoint is in the same position as the closed ones were
+        let checkpoint = trie.checkpoint();
+        trie.insert_single(key(2, 2), [2; 32]);
+        let root = trie.root_commitment();
+
+        assert!(!trie.revert_to(reverted));
+        assert!(!trie.commit_checkpoint(committed));
+        assert_eq!(trie.root_commitment(), root);
+        assert_eq!(trie.storage.num_checkpoints(), 1);
+
+        assert!(trie.revert_to(checkpoint));
+        assert_eq!(trie.get(key(2, 2)), None);
+    }
+}
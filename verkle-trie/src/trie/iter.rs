@@ -0,0 +1,440 @@
+use super::Trie;
+use crate::committer::Committer;
+use crate::database::{BranchChild, ReadOnlyHigherDb, StemMeta};
+use crate::{Key, Stem, Value};
+use std::ops::{Bound, RangeBounds};
+
+// Iteration walks the trie depth first, visiting the children of each branch node
+// in index order. Since the path to a node is a prefix of every key below it,
+// this yields the keys in lexicographic order.
+//
+// Subtrees whose keys cannot fall inside of the requested range are skipped,
+// so a range scan only touches the nodes on the boundary of the range and the
+// nodes inside of it.
+
+impl<Storage: ReadOnlyHigherDb, PolyCommit: Committer> Trie<Storage, PolyCommit> {
+    // Returns all key-value pairs in the trie, in key order
+    pub fn iter(&self) -> TrieIter<'_, Storage> {
+        self.iter_range(..)
+    }
+
+    // Returns all key-value pairs whose key falls inside of the range, in key order
+    pub fn iter_range(&self, range: impl RangeBounds<Key>) -> TrieIter<'_, Storage> {
+        TrieIter {
+            stems: StemWalker::new(&self.storage, KeyRange::from_bounds(range)),
+            leaves: Vec::new(),
+        }
+    }
+
+    // Returns all key-value pairs whose key starts with `prefix`, in key order
+    pub fn iter_prefix(&self, prefix: &[u8]) -> TrieIter<'_, Storage> {
+        TrieIter {
+            stems: StemWalker::new(&self.storage, KeyRange::from_prefix(prefix)),
+            leaves: Vec::new(),
+        }
+    }
+
+    // Returns all stems in the trie along with their metadata, in stem order
+    pub fn iter_stems(&self) -> StemIter<'_, Storage> {
+        self.iter_stems_range(..)
+    }
+
+    // Returns all stems which have at least one key inside of the range, along with their metadata
+    pub fn iter_stems_range(&self, range: impl RangeBounds<Key>) -> StemIter<'_, Storage> {
+        StemIter {
+            stems: StemWalker::new(&self.storage, KeyRange::from_bounds(range)),
+        }
+    }
+}
+
+// An iterator over the key-value pairs in the trie
+pub struct TrieIter<'a, Storage> {
+    stems: StemWalker<'a, Storage>,
+    // The leaves of the current stem which are in range, stored in reverse order
+    leaves: Vec<(Key, Value)>,
+}
+
+impl<'a, Storage: ReadOnlyHigherDb> Iterator for TrieIter<'a, Storage> {
+    type Item = (Key, Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(leaf) = self.leaves.pop() {
+                return Some(leaf);
+            }
+
+            let stem = self.stems.next()?;
+
+            let mut children = self.stems.storage.get_stem_children(stem);
+            children.sort_unstable_by(|(index_a, _), (index_b, _)| index_b.cmp(index_a));
+
+            let range = &self.stems.range;
+            self.leaves = children
+                .into_iter()
+                .map(|(suffix, value)| {
+                    let mut key = [0u8; 32];
+                    key[0..31].copy_from_slice(&stem);
+                    key[31] = suffix;
+                    (key, value)
+                })
+                .filter(|(key, _)| range.contains(key))
+                .collect();
+        }
+    }
+}
+
+// An iterator over the stems in the trie and their metadata
+pub struct StemIter<'a, Storage> {
+    stems: StemWalker<'a, Storage>,
+}
+
+impl<'a, Storage: ReadOnlyHigherDb> Iterator for StemIter<'a, Storage> {
+    type Item = (Stem, StemMeta);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let stem = self.stems.next()?;
+        let stem_meta = self
+            .stems
+            .storage
+            .get_stem_meta(stem)
+            .expect("every stem referenced by a branch should have metadata");
+        Some((stem, stem_meta))
+    }
+}
+
+enum PendingNode {
+    Branch(Vec<u8>),
+    Stem(Stem),
+}
+
+// Walks the trie depth first, yielding the stems which may contain keys in the range
+struct StemWalker<'a, Storage> {
+    storage: &'a Storage,
+    range: KeyRange,
+    // The nodes which still need to be visited, the next node to visit is at the top
+    stack: Vec<PendingNode>,
+}
+
+impl<'a, Storage> StemWalker<'a, Storage> {
+    fn new(storage: &'a Storage, range: KeyRange) -> Self {
+        StemWalker {
+            storage,
+            range,
+            stack: vec![PendingNode::Branch(vec![])],
+        }
+    }
+}
+
+impl<'a, Storage: ReadOnlyHigherDb> Iterator for StemWalker<'a, Storage> {
+    type Item = Stem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let branch_id = match self.stack.pop()? {
+                PendingNode::Stem(stem) => return Some(stem),
+                PendingNode::Branch(branch_id) => branch_id,
+            };
+
+            let mut children = self.storage.get_branch_children(&branch_id);
+            // Children are pushed in reverse, so that the smallest index is visited first
+            children.sort_unstable_by(|(index_a, _), (index_b, _)| index_b.cmp(index_a));
+
+            for (index, child) in children {
+                match child {
+                    BranchChild::Stem(stem) => {
+                        // The stem may sit higher in the trie than its length,
+                        // so we use the full stem to check the range
+                        if self.range.overlaps_prefix(&stem) {
+                            self.stack.push(PendingNode::Stem(stem))
+                        }
+                    }
+                    BranchChild::Branch(_) => {
+                        let mut child_id = branch_id.clone();
+                        child_id.push(index);
+                        if self.range.overlaps_prefix(&child_id) {
+                            self.stack.push(PendingNode::Branch(child_id))
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct KeyRange {
+    start: Bound<Key>,
+    end: Bound<Key>,
+}
+
+impl KeyRange {
+    fn from_bounds(range: impl RangeBounds<Key>) -> Self {
+        KeyRange {
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+        }
+    }
+
+    // All keys which start with the prefix are in the range [prefix||00.., (prefix+1)||00..)
+    // If the prefix is all 0xff, then the range is unbounded
+    fn from_prefix(prefix: &[u8]) -> Self {
+        // No key starts with a prefix which is longer than a key, so the range is empty
+        if prefix.len() > 32 {
+            return KeyRange {
+                start: Bound::Excluded([u8::MAX; 32]),
+                end: Bound::Unbounded,
+            };
+        }
+
+        let mut start = [0u8; 32];
+        start[0..prefix.len()].copy_from_slice(prefix);
+
+        let mut end = start;
+        let mut end_bound = Bound::Unbounded;
+        for i in (0..prefix.len()).rev() {
+            if end[i] == u8::MAX {
+                end[i] = 0;
+                continue;
+            }
+            end[i] += 1;
+            end_bound = Bound::Excluded(end);
+            break;
+        }
+
+        KeyRange {
+            start: Bound::Included(start),
+            end: end_bound,
+        }
+    }
+
+    fn contains(&self, key: &Key) -> bool {
+        (self.start, self.end).contains(key)
+    }
+
+    // Returns true if any key starting with the prefix is inside of the range
+    fn overlaps_prefix(&self, prefix: &[u8]) -> bool {
+        let mut smallest = [0u8; 32];
+        smallest[0..prefix.len()].copy_from_slice(prefix);
+        let mut largest = [u8::MAX; 32];
+        largest[0..prefix.len()].copy_from_slice(prefix);
+
+        let above_start = match &self.start {
+            Bound::Included(start) => &largest >= start,
+            Bound::Excluded(start) => &largest > start,
+            Bound::Unbounded => true,
+        };
+        let below_end = match &self.end {
+            Bound::Included(end) => &smallest <= end,
+            Bound::Excluded(end) => &smallest < end,
+            Bound::Unbounded => true,
+        };
+
+        above_start && below_end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::committer::test::TestCommitter;
+    use crate::database::memory_db::MemoryDb;
+    use crate::database::{test_db::KvStore, CachePolicy, ReadOnlyHigherDb, VerkleDb};
+    use crate::trie::Trie;
+    use crate::{Key, TestConfig, TrieTrait};
+    use std::collections::BTreeMap;
+
+    fn test_keys() -> BTreeMap<Key, Key> {
+        let mut keys = BTreeMap::new();
+        for i in 0u8..40 {
+            // Keys which share a stem, keys which share long prefixes
+            // and keys which diverge at the root
+            let mut key = [0u8; 32];
+            key[0] = i % 5;
+            key[1] = i % 3;
+            key[30] = i % 2;
+            key[31] = i.wrapping_mul(37);
+            keys.insert(key, [i; 32]);
+        }
+        keys
+    }
+
+    fn test_trie(keys: &BTreeMap<Key, Key>) -> Trie<MemoryDb, TestCommitter> {
+        let mut trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        trie.insert(keys.iter().map(|(key, value)| (*key, *value)));
+        trie
+    }
+
+    #[test]
+    fn iter_in_key_order() {
+        let keys = test_keys();
+        let trie = test_trie(&keys);
+
+        let got: Vec<_> = trie.iter().collect();
+        let expected: Vec<_> = keys.into_iter().collect();
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn iter_empty_trie() {
+        let trie = Trie::new(TestConfig::new(MemoryDb::new())).unwrap();
+        assert_eq!(trie.iter().count(), 0);
+        assert_eq!(trie.iter_stems().count(), 0);
+    }
+
+    #[test]
+    fn iter_range_bounds() {
+        let keys = test_keys();
+        let trie = test_trie(&keys);
+
+        let all: Vec<_> = keys.keys().copied().collect();
+        let start = all[7];
+        let end = all[29];
+
+        let got: Vec<_> = trie.iter_range(start..end).map(|(key, _)| key).collect();
+        assert_eq!(got, all[7..29].to_vec());
+
+        let got: Vec<_> = trie.iter_range(start..=end).map(|(key, _)| key).collect();
+        assert_eq!(got, all[7..=29].to_vec());
+
+        let got: Vec<_> = trie.iter_range(..end).map(|(key, _)| key).collect();
+        assert_eq!(got, all[..29].to_vec());
+
+        // Bounds which are not keys in the trie
+        let mut start = [0u8; 32];
+        start[0] = 2;
+        let mut end = [0u8; 32];
+        end[0] = 3;
+        end[1] = 1;
+        let expected: Vec<_> = keys.range(start..end).map(|(key, _)| *key).collect();
+        let got: Vec<_> = trie.iter_range(start..end).map(|(key, _)| key).collect();
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn iter_prefix() {
+        let keys = test_keys();
+        let trie = test_trie(&keys);
+
+        let got: Vec<_> = trie.iter_prefix(&[1, 2]).map(|(key, _)| key).collect();
+        let expected: Vec<_> = keys
+            .keys()
+            .filter(|key| key.starts_with(&[1, 2]))
+            .copied()
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(got, expected);
+
+        assert_eq!(trie.iter_prefix(&[]).count(), keys.len());
+        assert_eq!(trie.iter_prefix(&[0xff]).count(), 0);
+
+        // A prefix which is longer than a key matches nothing
+        let (key, _) = keys.iter().next().unwrap();
+        let mut long_prefix = key.to_vec();
+        long_prefix.push(0);
+        assert_eq!(trie.iter_prefix(key).count(), 1);
+        assert_eq!(trie.iter_prefix(&long_prefix).count(), 0);
+    }
+
+    #[test]
+    fn iter_stems_with_meta() {
+        let keys = test_keys();
+        let trie = test_trie(&keys);
+
+        let mut expected: Vec<[u8; 31]> = keys
+            .keys()
+            .map(|key| key[0..31].try_into().unwrap())
+            .collect();
+        expected.dedup();
+
+        let got: Vec<_> = trie.iter_stems().collect();
+        assert_eq!(got.len(), expected.len());
+        for ((stem, meta), expected_stem) in got.into_iter().zip(expected) {
+            assert_eq!(stem, expected_stem);
+            assert_eq!(Some(meta), trie.storage.get_stem_meta(stem));
+        }
+    }
+
+    // Checks every kind of iterator against the key-value pairs which should be in the trie
+    fn check_iterators(
+        trie: &Trie<VerkleDb<KvStore>, TestCommitter>,
+        expected: &BTreeMap<Key, Key>,
+    ) {
+        let got: Vec<_> = trie.iter().collect();
+        let all: Vec<_> = expected.iter().map(|(key, value)| (*key, *value)).collect();
+        assert_eq!(got, all);
+
+        let (start, end) = (all[3].0, all[all.len() - 4].0);
+        let got: Vec<_> = trie.iter_range(start..end).collect();
+        let range: Vec<_> = expected
+            .range(start..end)
+            .map(|(key, value)| (*key, *value))
+            .collect();
+        assert_eq!(got, range);
+
+        for prefix in [&[1u8][..], &[2, 1], &[4, 0]] {
+            let got: Vec<_> = trie.iter_prefix(prefix).collect();
+            let with_prefix: Vec<_> = all
+                .iter()
+                .filter(|(key, _)| key.starts_with(prefix))
+                .copied()
+                .collect();
+            assert_eq!(got, with_prefix);
+        }
+
+        let mut stems: Vec<[u8; 31]> = expected
+            .keys()
+            .map(|key| key[0..31].try_into().unwrap())
+            .collect();
+        stems.dedup();
+        let got: Vec<_> = trie.iter_stems().collect();
+        assert_eq!(got.iter().map(|(stem, _)| *stem).collect::<Vec<_>>(), stems);
+        for (stem, meta) in got {
+            assert_eq!(Some(meta), trie.storage.get_stem_meta(stem));
+        }
+    }
+
+    #[test]
+    fn iter_verkle_db() {
+        let policies = [
+            CachePolicy::fixed_depth(1),
+            CachePolicy::lru(1 << 20),
+            CachePolicy::fixed_depth_and_lru(0, 1 << 20),
+        ];
+        for policy in policies {
+            let db = VerkleDb::<KvStore>::with_cache_policy("", policy).unwrap();
+            let mut trie = Trie::new(TestConfig::new(db)).unwrap();
+            let mut expected = test_keys();
+            let (flushed, unflushed): (Vec<_>, Vec<_>) = expected
+                .clone()
+                .into_iter()
+                .enumerate()
+                .partition(|(i, _)| i % 2 == 0);
+
+            // Half of the keys are in the storage, and the other half are only in the batch
+            trie.insert(flushed.into_iter().map(|(_, pair)| pair));
+            trie.flush_database();
+            trie.insert(unflushed.into_iter().map(|(_, pair)| pair));
+
+            // Delete every key under one stem, along with some keys which were flushed and some which were not,
+            // and overwrite a flushed key
+            let stem = expected.keys().nth(10).unwrap()[0..31].to_vec();
+            let deleted: Vec<Key> = expected
+                .keys()
+                .enumerate()
+                .filter(|(i, key)| key[0..31] == stem[..] || i % 7 == 0 || i % 11 == 1)
+                .map(|(_, key)| *key)
+                .collect();
+            for key in &deleted {
+                assert!(trie.delete(*key).is_some());
+                expected.remove(key);
+            }
+            let overwritten = *expected.keys().nth(4).unwrap();
+            trie.insert_single(overwritten, [0xaa; 32]);
+            expected.insert(overwritten, [0xaa; 32]);
+            check_iterators(&trie, &expected);
+
+            // And once everything has been flushed
+            trie.flush_database();
+            check_iterators(&trie, &expected);
+        }
+    }
+}
@@ -17,7 +17,7 @@ pub static CONFIG: Lazy<VerkleConfig<MemoryDb>> = Lazy::new(|| {
 #[test]
 fn test_vector_insert_100_step() {
     let mut prng = BasicPRNG::default();
-    let mut trie = Trie::new(CONFIG.clone());
+    let mut trie = Trie::new(CONFIG.clone()).unwrap();
     let batch_size = 100;
     // N = 100
     step_test_helper(
@@ -60,7 +60,7 @@ fn test_vector_insert_100_step() {
 #[test]
 fn test_vector_insert_1000_step() {
     let mut prng = BasicPRNG::default();
-    let mut trie = Trie::new(CONFIG.clone());
+    let mut trie = Trie::new(CONFIG.clone()).unwrap();
     let batch_size = 1_000;
 
     // N = 1_000
@@ -123,7 +123,7 @@ fn step_test_helper(
 // A test structure that allows us to have a seedable prng
 // that is easy to implement in both python, go and Rust
 // This is only used for tests
-struct BasicPRNG {
+pub(crate) struct BasicPRNG {
     seed: [u8; 32],
     counter: u64,
 }